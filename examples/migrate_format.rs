@@ -0,0 +1,37 @@
+//! Rewrites the entries of a disk cache directory written by earlier versions
+//! with the current entry header.
+//!
+//! Applications may keep using the directory meanwhile. Run:
+//!
+//! ```text
+//! cargo run --example migrate_format -- /var/cache/SomeOmneCacheApp/evidence
+//! ```
+//!
+//! Entries are rewritten uncompressed and unencrypted; directories using
+//! compression or encryption are better migrated through a cache configured
+//! like the application's, with [`FsCache::migrate_format`].
+
+use omnecache::fs::{FsCache, ReadWrite};
+
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+
+    let (Some(dir), None) = (args.next(), args.next()) else {
+        eprintln!("usage: migrate_format <cache directory>");
+        std::process::exit(2);
+    };
+
+    let migrated = match FsCache::<ReadWrite>::new_write(&dir, usize::MAX).await {
+        Ok(cache) => cache.migrate_format().await,
+        Err(e) => Err(e.into()),
+    };
+
+    match migrated {
+        Ok(rewritten) => println!("rewrote {} entries of {}", rewritten, dir),
+        Err(e) => {
+            eprintln!("migration of {} failed: {:?}", dir, e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,40 @@
+//! Moves the entries of a disk cache directory into another layout.
+//!
+//! Stop every application using the directory first, then run:
+//!
+//! ```text
+//! cargo run --example migrate_layout -- /var/cache/SomeOmneCacheApp/evidence sharded
+//! ```
+//!
+//! and set `layout = "sharded"` in the `[disk]` section of its configuration.
+
+use omnecache::layout::{self, Layout};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+
+    let (Some(dir), Some(layout), None) = (args.next(), args.next(), args.next()) else {
+        eprintln!("usage: migrate_layout <cache directory> <flat|sharded>");
+        std::process::exit(2);
+    };
+
+    let layout = match layout.as_str() {
+        "flat" => Layout::Flat,
+        "sharded" => Layout::Sharded,
+        other => {
+            eprintln!("unknown layout: {}", other);
+            std::process::exit(2);
+        }
+    };
+
+    match layout::migrate(&dir, layout) {
+        Ok(moved) => println!(
+            "moved {} entries of {} into the {:?} layout",
+            moved, dir, layout
+        ),
+        Err(e) => {
+            eprintln!("migration of {} failed: {}", dir, e);
+            std::process::exit(1);
+        }
+    }
+}
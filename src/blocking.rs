@@ -0,0 +1,308 @@
+//! # OmneCache Blocking API
+//!
+//! A synchronous cache for consumers that do not run an async runtime, such as
+//! small command-line tools.
+//!
+//! [`BlockingOmneCache`] performs all filesystem work on the calling thread with
+//! `std::fs` and `flock`, instead of handing it to `spawn_blocking`. It uses the
+//! same on-disk format (entries, `.meta` files) and the same per-key locking
+//! protocol as [`OmneCache`](crate::OmneCache), so a blocking and an async cache
+//! may share one disk directory, even from different processes. A pack store
+//! belongs to a single cache, so a [`Backend::Pack`](crate::pack::Backend::Pack)
+//! disk layer is rejected.
+//!
+//! # Example
+//! ```rust,no_run
+//! use omnecache::{blocking::BlockingOmneCache, configuration::OmneCacheCfg};
+//!
+//! fn open() -> Result<BlockingOmneCache, omnecache::error::ConfigurationError> {
+//!     BlockingOmneCache::try_from(OmneCacheCfg::default())
+//! }
+//! ```
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use lru::LruCache;
+
+use crate::{
+    Cacheable, MemoryEntry, MemoryLayer, cache_key,
+    configuration::OmneCacheCfg,
+    error::{CacheableError, ConfigurationError},
+    fs::{Condition, FsCache, Read, ReadWrite, default_deadline},
+    lock_memory,
+    meta::EntryInfo,
+};
+
+/// Synchronous multi-layer cache with the same layers as [`OmneCache`](crate::OmneCache).
+///
+/// Lookups consult memory, then sideload, then disk, and values found in a
+/// lower layer are promoted into memory. Each filesystem operation waits up to
+/// [`DEFAULT_LOCK_TIMEOUT`](crate::fs::DEFAULT_LOCK_TIMEOUT) for its per-key lock.
+///
+/// [`Cacheable::key`] is asynchronous; it is driven to completion on the
+/// calling thread, so keys of types used with this cache must not depend on an
+/// async runtime.
+pub struct BlockingOmneCache {
+    /// In-memory LRU cache for fast access to recently used items
+    memory: Option<MemoryLayer>,
+
+    /// Path to the sideloaded content directory
+    sideload: Option<FsCache<Read>>,
+
+    /// Path to the disk cache directory
+    disk: Option<FsCache<ReadWrite>>,
+
+    /// How long stored values stay fresh, recorded for `OmneCache::get_or_fetch`
+    ttl: Option<Duration>,
+}
+
+impl BlockingOmneCache {
+    /// Creates a new blocking cache from a configuration.
+    ///
+    /// Layers are initialized exactly as by [`OmneCache::try_from`](crate::OmneCache::try_from).
+    ///
+    /// # Parameters
+    /// * `cfg`: The configuration defining which cache layers to enable and their settings
+    ///
+    /// # Returns
+    /// * `Ok(BlockingOmneCache)`: A fully initialized cache with the requested layers
+    /// * `Err(ConfigurationError)`: If any cache layer failed to initialize
+    pub fn try_from(cfg: OmneCacheCfg) -> Result<Self, ConfigurationError> {
+        let memory = match cfg.memory {
+            Some(memory) if !memory.disabled => {
+                Some(Arc::new(Mutex::new(LruCache::new(memory.capacity()?))))
+            }
+            _ => None,
+        };
+
+        let sideload = match cfg.sideload {
+            Some(s) => Some(s.as_blocking_fs_cache()?),
+            _ => None,
+        };
+
+        let disk = match cfg.disk {
+            Some(d) => Some(d.as_blocking_fs_cache()?),
+            _ => None,
+        };
+
+        Ok(Self {
+            memory,
+            sideload,
+            disk,
+            ttl: cfg.ttl.map(Duration::from_secs),
+        })
+    }
+
+    /// Attempts to retrieve the requested data from the cache.
+    ///
+    /// # Parameters
+    /// * `entry`: The Cacheable object that identifies the needed data
+    ///
+    /// # Returns
+    /// * `Ok(C::Value)`: The successfully retrieved and deserialized value
+    /// * `Err(C::Error)`: If retrieval or deserialization failed, including when data is not found in any cache
+    pub fn get<C: Cacheable>(&self, entry: C) -> Result<C::Value, C::Error> {
+        let key = build_key(&entry);
+
+        match self.lookup(&key) {
+            Some(data) => C::Value::try_from(data),
+            None => Err(C::Error::from(CacheableError::NotFound)),
+        }
+    }
+
+    /// Stores data in the memory layer and, if enabled, the disk layer.
+    ///
+    /// # Parameters
+    /// * `entry`: The Cacheable object that provides the key for the data
+    /// * `value`: The byte data to store in the cache
+    ///
+    /// # Returns
+    /// * `Ok(())`: If the data was successfully stored in at least one cache layer
+    /// * `Err(C::Error)`: A `WriteError` if neither memory nor disk is enabled, or
+    ///   the error of a failed disk write
+    pub fn put<C: Cacheable>(&self, entry: C, value: &[u8]) -> Result<(), C::Error> {
+        if self.memory.is_none() && self.disk.is_none() {
+            return Err(C::Error::from(CacheableError::WriteError));
+        }
+
+        let key = build_key(&entry);
+        let info = EntryInfo::new(None, SystemTime::now(), self.ttl);
+
+        if let Some(memory) = &self.memory {
+            lock_memory(memory).put(
+                key.clone(),
+                MemoryEntry {
+                    data: value.to_vec(),
+                    info: info.clone(),
+                },
+            );
+        }
+
+        if let Some(disk) = &self.disk {
+            disk.write_blocking(
+                &key,
+                value,
+                Condition::Always,
+                Some(&info),
+                default_deadline(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the requested data from the memory and disk layers.
+    ///
+    /// The disk entry is removed under its per-key exclusive lock. Sideloaded
+    /// content is read-only and is never removed.
+    ///
+    /// # Parameters
+    /// * `entry`: The Cacheable object that identifies the data
+    ///
+    /// # Returns
+    /// * `Ok(true)`: If the memory or disk layer held the data
+    /// * `Ok(false)`: If neither layer held it
+    /// * `Err(C::Error)`: If the disk entry could not be removed
+    pub fn remove<C: Cacheable>(&self, entry: C) -> Result<bool, C::Error> {
+        let key = build_key(&entry);
+
+        let in_memory = self
+            .memory
+            .as_ref()
+            .is_some_and(|memory| lock_memory(memory).pop(&key).is_some());
+
+        let on_disk = match &self.disk {
+            Some(disk) => disk.remove_blocking(&key, default_deadline())?,
+            None => false,
+        };
+
+        Ok(in_memory || on_disk)
+    }
+
+    /// Looks a complete key up in every enabled layer, in order.
+    fn lookup(&self, key: &str) -> Option<Vec<u8>> {
+        if let Some(memory) = &self.memory
+            && let Some(entry) = lock_memory(memory).get(key)
+        {
+            return Some(entry.data.clone());
+        }
+
+        let deadline = default_deadline();
+
+        let (data, info) = self
+            .sideload
+            .as_ref()
+            .and_then(|sideload| sideload.get_blocking(key, deadline))
+            .or_else(|| self.disk.as_ref()?.get_blocking(key, deadline))?;
+
+        if let Some(memory) = &self.memory {
+            lock_memory(memory).put(
+                key.to_string(),
+                MemoryEntry {
+                    data: data.clone(),
+                    info,
+                },
+            );
+        }
+
+        Some(data)
+    }
+}
+
+/// Builds the complete cache key of `entry` on the calling thread.
+fn build_key<C: Cacheable>(entry: &C) -> String {
+    futures::executor::block_on(cache_key(entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        OmneCache,
+        configuration::{DiskCfg, MemoryCfg, SideloadCfg},
+        fs::tests::{read_only_fixture, stored_value},
+        tests::Bytes,
+    };
+
+    use super::*;
+
+    fn cfg(disk: &std::path::Path, sideload: Option<&std::path::Path>) -> OmneCacheCfg {
+        OmneCacheCfg {
+            memory: Some(MemoryCfg {
+                disabled: false,
+                items: Some(10),
+            }),
+            disk: Some(DiskCfg {
+                disabled: false,
+                path: Some(disk.to_string_lossy().to_string()),
+                items: Some(100),
+                max_bytes: None,
+                eviction: Default::default(),
+                layout: Default::default(),
+                repair: false,
+                compression: None,
+                encryption: None,
+                backend: Default::default(),
+            }),
+            sideload: sideload.map(|path| SideloadCfg {
+                disabled: false,
+                path: Some(path.to_string_lossy().to_string()),
+                items: None,
+            }),
+            ttl: None,
+        }
+    }
+
+    #[test]
+    fn test_blocking_put_get_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let sideload = read_only_fixture();
+        let cache = BlockingOmneCache::try_from(cfg(dir.path(), Some(sideload.path()))).unwrap();
+
+        cache.put("a".to_string(), b"alpha").unwrap();
+        assert_eq!(
+            cache.get("a".to_string()).unwrap(),
+            Bytes(b"alpha".to_vec())
+        );
+        assert_eq!(stored_value(dir.path().join("CustomString_a")), b"alpha");
+
+        assert!(cache.remove("a".to_string()).unwrap());
+        assert!(!cache.remove("a".to_string()).unwrap());
+        assert!(cache.get("a".to_string()).is_err());
+        assert!(!dir.path().join("CustomString_a").exists());
+    }
+
+    #[test]
+    fn test_blocking_shares_directory_with_async_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocking = BlockingOmneCache::try_from(cfg(dir.path(), None)).unwrap();
+        blocking.put("shared".to_string(), b"from sync").unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let value = runtime.block_on(async {
+            let cache = OmneCache::try_from(cfg(dir.path(), None)).await.unwrap();
+            cache.put("other".to_string(), b"from async").await.unwrap();
+            cache.get("shared".to_string()).await.unwrap()
+        });
+
+        assert_eq!(value, Bytes(b"from sync".to_vec()));
+        assert_eq!(
+            blocking.get("other".to_string()).unwrap(),
+            Bytes(b"from async".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_blocking_rejects_pack_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cfg = cfg(dir.path(), None);
+        cfg.disk.as_mut().unwrap().backend = crate::pack::Backend::Pack;
+
+        let Err(ConfigurationError::Io(e)) = BlockingOmneCache::try_from(cfg) else {
+            panic!("a pack store was accepted");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,232 @@
+//! # OmneCache Entry Checksums
+//!
+//! Detection of truncated or corrupted entries in the disk and sideload layers.
+//!
+//! Every entry written by [`FsCache::put`] carries the BLAKE3 hash of its
+//! content in its [header](crate::format), so the entry and its checksum are
+//! replaced together. Entries stored without a header, such as those of
+//! sideload bundles, may instead have a `.sum` file holding the hash of their
+//! content as hex, in the format printed by `b3sum`, so sideload bundles can
+//! be checksummed with `b3sum <key> > <key>.sum`. The hash is checked on
+//! every read:
+//!
+//! * An entry whose content matches its hash is returned.
+//! * An entry without a header and without a `.sum` file is returned unchecked.
+//! * An entry whose content does not match, including an entry with a
+//!   header but no valid checksum, is treated as a miss and reported
+//!   as a [`CorruptEntry`]. Disk entries are moved to the `.quarantine`
+//!   directory inside the cache directory; sideload entries stay in place,
+//!   since the sideload directory is read-only.
+//!
+//! Reports are counted by [`FsCache::corrupt_entries`] and passed to the
+//! handler set with [`FsCache::on_corrupt_entry`], or written to standard
+//! error if there is none.
+//!
+//! [`FsCache::put`]: crate::fs::FsCache::put
+//! [`FsCache::corrupt_entries`]: crate::fs::FsCache::corrupt_entries
+//! [`FsCache::on_corrupt_entry`]: crate::fs::FsCache::on_corrupt_entry
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    fs::{META_EXTENSION, sidecar_path},
+    meta::EntryVersion,
+};
+
+/// Extension appended to a key to name its checksum file
+pub(crate) const SUM_EXTENSION: &str = "sum";
+/// Name of the directory corrupt disk entries are moved to
+pub(crate) const QUARANTINE_DIR: &str = ".quarantine";
+
+/// An entry whose content did not match its checksum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptEntry {
+    /// The key of the entry
+    pub key: String,
+    /// The file the entry was read from
+    pub path: PathBuf,
+    /// Where the entry was moved to, or `None` if it was left in place
+    pub quarantined: Option<PathBuf>,
+}
+
+/// Receives the corrupt entries found by a cache
+pub type CorruptEntryHandler = Arc<dyn Fn(&CorruptEntry) + Send + Sync>;
+
+/// Counts corrupt entries and passes them on to the handler.
+#[derive(Default)]
+pub(crate) struct CorruptionReports {
+    /// Number of corrupt entries found
+    count: AtomicU64,
+    /// Receives every corrupt entry, if set
+    handler: RwLock<Option<CorruptEntryHandler>>,
+}
+
+impl CorruptionReports {
+    /// Records a corrupt entry.
+    pub(crate) fn report(&self, entry: CorruptEntry) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+
+        match &*self.handler.read().unwrap_or_else(|e| e.into_inner()) {
+            Some(handler) => handler(&entry),
+            None => eprintln!(
+                "Warning: Corrupt cache entry for key: {} (quarantined: {:?})",
+                entry.key, entry.quarantined
+            ),
+        }
+    }
+
+    /// Returns the number of corrupt entries found.
+    pub(crate) fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Replaces the handler receiving corrupt entries.
+    pub(crate) fn set_handler(&self, handler: Option<CorruptEntryHandler>) {
+        *self.handler.write().unwrap_or_else(|e| e.into_inner()) = handler;
+    }
+}
+
+/// Outcome of checking an entry against its checksum
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Check {
+    /// The content matches the checksum
+    Intact,
+    /// The entry has no readable checksum
+    Unchecked,
+    /// The content does not match the checksum
+    Corrupt,
+}
+
+/// Checks `data`, read from the headerless entry at `file_path`, against the
+/// entry's `.sum` file.
+pub(crate) fn check(file_path: &Path, data: &[u8]) -> Check {
+    let Ok(text) = std::fs::read_to_string(sidecar_path(file_path, SUM_EXTENSION)) else {
+        return Check::Unchecked;
+    };
+
+    match text.split_whitespace().next() {
+        Some(sum) if sum.eq_ignore_ascii_case(&EntryVersion::of(data).to_string()) => Check::Intact,
+        Some(_) => Check::Corrupt,
+        None => Check::Unchecked,
+    }
+}
+
+/// Moves the entry at `file_path` and its checksum into the quarantine
+/// directory of the cache directory `dir`, and removes its metadata.
+///
+/// Must be called while holding the entry's per-key exclusive lock.
+///
+/// # Returns
+/// * `Ok(PathBuf)`: Where the entry was moved to
+/// * `Err(std::io::Error)`: If the entry could not be moved
+pub(crate) fn quarantine(dir: &Path, file_path: &Path, key: &str) -> std::io::Result<PathBuf> {
+    let quarantine_dir = dir.join(QUARANTINE_DIR);
+    std::fs::create_dir_all(&quarantine_dir)?;
+
+    // Keeps several corrupt versions of one key apart.
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let target = quarantine_dir.join(format!("{}.{}", key, stamp));
+
+    std::fs::rename(file_path, &target)?;
+
+    let _ = std::fs::rename(
+        sidecar_path(file_path, SUM_EXTENSION),
+        sidecar_path(&target, SUM_EXTENSION),
+    );
+    let _ = std::fs::remove_file(sidecar_path(file_path, META_EXTENSION));
+
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, sync::Mutex};
+
+    use super::*;
+    use crate::fs::{FsCache, Read, ReadWrite, tests::stored_size};
+
+    #[tokio::test]
+    async fn test_corrupt_entry_is_quarantined() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        cache.on_corrupt_entry({
+            let reported = reported.clone();
+            Arc::new(move |entry: &CorruptEntry| reported.lock().unwrap().push(entry.clone()))
+        });
+
+        cache.put("key1", b"Hello").await.unwrap();
+        cache.put("key2", b"world").await.unwrap();
+        assert_eq!(cache.get("key1").await, Some(b"Hello".to_vec()));
+
+        let mut stored = std::fs::read(dir.path().join("key1")).unwrap();
+        *stored.last_mut().unwrap() = b'p';
+        std::fs::write(dir.path().join("key1"), &stored).unwrap();
+        assert_eq!(cache.get("key1").await, None);
+        assert_eq!(cache.corrupt_entries(), 1);
+
+        let reported = reported.lock().unwrap().clone();
+        let quarantined = reported[0].quarantined.clone().unwrap();
+        assert_eq!(reported[0].key, "key1");
+        assert!(quarantined.starts_with(dir.path().join(QUARANTINE_DIR)));
+        assert_eq!(std::fs::read(quarantined).unwrap(), stored);
+
+        let keys: Vec<_> = cache
+            .entries()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.key)
+            .collect();
+        assert_eq!(keys, ["key2"]);
+        assert_eq!(cache.used_bytes().unwrap(), stored_size("key2", 5));
+
+        // The key can be written again.
+        cache.put("key1", b"Hello").await.unwrap();
+        assert_eq!(cache.get("key1").await, Some(b"Hello".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_sideload_checksums() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("intact"), b"Hello").unwrap();
+        std::fs::write(
+            dir.path().join("intact.sum"),
+            format!("{}  intact\n", blake3::hash(b"Hello").to_hex()),
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("corrupt"), b"Hellp").unwrap();
+        std::fs::write(
+            dir.path().join("corrupt.sum"),
+            format!("{}  corrupt\n", blake3::hash(b"Hello").to_hex()),
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("legacy"), b"world").unwrap();
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o555)).unwrap();
+
+        let cache = FsCache::<Read>::new_read(dir.path()).await.unwrap();
+        cache.on_corrupt_entry(Arc::new(|_| {}));
+        assert_eq!(cache.get("intact").await, Some(b"Hello".to_vec()));
+        assert_eq!(cache.get("legacy").await, Some(b"world".to_vec()));
+        assert_eq!(cache.get("corrupt").await, None);
+        assert_eq!(cache.corrupt_entries(), 1);
+
+        // The read-only directory is left untouched.
+        assert!(dir.path().join("corrupt").exists());
+        assert!(!dir.path().join(QUARANTINE_DIR).exists());
+
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o700)).unwrap();
+    }
+}
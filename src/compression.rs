@@ -0,0 +1,329 @@
+//! # OmneCache Entry Compression
+//!
+//! Optional compression of the entries stored by the disk layer, configured
+//! through [`DiskCfg::compression`](crate::configuration::DiskCfg::compression).
+//!
+//! A compressed entry starts with a short header naming its [`Codec`]; an entry
+//! without the header is stored as is. Readers recognize both, so compressed
+//! and uncompressed entries live side by side, and changing or disabling the
+//! compression of a cache only affects entries written afterwards. Sideload
+//! bundles can be pre-compressed with [`Compression::encode`]. An entry that
+//! claims to decompress to more than [`MAX_DECOMPRESSED_LEN`] bytes, or more
+//! than its codec could produce from it, is treated as corrupt before
+//! anything is allocated for it.
+//!
+//! Item and byte limits, as well as [checksums](crate::checksum), apply to the
+//! entries as stored, so compression makes room for more entries.
+//!
+//! # Example
+//! ```rust
+//! use omnecache::compression::{self, Codec, Compression};
+//!
+//! let compression = Compression {
+//!     codec: Codec::Zstd,
+//!     level: Some(3),
+//!     min_size: 0,
+//! };
+//!
+//! let value = b"{\"evidence\": [1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1]}".repeat(10);
+//! let stored = compression.encode(&value)?;
+//! assert!(stored.len() < value.len());
+//! assert_eq!(compression::decode(&stored)?, value);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::io::Read as _;
+
+use const_default::ConstDefault;
+use serde::{Deserialize, Serialize};
+
+use crate::format::{self, HEADER_LEN, invalid};
+
+/// Largest value, in bytes, that a compressed entry may decompress to; larger
+/// values are stored uncompressed
+pub const MAX_DECOMPRESSED_LEN: usize = 1 << 30;
+
+/// Algorithm used to compress an entry.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    /// Zstandard: a good ratio at a moderate speed
+    #[default]
+    Zstd,
+    /// LZ4: a lower ratio at a very high speed
+    Lz4,
+}
+
+impl ConstDefault for Codec {
+    const DEFAULT: Self = Self::Zstd;
+}
+
+impl Codec {
+    /// Returns the codec identified by `byte` in an entry header.
+    pub(crate) fn from_byte(byte: u8) -> Option<Option<Self>> {
+        match byte {
+            0 => Some(None),
+            1 => Some(Some(Self::Zstd)),
+            2 => Some(Some(Self::Lz4)),
+            _ => None,
+        }
+    }
+
+    /// Returns the byte identifying the codec in an entry header.
+    pub(crate) const fn byte(codec: Option<Self>) -> u8 {
+        match codec {
+            None => 0,
+            Some(Self::Zstd) => 1,
+            Some(Self::Lz4) => 2,
+        }
+    }
+}
+
+/// Compression settings of a disk layer.
+///
+/// # Fields
+/// * `codec`: The algorithm to compress with
+/// * `level`: The compression level of [`Codec::Zstd`], from 1 (fastest) to 22
+///   (smallest); 3 if unset. [`Codec::Lz4`] has no levels.
+/// * `min_size`: Entries smaller than this many bytes are stored uncompressed
+#[derive(ConstDefault, Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Compression {
+    /// Algorithm to compress with
+    #[serde(default)]
+    pub codec: Codec,
+    /// Compression level, the codec's default if unset
+    pub level: Option<i32>,
+    /// Size in bytes below which entries are stored uncompressed
+    #[serde(default)]
+    pub min_size: usize,
+}
+
+impl Compression {
+    /// Encodes a value as it is stored on disk.
+    ///
+    /// Values smaller than [`Compression::min_size`], larger than
+    /// [`MAX_DECOMPRESSED_LEN`], or that do not shrink, are stored uncompressed.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<u8>)`: The stored form of the value
+    /// * `Err(std::io::Error)`: If the value could not be compressed
+    pub fn encode(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        format::encode_legacy(data, Some(self)).map(|stored| stored.into_owned())
+    }
+
+    /// Compresses a value with the codec.
+    ///
+    /// # Returns
+    /// * `Ok(Some(Vec<u8>))`: The compressed value
+    /// * `Ok(None)`: If the value is smaller than [`Compression::min_size`],
+    ///   larger than [`MAX_DECOMPRESSED_LEN`] or does not shrink
+    /// * `Err(std::io::Error)`: If the value could not be compressed
+    pub(crate) fn compress(&self, data: &[u8]) -> std::io::Result<Option<Vec<u8>>> {
+        if data.len() < self.min_size || data.len() > MAX_DECOMPRESSED_LEN {
+            return Ok(None);
+        }
+
+        let compressed = match self.codec {
+            Codec::Zstd => zstd::bulk::compress(data, self.level.unwrap_or(0))?,
+            Codec::Lz4 => lz4_flex::compress_prepend_size(data),
+        };
+
+        Ok((compressed.len() + HEADER_LEN < data.len()).then_some(compressed))
+    }
+}
+
+/// Most bytes a Zstandard payload can decompress to per byte: a block of at
+/// most 128 KiB takes at least 4 bytes
+const ZSTD_MAX_RATIO: usize = 32 * 1024;
+/// Most bytes an LZ4 payload can decompress to per byte: each further byte of
+/// a match length adds at most 255 bytes
+const LZ4_MAX_RATIO: usize = 255;
+
+/// Decompresses a payload compressed with `codec`.
+///
+/// The size a payload claims to decompress to is checked before any memory is
+/// allocated for it: it may not exceed [`MAX_DECOMPRESSED_LEN`], nor what the
+/// codec could produce from a payload of its length. A crafted entry, such as
+/// one in a sideload bundle, therefore cannot make the reader allocate more
+/// than its own size allows.
+///
+/// # Returns
+/// * `Ok(Vec<u8>)`: The value
+/// * `Err(std::io::Error)`: Of kind [`std::io::ErrorKind::InvalidData`] if the
+///   payload is corrupt or claims a size beyond these limits
+pub(crate) fn decompress(codec: Codec, payload: &[u8]) -> std::io::Result<Vec<u8>> {
+    let too_large = || invalid("Compressed cache entry exceeds the decompressed size limit");
+
+    match codec {
+        Codec::Zstd => {
+            let limit = MAX_DECOMPRESSED_LEN.min(payload.len().saturating_mul(ZSTD_MAX_RATIO));
+
+            match zstd::zstd_safe::get_frame_content_size(payload) {
+                Ok(Some(len)) if len > limit as u64 => Err(too_large()),
+                Ok(Some(len)) => zstd::bulk::decompress(payload, len as usize)
+                    .map_err(|e| invalid(e.to_string())),
+                // A frame written without its size is read up to the limit.
+                Ok(None) => {
+                    let mut data = Vec::new();
+                    zstd::stream::read::Decoder::new(payload)?
+                        .take(limit as u64 + 1)
+                        .read_to_end(&mut data)
+                        .map_err(|e| invalid(e.to_string()))?;
+
+                    match data.len() > limit {
+                        true => Err(too_large()),
+                        false => Ok(data),
+                    }
+                }
+                Err(e) => Err(invalid(e.to_string())),
+            }
+        }
+        Codec::Lz4 => {
+            let Some((len, compressed)) = payload.split_first_chunk::<4>() else {
+                return Err(invalid("Truncated compressed cache entry"));
+            };
+            let len = u32::from_le_bytes(*len) as usize;
+            let limit = MAX_DECOMPRESSED_LEN.min(compressed.len().saturating_mul(LZ4_MAX_RATIO));
+
+            if len > limit {
+                return Err(too_large());
+            }
+
+            lz4_flex::decompress(compressed, len).map_err(|e| invalid(e.to_string()))
+        }
+    }
+}
+
+/// Decodes the stored form of an unencrypted entry into its value.
+///
+/// # Returns
+/// * `Ok(Vec<u8>)`: The value
+/// * `Err(std::io::Error)`: Of kind [`std::io::ErrorKind::InvalidData`] if the
+///   header is not recognized or the content cannot be decompressed, or
+///   [`std::io::ErrorKind::PermissionDenied`] if the entry is
+///   [encrypted](crate::encryption)
+pub fn decode(stored: &[u8]) -> std::io::Result<Vec<u8>> {
+    format::decode(stored, None, None).map(|decoded| decoded.data.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::MAGIC;
+
+    #[test]
+    fn test_round_trip() {
+        let value = b"<html><body>evidence</body></html>".repeat(20);
+
+        for codec in [Codec::Zstd, Codec::Lz4] {
+            let compression = Compression {
+                codec,
+                level: None,
+                min_size: 64,
+            };
+
+            let stored = compression.encode(&value).unwrap();
+            assert!(stored.starts_with(MAGIC));
+            assert!(stored.len() < value.len() / 4, "{:?}", codec);
+            assert_eq!(decode(&stored).unwrap(), value);
+
+            // Small values are stored as is.
+            assert_eq!(compression.encode(b"small").unwrap(), b"small");
+
+            // The size limits leave room for the codec's best ratio.
+            let zeros = vec![0; 16 << 20];
+            assert_eq!(decode(&compression.encode(&zeros).unwrap()).unwrap(), zeros);
+        }
+    }
+
+    #[test]
+    fn test_rejects_forged_sizes() {
+        let value = b"evidence".repeat(100);
+
+        for codec in [Codec::Zstd, Codec::Lz4] {
+            let compression = Compression {
+                codec,
+                level: None,
+                min_size: 0,
+            };
+            let mut stored = compression.encode(&value).unwrap();
+
+            match codec {
+                // A frame header claiming 4 TiB: magic number, a descriptor of
+                // a single-segment frame with an 8-byte content size, the size
+                Codec::Zstd => {
+                    stored.truncate(HEADER_LEN);
+                    stored.extend_from_slice(&[0x28, 0xb5, 0x2f, 0xfd, 0xe0]);
+                    stored.extend_from_slice(&(1u64 << 42).to_le_bytes());
+                    stored.extend_from_slice(&[0; 16]);
+                }
+                Codec::Lz4 => {
+                    stored[HEADER_LEN..HEADER_LEN + 4].copy_from_slice(&u32::MAX.to_le_bytes())
+                }
+            }
+
+            let e = decode(&stored).unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidData, "{:?}", codec);
+            assert!(e.to_string().contains("size limit"), "{:?}: {}", codec, e);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compressed_entries() {
+        use std::os::unix::fs::PermissionsExt;
+
+        use crate::{
+            fs::{FsCache, Read, ReadWrite, tests::stored_value},
+            meta::EntryVersion,
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        let value = b"{\"evidence\": \"repeated\"}".repeat(100);
+
+        cache.set_compression(Some(Compression::default()));
+        cache.put("compressed", &value).await.unwrap();
+        cache.set_compression(None);
+        cache.put("plain", &value).await.unwrap();
+
+        let stored = std::fs::read(dir.path().join("compressed")).unwrap();
+        assert!(stored.starts_with(MAGIC));
+        let plain = std::fs::read(dir.path().join("plain")).unwrap();
+        assert!(plain.ends_with(&value));
+        assert_eq!(stored_value(dir.path().join("plain")), value);
+        assert_eq!(
+            cache.used_bytes().unwrap(),
+            (stored.len() + plain.len()) as u64
+        );
+
+        assert_eq!(cache.get("compressed").await, Some(value.clone()));
+        assert_eq!(cache.get("plain").await, Some(value.clone()));
+        assert!(
+            cache
+                .compare_and_swap("compressed", &EntryVersion::of(&value), b"new")
+                .await
+                .unwrap()
+        );
+
+        // A sideload bundle holding a pre-compressed entry
+        let bundle = tempfile::tempdir().unwrap();
+        let compression = Compression {
+            codec: Codec::Lz4,
+            level: None,
+            min_size: 0,
+        };
+        std::fs::write(
+            bundle.path().join("bundled"),
+            compression.encode(&value).unwrap(),
+        )
+        .unwrap();
+        std::fs::set_permissions(bundle.path(), std::fs::Permissions::from_mode(0o555)).unwrap();
+
+        let sideload = FsCache::<Read>::new_read(bundle.path()).await.unwrap();
+        assert_eq!(sideload.get("bundled").await, Some(value));
+
+        std::fs::set_permissions(bundle.path(), std::fs::Permissions::from_mode(0o700)).unwrap();
+    }
+}
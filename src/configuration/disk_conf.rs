@@ -2,37 +2,78 @@ use std::path::PathBuf;
 
 use const_default::ConstDefault;
 
-use crate::fs::{FsCache, ReadWrite};
+use crate::{
+    compression::Compression,
+    encryption::{Encryption, Keyring},
+    eviction::EvictionPolicy,
+    fs::{FsCache, ReadWrite, RepairReport},
+    layout::Layout,
+    pack::Backend,
+};
 
 use super::*;
 
 /// Configuration for the disk-based cache storage component.
 ///
 /// This struct defines the settings for the disk cache, including the
-/// storage path and maximum number of items to manage.
+/// storage path, the maximum number of items to manage, an optional limit on
+/// their total size, which items are evicted to stay within those limits,
+/// how the items are arranged in the directory, whether the directory is
+/// repaired when the cache is opened, how items are compressed and
+/// encrypted, and whether they are stored as files or in a pack store.
 ///
 /// # Examples
 ///
 /// Basic configuration with system temporary directory:
 /// ```rust
-/// use byte_cache::configuration::DiskCfg;
+/// use omnecache::{
+///     compression::{Codec, Compression},
+///     configuration::DiskCfg,
+///     encryption::Encryption,
+///     eviction::EvictionPolicy,
+///     layout::Layout,
+///     pack::Backend,
+/// };
 /// use std::env::temp_dir;
 ///
 /// let disk_cfg = DiskCfg {
 ///     disabled: false,
 ///     path: Some(temp_dir().join("byte_cache").to_string_lossy().to_string()),
 ///     items: Some(1000),
+///     max_bytes: Some(512 * 1024 * 1024),
+///     eviction: EvictionPolicy::Lru,
+///     layout: Layout::Sharded,
+///     repair: true,
+///     compression: Some(Compression {
+///         codec: Codec::Zstd,
+///         level: Some(3),
+///         min_size: 4096,
+///     }),
+///     encryption: Some(Encryption {
+///         key_file: Some("/etc/omnecache/cache.key".to_string()),
+///         key_env: None,
+///         previous_key_files: Vec::new(),
+///         previous_key_envs: Vec::new(),
+///     }),
+///     backend: Backend::Files,
 /// };
 /// ```
 ///
 /// Configuration with disabled disk cache:
 /// ```rust
-/// use byte_cache::configuration::DiskCfg;
+/// use omnecache::configuration::DiskCfg;
 ///
 /// let disk_cfg = DiskCfg {
 ///     disabled: true,
 ///     path: None,
 ///     items: None,
+///     max_bytes: None,
+///     eviction: Default::default(),
+///     layout: Default::default(),
+///     repair: false,
+///     compression: None,
+///     encryption: None,
+///     backend: Default::default(),
 /// };
 /// ```
 #[derive(ConstDefault, Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -44,6 +85,26 @@ pub struct DiskCfg {
     pub path: Option<String>,
     /// Maximum number of items to store in the disk cache
     pub items: Option<usize>,
+    /// Maximum total size in bytes of the items in the disk cache, unlimited if unset
+    pub max_bytes: Option<u64>,
+    /// Which items to evict when a new item would exceed `items` or `max_bytes`
+    #[serde(default)]
+    pub eviction: EvictionPolicy,
+    /// How the items are arranged in the directory; see [`crate::layout`]
+    #[serde(default)]
+    pub layout: Layout,
+    /// Whether to [repair](FsCache::repair) the directory when the cache is opened
+    #[serde(default)]
+    pub repair: bool,
+    /// How items are compressed; see [`crate::compression`]. Uncompressed if unset
+    pub compression: Option<Compression>,
+    /// Where the keys items are encrypted with are read from; see
+    /// [`crate::encryption`]. Unencrypted if unset
+    pub encryption: Option<Encryption>,
+    /// Whether items are stored as files arranged by `layout`, or appended
+    /// to segment files; see [`crate::pack`]
+    #[serde(default)]
+    pub backend: Backend,
 }
 
 impl DiskCfg {
@@ -60,6 +121,10 @@ impl DiskCfg {
     /// This method returns an error in the following cases:
     /// - If the disk cache is disabled
     /// - If the path or item limit is not specified
+    /// - If the directory holds entries in another layout or backend
+    /// - If `backend` is [`Backend::Pack`] and another cache uses the directory
+    /// - If `encryption` is set and its keys could not be read
+    /// - If `repair` is set and the directory could not be repaired
     /// - If the filesystem cache initialization fails
     ///
     /// # Example
@@ -71,6 +136,13 @@ impl DiskCfg {
     ///         disabled: false,
     ///         path: Some("/tmp/cache".to_string()),
     ///         items: Some(1000),
+    ///         max_bytes: None,
+    ///         eviction: Default::default(),
+    ///         layout: Default::default(),
+    ///         repair: false,
+    ///         compression: None,
+    ///         encryption: None,
+    ///         backend: Default::default(),
     ///     };
     ///     
     ///     let fs_cache = cfg.as_fs_cache().await?;
@@ -78,15 +150,60 @@ impl DiskCfg {
     /// }
     /// ```
     pub async fn as_fs_cache(&self) -> std::io::Result<FsCache<ReadWrite>> {
-        if self.disabled {
+        let (path, items) = self.location()?;
+        let keyring = self.keyring()?;
+        let cache = match self.backend {
+            Backend::Files => FsCache::new_write_with_layout(path, items, self.layout).await?,
+            Backend::Pack => FsCache::new_pack(path, items).await?,
+        };
+        cache.set_max_bytes(self.max_bytes);
+        cache.set_eviction(self.eviction);
+        cache.set_compression(self.compression);
+        cache.set_encryption(keyring);
+
+        if self.repair {
+            warn_repaired(&cache, &cache.repair().await?);
+        }
+
+        Ok(cache)
+    }
+
+    /// Converts the disk configuration into a filesystem cache on the calling thread.
+    ///
+    /// Behaves like [`DiskCfg::as_fs_cache`], for use without an async runtime,
+    /// except that [`Backend::Pack`] is rejected: a blocking cache shares its
+    /// directory with other caches, and a pack store cannot be shared.
+    pub(crate) fn as_blocking_fs_cache(&self) -> std::io::Result<FsCache<ReadWrite>> {
+        let (path, items) = self.location()?;
+        let keyring = self.keyring()?;
+        if self.backend == Backend::Pack {
             return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Disk cache is disabled",
+                std::io::ErrorKind::Unsupported,
+                "Blocking caches cannot use a pack store",
             ));
         }
 
+        let cache = FsCache::new_write_blocking(path, items, Some(self.layout))?;
+        cache.set_max_bytes(self.max_bytes);
+        cache.set_eviction(self.eviction);
+        cache.set_compression(self.compression);
+        cache.set_encryption(keyring);
+
+        if self.repair {
+            warn_repaired(&cache, &cache.repair_blocking()?);
+        }
+
+        Ok(cache)
+    }
+
+    /// Validates the configuration and returns the cache directory and item limit.
+    pub(crate) fn location(&self) -> std::io::Result<(PathBuf, usize)> {
+        if self.disabled {
+            return Err(std::io::Error::other("Disk cache is disabled"));
+        }
+
         if let (Some(path), Some(items)) = (self.path.clone(), self.items) {
-            FsCache::new_write(PathBuf::from(path), items).await
+            Ok((PathBuf::from(path), items))
         } else {
             Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
@@ -94,6 +211,28 @@ impl DiskCfg {
             ))
         }
     }
+
+    /// Reads the keys named by [`DiskCfg::encryption`].
+    ///
+    /// # Returns
+    /// * `Ok(Some(Keyring))`: The keys, if encryption is configured
+    /// * `Ok(None)`: If entries are not encrypted
+    /// * `Err(std::io::Error)`: If the keys could not be read
+    pub(crate) fn keyring(&self) -> std::io::Result<Option<Keyring>> {
+        self.encryption.as_ref().map(Keyring::load).transpose()
+    }
+}
+
+/// Reports the changes made by repairing a cache on open.
+fn warn_repaired(cache: &FsCache<ReadWrite>, report: &RepairReport) {
+    if report.changed() {
+        eprintln!(
+            "Warning: Repaired disk cache at {}: removed {} files, quarantined {} entries",
+            cache.path().display(),
+            report.removed.len(),
+            report.quarantined.len()
+        );
+    }
 }
 
 #[cfg(test)]
@@ -106,6 +245,13 @@ mod tests {
             disabled: true,
             path: Some("cache".to_string()),
             items: Some(100),
+            max_bytes: None,
+            eviction: Default::default(),
+            layout: Default::default(),
+            repair: false,
+            compression: None,
+            encryption: None,
+            backend: Default::default(),
         };
         assert_eq!(cfg.path, Some("cache".to_string()));
         assert_eq!(cfg.items, Some(100));
@@ -124,6 +270,13 @@ mod tests {
             disabled: true,
             path: Some("cache".to_string()),
             items: Some(100),
+            max_bytes: None,
+            eviction: Default::default(),
+            layout: Default::default(),
+            repair: false,
+            compression: None,
+            encryption: None,
+            backend: Default::default(),
         };
         let toml_str = toml::to_string(&cfg).unwrap();
         assert!(toml_str.contains("path = \"cache\""));
@@ -135,18 +288,32 @@ mod tests {
         let toml_str = r#"
             path = "cache"
             items = 100
+            max_bytes = 1048576
+
+            [compression]
+            codec = "lz4"
+            min_size = 512
         "#;
         let cfg: DiskCfg = toml::from_str(toml_str).unwrap();
-        assert_eq!(cfg.disabled, false);
+        assert!(!cfg.disabled);
         assert_eq!(cfg.path, Some("cache".to_string()));
         assert_eq!(cfg.items, Some(100));
+        assert_eq!(cfg.max_bytes, Some(1048576));
+        assert_eq!(
+            cfg.compression,
+            Some(Compression {
+                codec: crate::compression::Codec::Lz4,
+                level: None,
+                min_size: 512,
+            })
+        );
     }
 
     #[test]
     fn test_deserialize_from_toml_disabled() {
         let toml_str = "disabled = true";
         let cfg: DiskCfg = toml::from_str(toml_str).unwrap();
-        assert_eq!(cfg.disabled, true);
+        assert!(cfg.disabled);
         assert_eq!(cfg.path, None);
         assert_eq!(cfg.items, None);
     }
@@ -13,7 +13,7 @@ use super::*;
 ///
 /// Basic memory cache with 500 item limit:
 /// ```rust
-/// use byte_cache::configuration::MemoryCfg;
+/// use omnecache::configuration::MemoryCfg;
 ///
 /// let memory_cfg = MemoryCfg {
 ///     disabled: false,
@@ -23,7 +23,7 @@ use super::*;
 ///
 /// Disabled memory cache:
 /// ```rust
-/// use byte_cache::configuration::MemoryCfg;
+/// use omnecache::configuration::MemoryCfg;
 ///
 /// let memory_cfg = MemoryCfg {
 ///     disabled: true,
@@ -52,16 +52,21 @@ impl MemoryCfg {
     /// - If the item count is not specified (items is None)
     /// - If the item count is zero (invalid NonZeroUsize)
     pub async fn lru_cache(&self) -> std::io::Result<lru::LruCache<String, Vec<u8>>> {
+        Ok(lru::LruCache::new(self.capacity()?))
+    }
+
+    /// Returns the validated capacity of the memory cache.
+    ///
+    /// # Errors
+    /// Under the same conditions as [`MemoryCfg::lru_cache`].
+    pub(crate) fn capacity(&self) -> std::io::Result<NonZeroUsize> {
         if self.disabled {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Memory cache is disabled",
-            ));
+            return Err(std::io::Error::other("Memory cache is disabled"));
         }
 
         if let Some(items) = self.items {
             if let Some(count) = NonZeroUsize::new(items) {
-                Ok(lru::LruCache::new(count))
+                Ok(count)
             } else {
                 Err(std::io::Error::new(
                     std::io::ErrorKind::InvalidInput,
@@ -87,14 +92,14 @@ mod tests {
             disabled: true,
             items: Some(100),
         };
-        assert_eq!(cfg.disabled, true);
+        assert!(cfg.disabled);
         assert_eq!(cfg.items, Some(100));
     }
 
     #[test]
     fn test_memory_cfg_default() {
         let cfg = MemoryCfg::DEFAULT;
-        assert_eq!(cfg.disabled, false);
+        assert!(!cfg.disabled);
         assert_eq!(cfg.items, None);
     }
 
@@ -115,7 +120,7 @@ mod tests {
             items = 100
         "#;
         let cfg: MemoryCfg = toml::from_str(toml_str).unwrap();
-        assert_eq!(cfg.disabled, false);
+        assert!(!cfg.disabled);
         assert_eq!(cfg.items, Some(100));
     }
 }
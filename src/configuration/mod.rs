@@ -25,8 +25,8 @@
 //! ## Example
 //!
 //! ```rust,no_run,ignore
-//! use byte_cache::OmneCache;
-//! use byte_cache::configuration::{OmneCacheCfg, Configurable, MemoryCfg, DiskCfg, SideloadCfg};
+//! use omnecache::OmneCache;
+//! use omnecache::configuration::{OmneCacheCfg, Configurable, MemoryCfg, DiskCfg, SideloadCfg};
 //! use std::path::PathBuf;
 //!
 //! // Create a new configuration
@@ -39,12 +39,20 @@
 //!         disabled: false,
 //!         path: Some("/var/cache/SomeOmneCacheApp/evidence".into()),
 //!         items: Some(10000),
+//!         max_bytes: None,
+//!         eviction: Default::default(),
+//!         layout: Default::default(),
+//!         repair: false,
+//!         compression: None,
+//!         encryption: None,
+//!         backend: Default::default(),
 //!     }),
 //!     sideload: Some(SideloadCfg {
 //!         disabled: false,
 //!         path: Some("/var/sideload/SomeOmneCacheApp/evidence".into()),
 //!         items: Some(5000),
 //!     }),
+//!     ttl: None,
 //! };
 //!
 //! // Save the configuration
@@ -64,12 +72,20 @@
 //!         disabled: false,
 //!         path: Some("/var/cache/SomeOmneCacheApp/evidence".into()),
 //!        items: Some(10000),
+//!        max_bytes: None,
+//!        eviction: Default::default(),
+//!        layout: Default::default(),
+//!        repair: false,
+//!        compression: None,
+//!        encryption: None,
+//!        backend: Default::default(),
 //!   }),
 //!   sideload: Some(SideloadCfg {
 //!        disabled: false,
 //!        path: Some("/var/sideload/SomeOmneCacheApp/evidence".into()),
 //!       items: Some(5000),
 //!   }),
+//!   ttl: None,
 //! }).unwrap();
 //! ```
 
@@ -97,6 +113,10 @@ pub struct OmneCacheCfg {
     pub sideload: Option<SideloadCfg>,
     /// Configuration for persistent disk storage
     pub disk: Option<DiskCfg>,
+    /// Seconds a stored value stays fresh before `get_or_fetch` revalidates it.
+    /// Without a TTL, cached values never go stale.
+    #[serde(default)]
+    pub ttl: Option<u64>,
 }
 
 #[cfg(test)]
@@ -114,17 +134,25 @@ mod tests {
                 disabled: false,
                 path: Some("/var/cache/SomeOmneCacheApp/evidence".into()),
                 items: Some(10000),
+                max_bytes: None,
+                eviction: Default::default(),
+                layout: Default::default(),
+                repair: false,
+                compression: None,
+                encryption: None,
+                backend: Default::default(),
             }),
             sideload: Some(SideloadCfg {
                 disabled: false,
                 path: Some("/var/sideload/SomeOmneCacheApp/evidence".into()),
                 items: Some(5000),
             }),
+            ttl: None,
         };
 
-        assert_eq!(builder.memory.is_some(), true);
-        assert_eq!(builder.disk.is_some(), true);
-        assert_eq!(builder.sideload.is_some(), true);
+        assert!(builder.memory.is_some());
+        assert!(builder.disk.is_some());
+        assert!(builder.sideload.is_some());
     }
 
     #[tokio::test]
@@ -138,12 +166,20 @@ mod tests {
                 disabled: true,
                 path: Some("/var/cache/SomeOmneCacheApp/evidence".into()),
                 items: Some(10000),
+                max_bytes: None,
+                eviction: Default::default(),
+                layout: Default::default(),
+                repair: false,
+                compression: None,
+                encryption: None,
+                backend: Default::default(),
             }),
             sideload: Some(SideloadCfg {
                 disabled: true,
                 path: Some("/var/sideload/SomeOmneCacheApp/evidence".into()),
                 items: Some(5000),
             }),
+            ttl: None,
         };
 
         let toml_str = toml::to_string(&cfg).unwrap();
@@ -161,7 +197,7 @@ mod tests {
         "#;
 
         let cfg: OmneCacheCfg = toml::from_str(toml_str).unwrap();
-        assert_eq!(cfg.memory.unwrap().disabled, false);
+        assert!(!cfg.memory.unwrap().disabled);
         assert_eq!(cfg.disk.unwrap().path, Some(String::from("/var/cache/app")));
         assert_eq!(
             cfg.sideload.unwrap().path,
@@ -183,7 +219,7 @@ path = "/var/sideload"
 "#;
 
         let cfg: OmneCacheCfg = toml::from_str(toml_str).unwrap();
-        assert_eq!(cfg.memory.unwrap().disabled, false);
+        assert!(!cfg.memory.unwrap().disabled);
         assert_eq!(cfg.memory.unwrap().items, Some(2000));
         assert_eq!(
             cfg.disk.clone().unwrap().path,
@@ -201,7 +237,7 @@ path = "/var/sideload"
         let mem_cfg = include_str!("../../examples/mem_only_cache.toml");
 
         let cfg: OmneCacheCfg = toml::from_str(mem_cfg).unwrap();
-        assert_eq!(cfg.memory.unwrap().disabled, false);
+        assert!(!cfg.memory.unwrap().disabled);
         assert_eq!(cfg.memory.unwrap().items, Some(100));
     }
 
@@ -234,7 +270,7 @@ path = "/var/sideload"
         let default_cfg = include_str!("../../examples/default.toml");
 
         let cfg: OmneCacheCfg = toml::from_str(default_cfg).unwrap();
-        assert_eq!(cfg.memory.unwrap().disabled, false);
+        assert!(!cfg.memory.unwrap().disabled);
         assert_eq!(cfg.memory.unwrap().items, Some(2000));
         assert_eq!(
             cfg.disk.clone().unwrap().path,
@@ -37,7 +37,7 @@ impl SideloadCfg {
     /// # Example
     /// ```rust,ignore
     /// use std::path::PathBuf;
-    /// use byte_cache::configuration::SideloadCfg;
+    /// use omnecache::configuration::SideloadCfg;
     ///
     /// let sideload_cfg = SideloadCfg::new("sideload".to_string()).await;
     /// assert_eq!(sideload_cfg.path, Some("sideload".to_string()));
@@ -78,9 +78,9 @@ impl SideloadCfg {
     /// # Example
     /// ```rust,ignore
     /// use std::path::PathBuf;
-    /// use byte_cache::configuration::SideloadCfg;
-    /// use byte_cache::fs::FsCache;
-    /// use byte_cache::fs::Read;
+    /// use omnecache::configuration::SideloadCfg;
+    /// use omnecache::fs::FsCache;
+    /// use omnecache::fs::Read;
     ///
     /// let sideload_cfg = SideloadCfg::new("sideload".to_string()).await;
     /// let fs_cache = sideload_cfg.as_fs_cache().await;
@@ -92,11 +92,26 @@ impl SideloadCfg {
     /// If the cache is disabled, it returns an error.
     /// If the path does not exist, it returns an error.
     pub async fn as_fs_cache(&self) -> std::io::Result<FsCache<Read>> {
+        let cache = FsCache::new_read(self.location()?).await?;
+        cache.set_configured_items(self.items);
+
+        Ok(cache)
+    }
+
+    /// Converts the sideload configuration into a filesystem cache on the calling thread.
+    ///
+    /// Behaves like [`SideloadCfg::as_fs_cache`], for use without an async runtime.
+    pub(crate) fn as_blocking_fs_cache(&self) -> std::io::Result<FsCache<Read>> {
+        let cache = FsCache::new_read_blocking(self.location()?)?;
+        cache.set_configured_items(self.items);
+
+        Ok(cache)
+    }
+
+    /// Validates the configuration and returns the sideload directory.
+    pub(crate) fn location(&self) -> std::io::Result<PathBuf> {
         if self.disabled {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Sideload cache is disabled",
-            ));
+            return Err(std::io::Error::other("Sideload cache is disabled"));
         }
 
         // Get file count from the path.
@@ -110,7 +125,7 @@ impl SideloadCfg {
                 ));
             }
 
-            FsCache::new_read(path).await
+            Ok(path)
         } else {
             Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
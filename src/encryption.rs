@@ -0,0 +1,382 @@
+//! # OmneCache Encryption at Rest
+//!
+//! Authenticated encryption of the entries stored by the disk layer with
+//! XChaCha20-Poly1305, configured through
+//! [`DiskCfg::encryption`](crate::configuration::DiskCfg::encryption).
+//!
+//! Keys are 32 bytes long. They are read from a key file, holding either the
+//! raw 32 bytes or 64 hex digits, or from an environment variable holding 64
+//! hex digits, such as the output of `openssl rand -hex 32`.
+//!
+//! Every encrypted entry records the id of its key, derived from the key
+//! itself. Entries written with one of the previous keys listed in the
+//! configuration stay readable, and are re-encrypted with the current key the
+//! next time they are read, so keys can be rotated without clearing the
+//! cache. Entries encrypted with a key that is no longer listed are misses.
+//!
+//! The entry's cache key is authenticated along with its content, so an
+//! encrypted file cannot be passed off as the entry of another key. Names of
+//! entries, their [metadata](crate::meta) and [checksums](crate::checksum) are
+//! not encrypted. Checksums, and the versions tying metadata files to their
+//! entries, are hashes of the entry as stored, ciphertext included, and pack
+//! records hold no hash of their value: nothing stored in clear is a hash of
+//! the value, which would let anyone reading the directory confirm a guess.
+//!
+//! # Example
+//! ```rust,no_run
+//! use omnecache::encryption::Encryption;
+//!
+//! let encryption = Encryption {
+//!     key_file: None,
+//!     key_env: Some("OMNECACHE_KEY".to_string()),
+//!     previous_key_files: vec!["/etc/omnecache/retired.key".to_string()],
+//!     previous_key_envs: Vec::new(),
+//! };
+//! ```
+
+use std::path::Path;
+
+use chacha20poly1305::{
+    KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, Payload},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::format::invalid;
+
+/// Length of a key in bytes
+const KEY_LEN: usize = 32;
+/// Length of the id recorded in every entry
+const KEY_ID_LEN: usize = 8;
+/// Length of the random nonce recorded in every entry
+const NONCE_LEN: usize = 24;
+/// Context of the key derivation naming a key's id
+const KEY_ID_CONTEXT: &str = "omnecache 2025-06 entry key id";
+
+/// Where the keys of an encrypted disk layer are read from.
+///
+/// Exactly one of `key_file` and `key_env` names the current key, which new
+/// entries are encrypted with.
+///
+/// # Fields
+/// * `key_file`: Path of the file holding the current key
+/// * `key_env`: Name of the environment variable holding the current key
+/// * `previous_key_files`: Paths of files holding keys entries may still be encrypted with
+/// * `previous_key_envs`: Names of environment variables holding such keys
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Encryption {
+    /// File holding the current key
+    pub key_file: Option<String>,
+    /// Environment variable holding the current key
+    pub key_env: Option<String>,
+    /// Files holding previous keys
+    #[serde(default)]
+    pub previous_key_files: Vec<String>,
+    /// Environment variables holding previous keys
+    #[serde(default)]
+    pub previous_key_envs: Vec<String>,
+}
+
+/// Id of a key, recorded in the entries encrypted with it
+type KeyId = [u8; KEY_ID_LEN];
+
+/// The keys of an encrypted disk layer: the current key first, then the previous keys.
+#[derive(Clone)]
+pub struct Keyring {
+    keys: Vec<(KeyId, XChaCha20Poly1305)>,
+}
+
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keyring")
+            .field("key_ids", &self.ids())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Keyring {
+    /// Creates a keyring from the current key and the previous keys.
+    pub fn new(current: [u8; KEY_LEN], previous: impl IntoIterator<Item = [u8; KEY_LEN]>) -> Self {
+        let keys = std::iter::once(current)
+            .chain(previous)
+            .map(|key| {
+                let id = blake3::derive_key(KEY_ID_CONTEXT, &key);
+                let mut key_id = [0; KEY_ID_LEN];
+                key_id.copy_from_slice(&id[..KEY_ID_LEN]);
+
+                (key_id, XChaCha20Poly1305::new(&key.into()))
+            })
+            .collect();
+
+        Self { keys }
+    }
+
+    /// Reads the keys named by an encryption configuration.
+    ///
+    /// # Returns
+    /// * `Ok(Keyring)`: The keys
+    /// * `Err(std::io::Error)`: Of kind [`std::io::ErrorKind::InvalidInput`] if
+    ///   not exactly one current key is named, or a key is missing or malformed
+    pub fn load(cfg: &Encryption) -> std::io::Result<Self> {
+        let current = match (&cfg.key_file, &cfg.key_env) {
+            (Some(path), None) => read_key_file(Path::new(path))?,
+            (None, Some(name)) => read_key_env(name)?,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "Exactly one of key_file and key_env must name the encryption key",
+                ));
+            }
+        };
+
+        let mut previous = Vec::new();
+        for path in &cfg.previous_key_files {
+            previous.push(read_key_file(Path::new(path))?);
+        }
+        for name in &cfg.previous_key_envs {
+            previous.push(read_key_env(name)?);
+        }
+
+        Ok(Self::new(current, previous))
+    }
+
+    /// Returns the ids of the keys as hex, the current key's first.
+    pub fn ids(&self) -> Vec<String> {
+        self.keys.iter().map(|(id, _)| hex(id)).collect()
+    }
+
+    /// Encrypts the payload of the entry for `key` with the current key.
+    ///
+    /// # Returns
+    /// The id of the key, the nonce and the encrypted payload
+    pub(crate) fn seal(
+        &self,
+        key: &str,
+        header: &[u8],
+        payload: &[u8],
+    ) -> std::io::Result<Vec<u8>> {
+        let (key_id, cipher) = &self.keys[0];
+        let nonce: [u8; NONCE_LEN] = rand::random();
+
+        let encrypted = cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: payload,
+                    aad: &associated_data(header, key),
+                },
+            )
+            .map_err(|_| std::io::Error::other("Cache entry could not be encrypted"))?;
+
+        Ok([&key_id[..], &nonce, &encrypted].concat())
+    }
+
+    /// Decrypts the payload of the entry for `key`, as sealed by [`Keyring::seal`].
+    ///
+    /// # Returns
+    /// * `Ok((Vec<u8>, bool))`: The payload, and whether it was encrypted with the current key
+    /// * `Err(std::io::Error)`: Of kind [`std::io::ErrorKind::PermissionDenied`]
+    ///   if the entry's key is not in the keyring, or
+    ///   [`std::io::ErrorKind::InvalidData`] if the entry is damaged
+    pub(crate) fn open(
+        &self,
+        key: &str,
+        header: &[u8],
+        sealed: &[u8],
+    ) -> std::io::Result<(Vec<u8>, bool)> {
+        let Some((key_id, rest)) = sealed.split_at_checked(KEY_ID_LEN) else {
+            return Err(invalid("Truncated encrypted cache entry"));
+        };
+        let Some((nonce, encrypted)) = rest.split_at_checked(NONCE_LEN) else {
+            return Err(invalid("Truncated encrypted cache entry"));
+        };
+
+        let Some(position) = self.keys.iter().position(|(id, _)| id[..] == *key_id) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("Cache entry is encrypted with unknown key {}", hex(key_id)),
+            ));
+        };
+
+        let payload = self.keys[position]
+            .1
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: encrypted,
+                    aad: &associated_data(header, key),
+                },
+            )
+            .map_err(|_| invalid("Cache entry failed authentication"))?;
+
+        Ok((payload, position == 0))
+    }
+}
+
+/// Returns the data authenticated along with an entry's payload.
+fn associated_data(header: &[u8], key: &str) -> Vec<u8> {
+    [header, key.as_bytes()].concat()
+}
+
+/// Reads a key from a file holding either the raw key or its hex digits.
+fn read_key_file(path: &Path) -> std::io::Result<[u8; KEY_LEN]> {
+    let content = std::fs::read(path).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Cannot read encryption key file {}: {}", path.display(), e),
+        )
+    })?;
+
+    match <[u8; KEY_LEN]>::try_from(&content[..]) {
+        Ok(key) => Ok(key),
+        Err(_) => parse_key(&String::from_utf8_lossy(&content))
+            .ok_or_else(|| malformed(&path.display().to_string())),
+    }
+}
+
+/// Reads the hex digits of a key from an environment variable.
+fn read_key_env(name: &str) -> std::io::Result<[u8; KEY_LEN]> {
+    let value = std::env::var(name).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Cannot read encryption key variable {}: {}", name, e),
+        )
+    })?;
+
+    parse_key(&value).ok_or_else(|| malformed(name))
+}
+
+/// Parses the hex digits of a key.
+fn parse_key(text: &str) -> Option<[u8; KEY_LEN]> {
+    let digits = text.trim().as_bytes();
+
+    if digits.len() != KEY_LEN * 2 {
+        return None;
+    }
+
+    let mut key = [0; KEY_LEN];
+    for (byte, pair) in key.iter_mut().zip(digits.chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+
+    Some(key)
+}
+
+/// Builds the error of a key that is neither 32 bytes nor 64 hex digits.
+fn malformed(source: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!(
+            "Encryption key {} must be {} bytes or {} hex digits",
+            source,
+            KEY_LEN,
+            KEY_LEN * 2
+        ),
+    )
+}
+
+/// Formats bytes as lowercase hex digits.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        fs::{FsCache, ReadWrite},
+        meta::{EntryInfo, EntryVersion},
+    };
+
+    #[test]
+    fn test_load_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let raw = dir.path().join("raw.key");
+        let hex_file = dir.path().join("hex.key");
+        std::fs::write(&raw, [7; KEY_LEN]).unwrap();
+        std::fs::write(&hex_file, format!("{}\n", hex(&[7; KEY_LEN]))).unwrap();
+
+        let load = |key_file: &Path| {
+            Keyring::load(&Encryption {
+                key_file: Some(key_file.to_string_lossy().to_string()),
+                ..Default::default()
+            })
+        };
+        assert_eq!(load(&raw).unwrap().ids(), load(&hex_file).unwrap().ids());
+
+        std::fs::write(&raw, b"short").unwrap();
+        assert_eq!(
+            load(&raw).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            Keyring::load(&Encryption::default()).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        let info = EntryInfo {
+            validator: Some("\"etag\"".to_string()),
+            fresh_until: Some(42),
+        };
+
+        cache.set_encryption(Some(Keyring::new([1; KEY_LEN], [])));
+        cache
+            .put_with_info("key1", b"secret evidence", &info)
+            .await
+            .unwrap();
+        cache.put("key2", b"more evidence").await.unwrap();
+
+        let stored = std::fs::read(dir.path().join("key1")).unwrap();
+        assert!(!stored.windows(6).any(|window| window == b"secret"));
+
+        // The metadata file does not reveal a hash of the value.
+        let meta = std::fs::read_to_string(dir.path().join("key1.meta")).unwrap();
+        assert!(!meta.contains(&EntryVersion::of(b"secret evidence").to_string()));
+        assert_eq!(
+            cache.get_with_info("key1").await,
+            Some((b"secret evidence".to_vec(), info.clone()))
+        );
+        assert!(
+            cache
+                .compare_and_swap("key2", &EntryVersion::of(b"more evidence"), b"evidence")
+                .await
+                .unwrap()
+        );
+
+        // Rotating the key re-encrypts entries as they are read.
+        cache.set_encryption(Some(Keyring::new([2; KEY_LEN], [[1; KEY_LEN]])));
+        assert_eq!(
+            cache.get_with_info("key1").await,
+            Some((b"secret evidence".to_vec(), info.clone()))
+        );
+        cache.set_encryption(Some(Keyring::new([2; KEY_LEN], [])));
+        assert_eq!(
+            cache.get_with_info("key1").await,
+            Some((b"secret evidence".to_vec(), info))
+        );
+
+        // Entries encrypted with an unknown key are misses, and stay in place.
+        assert_eq!(cache.get("key2").await, None);
+        cache.set_encryption(None);
+        assert_eq!(cache.get("key1").await, None);
+        assert_eq!(cache.corrupt_entries(), 0);
+        assert!(dir.path().join("key2").exists());
+
+        // An entry cannot be passed off as the entry of another key.
+        cache.set_encryption(Some(Keyring::new([2; KEY_LEN], [])));
+        cache.on_corrupt_entry(Arc::new(|_| {}));
+        std::fs::copy(dir.path().join("key1"), dir.path().join("key2")).unwrap();
+        assert_eq!(cache.get("key2").await, None);
+        assert_eq!(cache.corrupt_entries(), 1);
+        assert!(!dir.path().join("key2").exists());
+    }
+}
@@ -6,7 +6,7 @@
 //!
 //! * [`CacheableError`]: Errors that occur during the core caching operations
 //! * [`ConfigurationError`]: Errors related to configuration loading and saving
-//! * [`TokioError`]: Wrapper for various tokio-related errors
+//! * `TokioError`: Wrapper for various tokio-related errors (`tokio` feature only)
 //!
 //! The error types implement standard Rust traits like `std::error::Error` and
 //! `std::fmt::Display` for integration with the Rust error handling ecosystem.
@@ -18,12 +18,17 @@
 //! * `std::io::Error` → `ConfigurationError` and `CacheableError`
 //! * `toml::de::Error` → `ConfigurationError`
 //! * `toml::ser::Error` → `ConfigurationError`
-//! * `tokio::task::JoinError` → `TokioError` and `CacheableError`
-//! * `tokio::time::error::Elapsed` → `TokioError` and `CacheableError`
+//! * `tokio::task::JoinError` → `TokioError` and `CacheableError` (`tokio` feature only)
+//! * `tokio::time::error::Elapsed` → `TokioError` and `CacheableError` (`tokio` feature only)
 //! * `nix::errno::Errno` → `CacheableError`
 //!
 //! This makes it easy to use the `?` operator in functions that can produce these errors.
+//!
+//! Runtime failures inside the cache itself (timeouts, failed blocking tasks) are
+//! reported as [`CacheableError::Io`] on every runtime; a timeout has the kind
+//! [`std::io::ErrorKind::TimedOut`].
 
+#[cfg(feature = "tokio")]
 #[derive(Debug)]
 pub enum TokioError {
     Timeout(tokio::time::error::Elapsed),
@@ -32,8 +37,10 @@ pub enum TokioError {
     Io(tokio::io::Error),
 }
 
+#[cfg(feature = "tokio")]
 impl std::error::Error for TokioError {}
 
+#[cfg(feature = "tokio")]
 impl std::fmt::Display for TokioError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -45,24 +52,28 @@ impl std::fmt::Display for TokioError {
     }
 }
 
+#[cfg(feature = "tokio")]
 impl From<tokio::time::error::Elapsed> for TokioError {
     fn from(value: tokio::time::error::Elapsed) -> Self {
         Self::Timeout(value)
     }
 }
 
+#[cfg(feature = "tokio")]
 impl From<tokio::time::error::Error> for TokioError {
     fn from(value: tokio::time::error::Error) -> Self {
         Self::Time(value)
     }
 }
 
+#[cfg(feature = "tokio")]
 impl From<tokio::io::Error> for TokioError {
     fn from(value: tokio::io::Error) -> Self {
         Self::Io(value)
     }
 }
 
+#[cfg(feature = "tokio")]
 impl From<tokio::task::JoinError> for TokioError {
     fn from(value: tokio::task::JoinError) -> Self {
         Self::Task(value)
@@ -84,6 +95,7 @@ pub enum CacheableError {
     /// Empty Key error when a key string is unexpectedly empty
     EmptyKey,
     /// Tokio-related errors (timeouts, tasks, etc.)
+    #[cfg(feature = "tokio")]
     Tokio(TokioError),
     /// An error occurred during file I/O operations
     Io(std::io::Error),
@@ -97,6 +109,7 @@ impl std::fmt::Display for CacheableError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::NotFound => write!(f, "Data not found"),
+            #[cfg(feature = "tokio")]
             Self::Tokio(error) => write!(f, "Tokio Error: {}", error),
             Self::WriteError => write!(f, "Could not find a viable cache to write to"),
             Self::EmptyBuffer => write!(f, "Keys cannot have an empty value"),
@@ -107,18 +120,21 @@ impl std::fmt::Display for CacheableError {
     }
 }
 
+#[cfg(feature = "tokio")]
 impl From<tokio::task::JoinError> for CacheableError {
     fn from(error: tokio::task::JoinError) -> Self {
         Self::Tokio(error.into())
     }
 }
 
+#[cfg(feature = "tokio")]
 impl From<tokio::time::error::Elapsed> for CacheableError {
     fn from(error: tokio::time::error::Elapsed) -> Self {
         Self::Tokio(error.into())
     }
 }
 
+#[cfg(feature = "tokio")]
 impl From<TokioError> for CacheableError {
     fn from(error: TokioError) -> Self {
         Self::Tokio(error)
@@ -209,7 +225,7 @@ mod tests {
 
     #[test]
     fn test_configuration_error_io_display() {
-        let error = std::io::Error::new(std::io::ErrorKind::Other, "IO error");
+        let error = std::io::Error::other("IO error");
         let config_error: ConfigurationError = error.into();
         assert_eq!(format!("{}", config_error), "IO error: IO error");
     }
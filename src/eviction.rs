@@ -0,0 +1,164 @@
+//! # OmneCache Disk Eviction
+//!
+//! What the disk layer does when a new entry would exceed its item limit
+//! ([`DiskCfg::items`](crate::configuration::DiskCfg::items)):
+//!
+//! * [`EvictionPolicy::Lru`] (default): remove the entries read or written least recently
+//! * [`EvictionPolicy::Lfu`]: remove the entries read or written least often,
+//!   the least recently used first among equally used entries
+//! * [`EvictionPolicy::Fifo`]: remove the entries written first
+//! * [`EvictionPolicy::Reject`]: keep every entry and fail the write with
+//!   [`std::io::ErrorKind::StorageFull`]
+//!
+//! Reads and writes are recorded in the disk layer's index, so they survive
+//! restarts and are shared by every process using the directory. Reads are
+//! batched, so other processes see them up to a second late. Accesses are
+//! only recorded while the policy ranks by them.
+
+use const_default::ConstDefault;
+use serde::{Deserialize, Serialize};
+
+use crate::index::IndexEntry;
+
+/// Selects the entries removed when the disk layer is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EvictionPolicy {
+    /// Evict the least recently used entries
+    #[default]
+    Lru,
+    /// Evict the least frequently used entries
+    Lfu,
+    /// Evict the oldest entries
+    Fifo,
+    /// Evict nothing; writes of new entries fail while the layer is full
+    Reject,
+}
+
+impl ConstDefault for EvictionPolicy {
+    const DEFAULT: Self = Self::Lru;
+}
+
+impl EvictionPolicy {
+    /// Returns `true` if the policy ranks entries by their recorded accesses.
+    pub(crate) const fn tracks_access(&self) -> bool {
+        matches!(self, Self::Lru | Self::Lfu)
+    }
+}
+
+/// Orders the indexed entries so that those to evict first come first.
+///
+/// # Returns
+/// The keys of the entries with their sizes in bytes
+pub(crate) fn rank<'a>(
+    entries: impl IntoIterator<Item = (&'a String, &'a IndexEntry)>,
+    policy: EvictionPolicy,
+) -> Vec<(String, u64)> {
+    let mut ranked: Vec<_> = entries.into_iter().collect();
+
+    match policy {
+        EvictionPolicy::Lru => ranked.sort_by_key(|(_, entry)| entry.last_access),
+        EvictionPolicy::Lfu => ranked.sort_by_key(|(_, entry)| (entry.hits, entry.last_access)),
+        EvictionPolicy::Fifo | EvictionPolicy::Reject => {
+            ranked.sort_by_key(|(_, entry)| entry.modified)
+        }
+    }
+
+    ranked
+        .into_iter()
+        .map(|(key, entry)| (key.clone(), entry.size))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use crate::{
+        error::CacheableError,
+        fs::{FsCache, ReadWrite},
+        meta::EntryVersion,
+    };
+
+    use super::*;
+
+    /// Fills a cache with a limit of two with `a` then `b`, reading `reads` in order.
+    async fn full_cache(dir: &Path, policy: EvictionPolicy, reads: &[&str]) -> FsCache<ReadWrite> {
+        let cache = FsCache::new_write(dir, 2).await.unwrap();
+        cache.set_eviction(policy);
+
+        for key in ["a", "b"].into_iter().chain(reads.iter().copied()) {
+            // Keep modification times of consecutive accesses apart.
+            tokio::time::sleep(Duration::from_millis(20)).await;
+
+            if cache.get(key).await.is_none() {
+                cache.put(key, key.as_bytes()).await.unwrap();
+            }
+        }
+
+        cache
+    }
+
+    async fn keys(cache: &FsCache<ReadWrite>) -> Vec<String> {
+        let mut keys: Vec<_> = cache
+            .entries()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.key)
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn test_policies_choose_victims() {
+        for (policy, reads, kept) in [
+            (EvictionPolicy::Lru, &["a"][..], ["a", "c"]),
+            (EvictionPolicy::Lfu, &["b", "b", "a"][..], ["b", "c"]),
+            (EvictionPolicy::Fifo, &["a"][..], ["b", "c"]),
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let cache = full_cache(dir.path(), policy, reads).await;
+
+            cache.put("c", b"c").await.unwrap();
+            assert_eq!(keys(&cache).await, kept, "{:?}", policy);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reject_keeps_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = full_cache(dir.path(), EvictionPolicy::Reject, &[]).await;
+
+        match cache.put("c", b"c").await {
+            Err(CacheableError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::StorageFull),
+            other => panic!("expected StorageFull, got {:?}", other),
+        }
+
+        // Replacing an existing entry needs no room.
+        cache.put("a", b"a2").await.unwrap();
+        assert_eq!(keys(&cache).await, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_condition_evicts_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = full_cache(dir.path(), EvictionPolicy::Lru, &[]).await;
+
+        let absent = EntryVersion::of(b"c");
+        assert!(!cache.compare_and_swap("c", &absent, b"c").await.unwrap());
+        assert_eq!(keys(&cache).await, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_access_tracking_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        full_cache(dir.path(), EvictionPolicy::Lru, &["a"]).await;
+
+        // A new cache on the same directory ranks by the recorded accesses.
+        let reopened = FsCache::new_write(dir.path(), 2).await.unwrap();
+        reopened.put("c", b"c").await.unwrap();
+        assert_eq!(keys(&reopened).await, ["a", "c"]);
+    }
+}
@@ -0,0 +1,440 @@
+//! # OmneCache Entry Format
+//!
+//! The form in which the disk layer stores values.
+//!
+//! Every entry written by [`FsCache::put`] starts with a header:
+//!
+//! | Bytes | Content                                                        |
+//! |-------|----------------------------------------------------------------|
+//! | 4     | Magic bytes `\x89OMC`                                          |
+//! | 1     | Format version, currently 2                                    |
+//! | 1     | Flags: the codec in the low four bits, `0x80` if encrypted     |
+//! | 8     | Expiry time in seconds since the Unix epoch, 0 if none         |
+//! | 2     | Length of the cache key in bytes                               |
+//! | n     | The cache key                                                  |
+//! | 32    | Checksum: BLAKE3 hash of the entry without these 32 bytes      |
+//!
+//! followed by the payload: the value, compressed with the codec if any, and
+//! for an encrypted entry sealed as described in [`crate::encryption`].
+//! Integers are little-endian. The expiry time is the
+//! [`EntryInfo::fresh_until`](crate::meta::EntryInfo::fresh_until) the entry
+//! was written with, and is reported as its metadata if the metadata file
+//! next to the entry is missing or was written for other content.
+//!
+//! The checksum covers the rest of the header and the stored payload, so
+//! decoding an entry with a version 2 header verifies it, and an entry that
+//! does not match its checksum is [corrupt](crate::checksum). Entries in the
+//! earlier forms below carry no checksum of their own.
+//!
+//! Readers also accept the forms written by earlier versions, which
+//! [`FsCache::migrate_format`] rewrites in place:
+//!
+//! * headerless files, holding the value as is, such as the entries of
+//!   sideload bundles
+//! * version 1 headers, holding only the magic bytes, version and flags, as
+//!   written by [`Compression::encode`](crate::compression::Compression::encode)
+//!
+//! [`FsCache::put`]: crate::fs::FsCache::put
+//! [`FsCache::migrate_format`]: crate::fs::FsCache::migrate_format
+
+use std::borrow::Cow;
+
+use crate::{
+    compression::{self, Codec, Compression},
+    encryption::Keyring,
+    meta::EntryInfo,
+};
+
+/// Bytes every entry with a header starts with
+pub(crate) const MAGIC: &[u8; 4] = b"\x89OMC";
+/// Version of the header format written by [`crate::fs::FsCache::put`]
+pub const VERSION: u8 = 2;
+/// Version of the header format holding only the magic bytes, version and flags
+const LEGACY_VERSION: u8 = 1;
+/// Length of the part every header starts with: magic, version and flags
+pub(crate) const HEADER_LEN: usize = MAGIC.len() + 2;
+/// Length of the checksum of a version 2 header
+const CHECKSUM_LEN: usize = 32;
+/// Length of a version 2 header without the key
+pub(crate) const STAMPED_HEADER_LEN: usize = HEADER_LEN + 8 + 2 + CHECKSUM_LEN;
+/// Bits of the flags naming the codec
+const CODEC_MASK: u8 = 0x0f;
+/// Flag of an encrypted entry
+const ENCRYPTED: u8 = 0x80;
+
+/// The header of a stored entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    /// Format version of the header
+    pub version: u8,
+    /// Codec the payload is compressed with, if any
+    pub codec: Option<Codec>,
+    /// Whether the payload is encrypted
+    pub encrypted: bool,
+    /// When the entry expires, in seconds since the Unix epoch, if ever
+    pub expires: Option<u64>,
+    /// The cache key the entry was written for, if recorded
+    pub key: Option<String>,
+    /// BLAKE3 hash of the entry without its checksum, if recorded
+    pub checksum: Option<[u8; 32]>,
+}
+
+/// Reads the header of the stored form of an entry.
+///
+/// # Returns
+/// * `Ok(Some(Header))`: The header
+/// * `Ok(None)`: If the entry is stored without a header
+/// * `Err(std::io::Error)`: Of kind [`std::io::ErrorKind::InvalidData`] if the
+///   header is truncated or not recognized
+pub fn read_header(stored: &[u8]) -> std::io::Result<Option<Header>> {
+    Ok(parse(stored)?.map(|(header, _)| header))
+}
+
+/// Reads the header of the stored form of an entry and its length in bytes.
+fn parse(stored: &[u8]) -> std::io::Result<Option<(Header, usize)>> {
+    if !stored.starts_with(MAGIC) {
+        return Ok(None);
+    }
+
+    let Some(prefix) = stored.get(..HEADER_LEN) else {
+        return Err(invalid("Truncated cache entry header"));
+    };
+
+    let version = prefix[MAGIC.len()];
+    let flags = prefix[HEADER_LEN - 1];
+    let codec = match version {
+        LEGACY_VERSION | VERSION if flags & !(CODEC_MASK | ENCRYPTED) == 0 => {
+            Codec::from_byte(flags & CODEC_MASK)
+        }
+        _ => None,
+    }
+    .ok_or_else(|| invalid("Unknown cache entry header"))?;
+
+    let mut header = Header {
+        version,
+        codec,
+        encrypted: flags & ENCRYPTED != 0,
+        expires: None,
+        key: None,
+        checksum: None,
+    };
+
+    if version == LEGACY_VERSION {
+        return Ok(Some((header, HEADER_LEN)));
+    }
+
+    let key_start = STAMPED_HEADER_LEN - CHECKSUM_LEN;
+    let Some(fixed) = stored.get(HEADER_LEN..key_start) else {
+        return Err(invalid("Truncated cache entry header"));
+    };
+    let (expires, key_len) = fixed.split_at(8);
+
+    let key_len = u16::from_le_bytes([key_len[0], key_len[1]]) as usize;
+    let header_len = STAMPED_HEADER_LEN + key_len;
+    let Some(key) = stored.get(key_start..key_start + key_len) else {
+        return Err(invalid("Truncated cache entry header"));
+    };
+    let Some(checksum) = stored.get(header_len - CHECKSUM_LEN..header_len) else {
+        return Err(invalid("Truncated cache entry header"));
+    };
+
+    header.expires = match u64::from_le_bytes(expires.try_into().unwrap_or_default()) {
+        0 => None,
+        expires => Some(expires),
+    };
+    header.key =
+        Some(String::from_utf8(key.to_vec()).map_err(|_| invalid("Cache entry key is not UTF-8"))?);
+    header.checksum = checksum.try_into().ok();
+
+    Ok(Some((header, header_len)))
+}
+
+/// Computes the checksum of the stored form of an entry with a version 2
+/// header of `header_len` bytes: the hash of everything but the checksum.
+fn checksum(stored: &[u8], header_len: usize) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&stored[..header_len - CHECKSUM_LEN]);
+    hasher.update(&stored[header_len..]);
+    *hasher.finalize().as_bytes()
+}
+
+/// A decoded entry
+pub(crate) struct Decoded<'a> {
+    /// The value
+    pub(crate) data: Cow<'a, [u8]>,
+    /// What else decoding learned about the entry
+    pub(crate) stamp: Stamp,
+}
+
+/// What decoding an entry learned about it besides its value
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Stamp {
+    /// Whether the entry was encrypted with a previous key
+    pub(crate) stale_key: bool,
+    /// When the entry expires according to its header, in seconds since the
+    /// Unix epoch, if ever or if not recorded
+    pub(crate) expires: Option<u64>,
+    /// Checksum recorded in the entry's header, if any
+    pub(crate) checksum: Option<[u8; 32]>,
+}
+
+impl Stamp {
+    /// Returns the metadata recorded in the entry's header: its expiry,
+    /// without a validator.
+    pub(crate) fn info(&self) -> EntryInfo {
+        EntryInfo {
+            validator: None,
+            fresh_until: self.expires,
+        }
+    }
+}
+
+/// Encodes a value as [`FsCache::put`](crate::fs::FsCache::put) stores it,
+/// with a version 2 header.
+///
+/// # Parameters
+/// * `data`: The value
+/// * `key`: The cache key of the entry
+/// * `expires`: When the entry expires, in seconds since the Unix epoch, if ever
+/// * `compression`: How to compress the value, if at all
+/// * `keyring`: The keys to encrypt with, if encrypted
+pub(crate) fn encode_entry(
+    data: &[u8],
+    key: &str,
+    expires: Option<u64>,
+    compression: Option<&Compression>,
+    keyring: Option<&Keyring>,
+) -> std::io::Result<Vec<u8>> {
+    let key_len = u16::try_from(key.len()).map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "Cache key is too long")
+    })?;
+    let compressed = compress(data, compression)?;
+
+    let (codec, payload) = match &compressed {
+        Some((codec, compressed)) => (Some(*codec), &compressed[..]),
+        None => (None, data),
+    };
+
+    let mut stored = Vec::with_capacity(STAMPED_HEADER_LEN + key.len() + payload.len());
+    stored.extend_from_slice(MAGIC);
+    stored.push(VERSION);
+    stored.push(Codec::byte(codec) | if keyring.is_some() { ENCRYPTED } else { 0 });
+    stored.extend_from_slice(&expires.unwrap_or(0).to_le_bytes());
+    stored.extend_from_slice(&key_len.to_le_bytes());
+    stored.extend_from_slice(key.as_bytes());
+
+    // The header without its checksum is authenticated along with the payload.
+    let sealed = keyring
+        .map(|keyring| keyring.seal(key, &stored, payload))
+        .transpose()?;
+
+    let header_len = stored.len() + CHECKSUM_LEN;
+    stored.resize(header_len, 0);
+    stored.extend_from_slice(sealed.as_deref().unwrap_or(payload));
+
+    let checksum = checksum(&stored, header_len);
+    stored[header_len - CHECKSUM_LEN..header_len].copy_from_slice(&checksum);
+
+    Ok(stored)
+}
+
+/// Encodes a value with a version 1 header if it is compressed or resembles
+/// a header, or as is otherwise.
+pub(crate) fn encode_legacy<'a>(
+    data: &'a [u8],
+    compression: Option<&Compression>,
+) -> std::io::Result<Cow<'a, [u8]>> {
+    let Some((codec, compressed)) = compress(data, compression)? else {
+        if !data.starts_with(MAGIC) {
+            return Ok(Cow::Borrowed(data));
+        }
+
+        return Ok(Cow::Owned(
+            [&MAGIC[..], &[LEGACY_VERSION, Codec::byte(None)], data].concat(),
+        ));
+    };
+
+    Ok(Cow::Owned(
+        [
+            &MAGIC[..],
+            &[LEGACY_VERSION, Codec::byte(Some(codec))],
+            &compressed,
+        ]
+        .concat(),
+    ))
+}
+
+/// Compresses a value, if it is worth it.
+fn compress(
+    data: &[u8],
+    compression: Option<&Compression>,
+) -> std::io::Result<Option<(Codec, Vec<u8>)>> {
+    match compression {
+        Some(compression) => Ok(compression
+            .compress(data)?
+            .map(|compressed| (compression.codec, compressed))),
+        None => Ok(None),
+    }
+}
+
+/// Decodes the stored form of an entry into its value.
+///
+/// # Parameters
+/// * `stored`: The stored form of the entry
+/// * `key`: The cache key the entry is read for, if known
+/// * `keyring`: The keys to decrypt with, if any
+///
+/// # Returns
+/// * `Ok(Decoded)`: The value
+/// * `Err(std::io::Error)`: Of kind [`std::io::ErrorKind::InvalidData`] if the
+///   entry is damaged, does not match its checksum or was written for another
+///   key, or [`std::io::ErrorKind::PermissionDenied`] if it is encrypted with
+///   a key that is not available
+pub(crate) fn decode<'a>(
+    stored: &'a [u8],
+    key: Option<&str>,
+    keyring: Option<&Keyring>,
+) -> std::io::Result<Decoded<'a>> {
+    let Some((header, header_len)) = parse(stored)? else {
+        return Ok(Decoded {
+            data: Cow::Borrowed(stored),
+            stamp: Stamp::default(),
+        });
+    };
+
+    if let (Some(key), Some(written_for)) = (key, &header.key)
+        && key != written_for
+    {
+        return Err(invalid("Cache entry was written for another key"));
+    }
+
+    if let Some(recorded) = header.checksum
+        && checksum(stored, header_len) != recorded
+    {
+        return Err(invalid("Cache entry does not match its checksum"));
+    }
+
+    let (prefix, payload) = stored.split_at(header_len);
+    let prefix = match header.checksum {
+        Some(_) => &prefix[..header_len - CHECKSUM_LEN],
+        None => prefix,
+    };
+
+    let (payload, stale_key) = if header.encrypted {
+        let (Some(keyring), Some(key)) = (keyring, key.or(header.key.as_deref())) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Cache entry is encrypted",
+            ));
+        };
+        let (plain, current) = keyring.open(key, prefix, payload)?;
+
+        (Cow::Owned(plain), !current)
+    } else {
+        (Cow::Borrowed(payload), false)
+    };
+
+    let data = match header.codec {
+        None => payload,
+        Some(codec) => Cow::Owned(compression::decompress(codec, &payload)?),
+    };
+
+    Ok(Decoded {
+        data,
+        stamp: Stamp {
+            stale_key,
+            expires: header.expires,
+            checksum: header.checksum,
+        },
+    })
+}
+
+/// Decodes the stored form of an entry into its value, reusing its buffer.
+///
+/// # Returns
+/// The value, and what else decoding learned about the entry
+pub(crate) fn decode_vec(
+    mut stored: Vec<u8>,
+    key: Option<&str>,
+    keyring: Option<&Keyring>,
+) -> std::io::Result<(Vec<u8>, Stamp)> {
+    let decoded = decode(&stored, key, keyring)?;
+    let stamp = decoded.stamp;
+
+    let offset = match decoded.data {
+        Cow::Owned(data) => return Ok((data, stamp)),
+        Cow::Borrowed(data) => stored.len() - data.len(),
+    };
+
+    stored.drain(..offset);
+    Ok((stored, stamp))
+}
+
+/// Builds an error describing an entry that cannot be decoded.
+pub(crate) fn invalid(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_resembling_a_header() {
+        let value = [&MAGIC[..], b"not a header"].concat();
+
+        let stored = encode_legacy(&value, None).unwrap();
+        assert_eq!(stored.len(), value.len() + HEADER_LEN);
+        assert_eq!(decode(&stored, None, None).unwrap().data, &value[..]);
+
+        assert_eq!(
+            decode(&value, None, None).err().unwrap().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_entry_header() {
+        let stored = encode_entry(b"Hello", "key1", Some(42), None, None).unwrap();
+
+        let header = read_header(&stored).unwrap().unwrap();
+        assert_eq!(header.version, VERSION);
+        assert_eq!(header.codec, None);
+        assert!(!header.encrypted);
+        assert_eq!(header.expires, Some(42));
+        assert_eq!(header.key.as_deref(), Some("key1"));
+
+        assert_eq!(
+            decode(&stored, Some("key1"), None).unwrap().data,
+            &b"Hello"[..]
+        );
+        assert_eq!(
+            decode(&stored, Some("key2"), None).err().unwrap().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+
+        // Damage anywhere in the entry fails its checksum.
+        for position in [HEADER_LEN, stored.len() - 1] {
+            let mut damaged = stored.clone();
+            damaged[position] ^= 1;
+            assert_eq!(
+                decode(&damaged, Some("key1"), None).err().unwrap().kind(),
+                std::io::ErrorKind::InvalidData
+            );
+        }
+        assert_eq!(
+            decode(&stored[..stored.len() - 7], None, None)
+                .err()
+                .unwrap()
+                .kind(),
+            std::io::ErrorKind::InvalidData
+        );
+
+        // Headerless files are read as they are.
+        assert_eq!(read_header(b"Hello").unwrap(), None);
+        assert_eq!(
+            decode(b"Hello", Some("key1"), None).unwrap().data,
+            &b"Hello"[..]
+        );
+    }
+}
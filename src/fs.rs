@@ -11,33 +11,218 @@
 //! The module uses a marker type pattern to distinguish between these access modes
 //! at compile time, ensuring that operations are only performed when appropriate.
 
-use crate::{error::CacheableError, result::Result};
+use crate::{
+    checksum::{self, Check, CorruptEntry, CorruptEntryHandler, CorruptionReports, SUM_EXTENSION},
+    compression::Compression,
+    encryption::Keyring,
+    error::CacheableError,
+    eviction::{self, EvictionPolicy},
+    format::{self, Stamp},
+    index::Index,
+    layout::Layout,
+    lock::{self, LockOwner, lock_key},
+    mapped::{DEFAULT_MMAP_THRESHOLD, EntryBytes},
+    meta::{EntryInfo, EntryVersion},
+    pack::{self, Backend, PACK_DIR, PackStore},
+    result::Result,
+    runtime,
+};
 use fs2::FileExt;
-use nix::sys::resource::{getrlimit, Resource};
+use serde::{Deserialize, Serialize};
 use std::{
-    io::Write,
+    io::{Read as _, Write},
     os::unix::fs::DirBuilderExt,
-    path::{Component, PathBuf},
+    path::{Component, Path, PathBuf},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
 };
 
+/// How long a filesystem operation waits for its per-key lock unless the
+/// caller sets its own deadline (see [`CacheOptions::deadline`](crate::options::CacheOptions::deadline))
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
 // Constants for file operations
-const LOCK_RETRY_TIMEOUT: u64 = 5;
-const WRITE_LOCK_COUNT: usize = 2;
+pub(crate) const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Extension appended to a key to name its per-key lock file
+pub(crate) const LOCK_EXTENSION: &str = "lock";
+/// Extension appended to a key to name its in-flight temporary file
+pub(crate) const TMP_EXTENSION: &str = "tmp";
+/// Extension appended to a key to name its freshness metadata file
+pub(crate) const META_EXTENSION: &str = "meta";
+
+/// Value of [`ReadWrite::max_bytes`] for a cache without a byte limit
+const NO_BYTE_LIMIT: u64 = u64::MAX;
+/// Value of [`FsCache::mmap_threshold`] for a cache that never maps entries
+const NO_MMAP: u64 = u64::MAX;
 
 /// Marker type for read-only filesystem operations.
 ///
 /// This is used for cache layers that should only read pre-existing data,
-/// such as the sideload cache.
-pub struct Read(());
+/// such as the sideload cache. Such a cache never writes or evicts entries,
+/// but reports the number of items it was configured with in its
+/// [`FsStats`]. Clones of a cache share that number.
+#[derive(Clone)]
+pub struct Read {
+    /// Number of items the directory was configured with, if any
+    items: Arc<Mutex<Option<usize>>>,
+}
 
 /// Marker type for read-write filesystem operations with capacity limit.
 ///
 /// This is used for cache layers that need to both read and write data,
-/// such as the disk cache. It includes a limit on the number of items
-/// to enforce cache size constraints.
+/// such as the disk cache. It includes a limit on the number of items, and
+/// optionally on their total size, to enforce cache size constraints. Clones
+/// of a cache share the limits, the eviction policy, the compression, the
+/// encryption keys and the directory's index, so [`FsCache::set_limit`],
+/// [`FsCache::set_max_bytes`], [`FsCache::set_eviction`],
+/// [`FsCache::set_compression`] and [`FsCache::set_encryption`] apply to all of them.
+/// A cache opened with [`FsCache::new_pack`] keeps its entries in a
+/// [pack store](crate::pack) instead of a file per entry.
+#[derive(Clone)]
 pub struct ReadWrite {
     /// Maximum number of items to store in this cache
-    _limit: usize,
+    _limit: Arc<AtomicUsize>,
+    /// Maximum total size of the stored items in bytes, [`NO_BYTE_LIMIT`] if unbounded
+    max_bytes: Arc<AtomicU64>,
+    /// Sizes and access statistics of the stored items, unless they are in a
+    /// pack store, which keeps its own
+    index: Option<Arc<Mutex<Index>>>,
+    /// What to remove when a new entry would exceed the limit
+    eviction: Arc<Mutex<EvictionPolicy>>,
+    /// How new entries are compressed, if at all
+    compression: Arc<Mutex<Option<Compression>>>,
+    /// The keys entries are encrypted with, if at all
+    encryption: Arc<Mutex<Option<Arc<Keyring>>>>,
+    /// The segments holding the entries, if the cache is a pack store
+    pack: Option<Arc<PackStore>>,
+}
+
+mod sealed {
+    use std::sync::Arc;
+
+    use crate::encryption::Keyring;
+
+    /// Bookkeeping that depends on the access mode of a cache.
+    pub trait Sealed: Clone + Send + Sync + 'static {
+        /// Called after the entry for `key` was read.
+        fn entry_read(&self, key: &str);
+
+        /// Returns the keys entries are decrypted with, if any.
+        fn keyring(&self) -> Option<Arc<Keyring>>;
+
+        /// Returns `cache` if it can write entries.
+        fn writable(cache: &super::FsCache<Self>) -> Option<&super::FsCache<super::ReadWrite>>;
+
+        /// Returns the configured limits of `cache`, if any.
+        fn limits(cache: &super::FsCache<Self>) -> Option<super::FsLimits>;
+    }
+}
+
+/// What [`FsCache::read_checked`] does besides reading an entry
+#[derive(Clone, Copy)]
+struct ReadMode {
+    /// Map the entry into memory if it holds at least [`FsCache::mmap_threshold`] bytes
+    mapped: bool,
+    /// Also read the entry's metadata
+    with_info: bool,
+    /// Record the read for eviction
+    track: bool,
+}
+
+/// Outcome of handling an entry that failed its checksum
+enum Recheck {
+    /// The entry was being replaced; read again under its lock, it is
+    /// intact, with what decoding learned about it
+    Intact(Vec<u8>, Stamp),
+    /// The entry is corrupt, and was moved to the given path if any
+    Corrupt(Option<PathBuf>),
+    /// The entry could not be read again in time
+    Unknown,
+}
+
+/// Access mode of an [`FsCache`]: either [`Read`] or [`ReadWrite`].
+///
+/// This trait is sealed and cannot be implemented outside this crate.
+pub trait AccessMode: sealed::Sealed {}
+
+impl sealed::Sealed for Read {
+    fn entry_read(&self, _key: &str) {}
+
+    fn keyring(&self) -> Option<Arc<Keyring>> {
+        None
+    }
+
+    fn writable(_cache: &FsCache<Self>) -> Option<&FsCache<ReadWrite>> {
+        None
+    }
+
+    fn limits(cache: &FsCache<Self>) -> Option<FsLimits> {
+        cache.configured_items().map(|max_entries| FsLimits {
+            max_entries,
+            max_bytes: None,
+            eviction: EvictionPolicy::Reject,
+        })
+    }
+}
+
+impl AccessMode for Read {}
+
+impl sealed::Sealed for ReadWrite {
+    fn entry_read(&self, key: &str) {
+        // Tracking is best effort: a lost access only affects which entry is evicted later.
+        if self.eviction().tracks_access()
+            && let Ok(mut index) = self.index()
+        {
+            let _ = index.hit(key);
+        }
+    }
+
+    fn keyring(&self) -> Option<Arc<Keyring>> {
+        ReadWrite::keyring(self)
+    }
+
+    fn writable(cache: &FsCache<Self>) -> Option<&FsCache<ReadWrite>> {
+        Some(cache)
+    }
+
+    fn limits(cache: &FsCache<Self>) -> Option<FsLimits> {
+        Some(FsLimits {
+            max_entries: cache.limit(),
+            max_bytes: cache.max_bytes(),
+            eviction: cache.eviction(),
+        })
+    }
+}
+
+impl AccessMode for ReadWrite {}
+
+impl ReadWrite {
+    fn eviction(&self) -> EvictionPolicy {
+        *self.eviction.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn compression(&self) -> Option<Compression> {
+        *self.compression.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn keyring(&self) -> Option<Arc<Keyring>> {
+        self.encryption
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Locks the directory's index, which only caches storing entries as files have.
+    fn index(&self) -> std::io::Result<MutexGuard<'_, Index>> {
+        match &self.index {
+            Some(index) => Ok(index.lock().unwrap_or_else(|e| e.into_inner())),
+            None => Err(std::io::Error::other("Pack stores keep no directory index")),
+        }
+    }
 }
 
 /// File system cache representation.
@@ -49,10 +234,93 @@ pub struct ReadWrite {
 pub struct FsCache<T> {
     /// Path to the directory containing cached items
     path: PathBuf,
+    /// Where the items are stored within the directory
+    layout: Layout,
+    /// Entries found not to match their checksum, shared by clones of the cache
+    corruption: Arc<CorruptionReports>,
+    /// Size in bytes from which entries are memory-mapped, shared by clones of the cache
+    mmap_threshold: Arc<AtomicU64>,
+    /// Number of blocking operations still running, shared by clones of the cache
+    operations: Arc<AtomicUsize>,
     /// Type marker that determines available operations
     _kind: T,
 }
 
+/// A blocking operation of an [`FsCache`], counted as in flight while it lives
+struct Operation(Arc<AtomicUsize>);
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Metadata describing a single entry stored in an [`FsCache`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsEntry {
+    /// The complete cache key of the entry
+    pub key: String,
+    /// Size of the entry in its [stored form](crate::format), in bytes
+    pub size: u64,
+    /// Time the entry was last written
+    pub modified: std::time::SystemTime,
+}
+
+/// What [`FsCache::repair`] found and changed in a cache directory.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Orphaned temporary, lock, metadata and checksum files that were removed
+    pub removed: Vec<PathBuf>,
+    /// Entries that did not match their checksum and were quarantined
+    pub quarantined: Vec<CorruptEntry>,
+    /// Keys that were skipped because another operation held their lock
+    pub busy: Vec<String>,
+    /// Number of entries in the rebuilt index
+    pub entries: usize,
+}
+
+impl RepairReport {
+    /// Returns `true` if the repair removed or quarantined any file.
+    pub fn changed(&self) -> bool {
+        !self.removed.is_empty() || !self.quarantined.is_empty()
+    }
+}
+
+/// Size and capacity of an [`FsCache`], as reported by [`FsCache::stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsStats {
+    /// Number of entries
+    pub entries: usize,
+    /// Total size of the entries in their [stored form](crate::format), in bytes
+    pub bytes: u64,
+    /// The entry written longest ago, if any
+    pub oldest: Option<FsEntry>,
+    /// The entry written most recently, if any
+    pub newest: Option<FsEntry>,
+    /// Temporary files left by interrupted writes, which [`FsCache::repair`] removes
+    pub orphaned_tmp_files: usize,
+    /// Total size of the orphaned temporary files, in bytes
+    pub orphaned_tmp_bytes: u64,
+    /// The limits of a writable cache, or the number of items a read-only one
+    /// was configured with; `None` for a read-only cache configured without one
+    pub limits: Option<FsLimits>,
+}
+
+/// The configured limits of an [`FsCache`].
+///
+/// A read-only cache only reports the number of items it was configured
+/// with, which it does not enforce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsLimits {
+    /// Maximum number of entries, see [`FsCache::limit`]
+    pub max_entries: usize,
+    /// Maximum total size of the entries in bytes, see [`FsCache::max_bytes`]
+    pub max_bytes: Option<u64>,
+    /// What is removed when a new entry would exceed a limit;
+    /// [`EvictionPolicy::Reject`] for a read-only cache, which never writes
+    pub eviction: EvictionPolicy,
+}
+
 /// The UnlockGuard ensures files are unlocked when they fall out of scope.
 ///
 /// This guard uses RAII (Resource Acquisition Is Initialization) pattern to guarantee
@@ -60,7 +328,7 @@ pub struct FsCache<T> {
 /// the current scope. It's a safety mechanism that prevents lock leaks.
 ///
 /// # Example
-/// ```rust,no_run
+/// ```rust,ignore
 /// use fs2::FileExt;
 /// use std::fs::File;
 ///
@@ -78,12 +346,84 @@ impl Drop for UnlockGuard<'_> {
     }
 }
 
-impl<T> FsCache<T> {
+impl<T: AccessMode> FsCache<T> {
+    /// Returns the directory containing the cached items.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns how the cached items are arranged in the directory.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Returns the cache as a writable one, and its store, if it keeps its
+    /// entries in a [pack store](crate::pack).
+    fn packed(&self) -> Option<(&FsCache<ReadWrite>, &Arc<PackStore>)> {
+        let cache = T::writable(self)?;
+
+        Some((cache, cache._kind.pack.as_ref()?))
+    }
+
+    /// Runs `f` on the blocking thread pool like [`runtime::unblock`],
+    /// counting it as in flight until it finishes.
+    fn unblock<F, R>(&self, f: F) -> impl Future<Output = std::io::Result<R>> + use<F, R, T>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let operation = self.operation();
+        runtime::unblock(move || {
+            let _operation = operation;
+            f()
+        })
+    }
+
+    /// Runs `f` on the blocking thread pool like [`runtime::unblock_until`],
+    /// counting it as in flight until it finishes, even if the caller stops
+    /// waiting for it at `deadline` or is cancelled.
+    fn unblock_until<F, R>(
+        &self,
+        deadline: Instant,
+        f: F,
+    ) -> impl Future<Output = std::io::Result<R>> + use<F, R, T>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let operation = self.operation();
+        runtime::unblock_until(deadline, move || {
+            let _operation = operation;
+            f()
+        })
+    }
+
+    /// Counts an operation as in flight until the returned guard is dropped.
+    fn operation(&self) -> Operation {
+        self.operations.fetch_add(1, Ordering::AcqRel);
+        Operation(self.operations.clone())
+    }
+
+    /// Returns `true` while blocking work started by the cache or its clones
+    /// is still running, or a pack store's background compaction is.
+    pub(crate) fn is_busy(&self) -> bool {
+        self.operations.load(Ordering::Acquire) > 0
+            || self
+                .packed()
+                .is_some_and(|(_, pack)| Arc::strong_count(pack) > 1)
+    }
+
+    /// Returns the path of the entry for `key`.
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.layout.entry_path(&self.path, key)
+    }
+
     /// Retrieves data from the filesystem cache for the specified key.
     ///
     /// This method first validates the key's format and then attempts to read
     /// the associated file from disk. It uses a shared lock to ensure thread safety
-    /// during reads and includes a timeout to prevent blocking the async runtime.
+    /// during reads and gives up after [`DEFAULT_LOCK_TIMEOUT`] to prevent blocking
+    /// the async runtime.
     ///
     /// # Parameters
     /// * `key`: The unique identifier for the data to retrieve
@@ -96,47 +436,355 @@ impl<T> FsCache<T> {
     /// This method handles errors internally and returns `None` instead of propagating
     /// them, preferring graceful degradation over error propagation for cache misses.
     pub async fn get(&self, key: &str) -> Option<Vec<u8>> {
-        if validate_key(key).await.is_err() {
+        self.read(key, default_deadline()).await
+    }
+
+    /// Reads an entry, waiting for its lock no longer than `deadline`.
+    pub(crate) async fn read(&self, key: &str, deadline: Instant) -> Option<Vec<u8>> {
+        if validate_key(key).is_err() {
+            return None;
+        }
+
+        // A mapping would only be copied, so the entry is read into memory right away.
+        self.read_bytes(key, deadline, false)
+            .await
+            .map(EntryBytes::into_vec)
+    }
+
+    /// Retrieves data like [`FsCache::get`], without copying large entries.
+    ///
+    /// An entry of at least [`FsCache::mmap_threshold`] bytes that is stored
+    /// uncompressed and unencrypted is [memory-mapped](crate::mapped), and
+    /// the returned bytes borrow from the mapping. The entry's file stays
+    /// under a shared lock until they are dropped. Other entries, and entries
+    /// of a [pack store](crate::pack), are returned in memory. The methods
+    /// returning a `Vec<u8>` never map entries, as they would copy the
+    /// mapping anyway.
+    ///
+    /// # Parameters
+    /// * `key`: The unique identifier for the data to retrieve
+    ///
+    /// # Returns
+    /// * `Some(EntryBytes)`: The cached data if found
+    /// * `None`: Under the same conditions as [`FsCache::get`]
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use omnecache::fs::{FsCache, Read};
+    ///
+    /// async fn checksum(bundle: &FsCache<Read>) -> Option<u32> {
+    ///     let data = bundle.get_mapped("firmware.bin").await?;
+    ///     Some(data.iter().map(|&byte| byte as u32).sum())
+    /// }
+    /// ```
+    pub async fn get_mapped(&self, key: &str) -> Option<EntryBytes> {
+        self.read_bytes(key, default_deadline(), true).await
+    }
+
+    /// Reads an entry as [`EntryBytes`], waiting for its lock no longer than
+    /// `deadline`, and mapping it if `mapped` is set and it is large enough.
+    async fn read_bytes(&self, key: &str, deadline: Instant, mapped: bool) -> Option<EntryBytes> {
+        if validate_key(key).is_err() {
             return None;
         }
 
-        let file_path = self.path.join(key);
+        if let Some((cache, _)) = self.packed() {
+            return cache
+                .read_packed_until(key, deadline)
+                .await
+                .map(|(data, _)| data.into());
+        }
+
+        let file_path = self.entry_path(key);
 
         if !file_path.exists() {
             return None;
         }
 
+        let cache = self.clone();
+        let entry_key = key.to_string();
+
         // Use blocking task with timeout to ensure we don't block the async runtime indefinitely
-        match tokio::time::timeout(
-            std::time::Duration::from_secs(LOCK_RETRY_TIMEOUT), // 5 second timeout
-            tokio::task::spawn_blocking(move || {
-                let file = match std::fs::File::open(&file_path) {
-                    Ok(f) => f,
-                    Err(_) => return None,
+        match self
+            .unblock_until(deadline, move || {
+                let mode = ReadMode {
+                    mapped,
+                    with_info: false,
+                    track: true,
                 };
+                cache.read_checked(&entry_key, &file_path, deadline, mode)
+            })
+            .await
+        {
+            Ok(result) => result.map(|(data, _)| data),
+            Err(_) => {
+                // Timeout occurred, log the issue but don't propagate the error
+                eprintln!("Warning: Read operation timed out for key: {}", key);
+                None
+            }
+        }
+    }
+
+    /// Reads the entry for `key` at `file_path` on the calling thread,
+    /// verifies it against its [checksum](crate::checksum), decrypts and
+    /// decompresses it.
+    ///
+    /// An entry that does not match or cannot be decoded is reported and
+    /// treated as a miss, as is an entry encrypted with an unknown key. An
+    /// entry encrypted with a previous key is re-encrypted with the current one.
+    ///
+    /// # Returns
+    /// The value, and its metadata if `mode` asks for it
+    fn read_checked(
+        &self,
+        key: &str,
+        file_path: &Path,
+        deadline: Instant,
+        mode: ReadMode,
+    ) -> Option<(EntryBytes, Option<EntryInfo>)> {
+        let mmap_threshold = if mode.mapped {
+            self.mmap_threshold.load(Ordering::Relaxed)
+        } else {
+            NO_MMAP
+        };
+        let stored = read_entry(file_path, deadline, mmap_threshold)?;
+        let keyring = self._kind.keyring();
 
-                // Create the lock guard for the file-handle to protect
-                // against a failed lock.
-                let _file_guard = UnlockGuard(&file);
+        let (data, stamp) = match decode_entry(file_path, key, stored, keyring.as_deref()) {
+            Ok(decoded) => decoded,
+            Err(e) if e.kind() != std::io::ErrorKind::InvalidData => {
+                eprintln!("Warning: Cannot read cache entry for key: {}: {}", key, e);
+                return None;
+            }
+            Err(_) => {
+                let recheck = match T::writable(self) {
+                    Some(cache) => cache.recheck(key, file_path, deadline),
+                    // The sideload directory is read-only, so the entry stays where it is.
+                    None => Recheck::Corrupt(None),
+                };
 
-                // Use shared lock for reading to prevent reading during writes
-                if FileExt::lock_shared(&file).is_err() {
-                    return None;
+                match recheck {
+                    Recheck::Intact(data, stamp) => (data.into(), stamp),
+                    Recheck::Corrupt(quarantined) => {
+                        self.corruption.report(CorruptEntry {
+                            key: key.to_string(),
+                            path: file_path.to_path_buf(),
+                            quarantined,
+                        });
+                        return None;
+                    }
+                    Recheck::Unknown => return None,
                 }
+            }
+        };
 
-                std::fs::read(&file_path).ok()
-            }),
-        )
-        .await
+        // Read before re-encryption, which ties the metadata to the new entry.
+        let info = mode.with_info.then(|| read_info(file_path, &data, stamp));
+
+        if stamp.stale_key
+            && let Some(cache) = T::writable(self)
+        {
+            cache.reencrypt(key, file_path, &data, stamp, deadline);
+        }
+
+        if mode.track {
+            self._kind.entry_read(key);
+        }
+        Some((data, info))
+    }
+
+    /// Returns the size in bytes from which entries read with
+    /// [`FsCache::get_mapped`] are [memory-mapped](crate::mapped) rather than
+    /// copied, or `None` if they are always copied.
+    pub fn mmap_threshold(&self) -> Option<u64> {
+        match self.mmap_threshold.load(Ordering::Relaxed) {
+            NO_MMAP => None,
+            threshold => Some(threshold),
+        }
+    }
+
+    /// Changes the size in bytes from which entries read with
+    /// [`FsCache::get_mapped`] are memory-mapped.
+    ///
+    /// Defaults to [`DEFAULT_MMAP_THRESHOLD`]. Clones of the cache share the threshold.
+    ///
+    /// # Parameters
+    /// * `threshold`: The smallest size to map, or `None` to always copy entries
+    pub fn set_mmap_threshold(&self, threshold: Option<u64>) {
+        self.mmap_threshold
+            .store(threshold.unwrap_or(NO_MMAP), Ordering::Relaxed);
+    }
+
+    /// Returns the number of entries found not to match their
+    /// [checksum](crate::checksum) since the cache was created.
+    ///
+    /// Clones of the cache share the count.
+    pub fn corrupt_entries(&self) -> u64 {
+        self.corruption.count()
+    }
+
+    /// Sets the handler receiving every entry found not to match its
+    /// [checksum](crate::checksum), replacing the default warning on standard error.
+    ///
+    /// Clones of the cache share the handler.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use std::sync::Arc;
+    /// use omnecache::fs::{FsCache, ReadWrite};
+    ///
+    /// async fn watch(cache: &FsCache<ReadWrite>) {
+    ///     cache.on_corrupt_entry(Arc::new(|entry| {
+    ///         eprintln!("{} moved to {:?}", entry.key, entry.quarantined);
+    ///     }));
+    /// }
+    /// ```
+    pub fn on_corrupt_entry(&self, handler: CorruptEntryHandler) {
+        self.corruption.set_handler(Some(handler));
+    }
+
+    /// Retrieves data together with its freshness metadata.
+    ///
+    /// The metadata is only returned if it was written for exactly the content
+    /// that was read; if the entry was replaced concurrently, or its metadata
+    /// is missing, the expiry recorded in the entry's [header](crate::format)
+    /// is returned instead, without a validator. An entry written without
+    /// metadata has [`EntryInfo::default`], which marks the value as stale.
+    ///
+    /// # Parameters
+    /// * `key`: The unique identifier for the data to retrieve
+    ///
+    /// # Returns
+    /// * `Some((Vec<u8>, EntryInfo))`: The cached data and its metadata if found
+    /// * `None`: Under the same conditions as [`FsCache::get`]
+    pub async fn get_with_info(&self, key: &str) -> Option<(Vec<u8>, EntryInfo)> {
+        self.read_with_info(key, default_deadline(), true).await
+    }
+
+    /// Reads an entry and its metadata, waiting for its lock no longer than
+    /// `deadline`.
+    ///
+    /// Unless `track` is set, the read is not recorded for eviction, so bulk
+    /// readers such as [snapshot exports](crate::snapshot) leave the ranking
+    /// of entries as it was.
+    pub(crate) async fn read_with_info(
+        &self,
+        key: &str,
+        deadline: Instant,
+        track: bool,
+    ) -> Option<(Vec<u8>, EntryInfo)> {
+        let cache = self.clone();
+        let entry_key = key.to_string();
+
+        match self
+            .unblock_until(deadline, move || {
+                cache.read_with_info_blocking(&entry_key, deadline, track)
+            })
+            .await
         {
-            Ok(result) => result.unwrap_or(None),
+            Ok(result) => result,
             Err(_) => {
-                // Timeout occurred, log the issue but don't propagate the error
                 eprintln!("Warning: Read operation timed out for key: {}", key);
                 None
             }
         }
     }
+
+    /// Reads an entry and its metadata on the calling thread.
+    ///
+    /// This is the synchronous counterpart of [`FsCache::get_with_info`] used by
+    /// [`BlockingOmneCache`](crate::blocking::BlockingOmneCache); it takes the same
+    /// shared lock, so both can share one directory.
+    pub(crate) fn get_blocking(
+        &self,
+        key: &str,
+        deadline: Instant,
+    ) -> Option<(Vec<u8>, EntryInfo)> {
+        self.read_with_info_blocking(key, deadline, true)
+    }
+
+    /// Reads an entry and its metadata on the calling thread, recording the
+    /// read for eviction if `track` is set.
+    fn read_with_info_blocking(
+        &self,
+        key: &str,
+        deadline: Instant,
+        track: bool,
+    ) -> Option<(Vec<u8>, EntryInfo)> {
+        validate_key(key).ok()?;
+
+        if let Some((cache, _)) = self.packed() {
+            return cache.read_packed(key, deadline, track);
+        }
+
+        let file_path = self.entry_path(key);
+        let mode = ReadMode {
+            mapped: false,
+            with_info: true,
+            track,
+        };
+        let (data, info) = self.read_checked(key, &file_path, deadline, mode)?;
+
+        Some((data.into_vec(), info.unwrap_or_default()))
+    }
+
+    /// Lists the entries currently stored in the filesystem cache.
+    ///
+    /// Internal bookkeeping files (per-key `.lock`, `.meta` and `.sum` files,
+    /// in-flight `.tmp` files and hidden files) are skipped, as are
+    /// subdirectories and names that are not valid UTF-8, since neither can be
+    /// produced by [`FsCache::put`].
+    ///
+    /// # Returns
+    /// * `Ok(Vec<FsEntry>)`: The entries found, in directory order
+    /// * `Err(std::io::Error)`: If the cache directory could not be read
+    pub async fn entries(&self) -> std::io::Result<Vec<FsEntry>> {
+        if let Some((_, pack)) = self.packed() {
+            return Ok(pack.entries());
+        }
+
+        let path = self.path.clone();
+        let layout = self.layout;
+
+        self.unblock(move || list_entries(&path, layout)).await?
+    }
+
+    /// Reports the number and size of the entries, the oldest and newest
+    /// of them, orphaned temporary files and, for a writable cache, its limits.
+    ///
+    /// Entries are counted as by [`FsCache::entries`]. A temporary file is
+    /// orphaned unless the [owner](crate::lock::LockOwner) recorded in its
+    /// key's lock file is still alive, so the files of writes in flight, in
+    /// this or any other process, are not counted. A [pack store](crate::pack)
+    /// writes no temporary files.
+    ///
+    /// # Returns
+    /// * `Ok(FsStats)`: The statistics of the cache
+    /// * `Err(std::io::Error)`: If the cache directory could not be read
+    pub async fn stats(&self) -> std::io::Result<FsStats> {
+        let entries = self.entries().await?;
+
+        let (orphaned_tmp_files, orphaned_tmp_bytes) = match self.packed() {
+            Some(_) => (0, 0),
+            None => {
+                let path = self.path.clone();
+                let layout = self.layout;
+
+                self.unblock(move || orphaned_tmp_files(&path, layout))
+                    .await??
+            }
+        };
+
+        Ok(FsStats {
+            entries: entries.len(),
+            bytes: entries.iter().map(|entry| entry.size).sum(),
+            oldest: entries.iter().min_by_key(|entry| entry.modified).cloned(),
+            newest: entries.iter().max_by_key(|entry| entry.modified).cloned(),
+            orphaned_tmp_files,
+            orphaned_tmp_bytes,
+            limits: T::limits(self),
+        })
+    }
 }
 
 impl FsCache<Read> {
@@ -157,99 +805,98 @@ impl FsCache<Read> {
         let path: PathBuf = path.into();
 
         // Use blocking task with timeout to ensure we don't block the async runtime indefinitely
-        tokio::time::timeout(
-            std::time::Duration::from_secs(LOCK_RETRY_TIMEOUT),
-            tokio::task::spawn_blocking(move || {
-                if path.exists() {
-                    let fh = std::fs::File::open(&path)?;
-
-                    let _guard = UnlockGuard(&fh);
-
-                    if FileExt::lock_shared(&fh).is_err() {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::ResourceBusy,
-                            "File could not obtain a lock.",
-                        ));
-                    }
+        runtime::unblock_until(default_deadline(), move || Self::new_read_blocking(path)).await?
+    }
 
-                    if fh.metadata()?.permissions().readonly() {
-                        Ok(Self {
-                            path,
-                            _kind: Read(()),
-                        })
-                    } else {
-                        Err(std::io::Error::new(
-                            std::io::ErrorKind::PermissionDenied,
-                            "Sideload cache must have read-only permissions",
-                        ))
-                    }
-                } else {
-                    Err(std::io::Error::new(
-                        std::io::ErrorKind::NotFound,
-                        "Path does not exist",
-                    ))
-                }
-            }),
-        )
-        .await??
+    /// Creates a new read-only filesystem cache on the calling thread.
+    ///
+    /// Performs the same checks as [`FsCache::new_read`].
+    pub(crate) fn new_read_blocking(path: PathBuf) -> std::io::Result<Self> {
+        if path.exists() {
+            let fh = std::fs::File::open(&path)?;
+
+            let _guard = UnlockGuard(&fh);
+
+            if lock_shared_until(&fh, default_deadline()).is_err() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::ResourceBusy,
+                    "File could not obtain a lock.",
+                ));
+            }
+
+            if fh.metadata()?.permissions().readonly() {
+                Ok(Self {
+                    layout: Layout::detect(&path)?,
+                    corruption: Arc::default(),
+                    mmap_threshold: Arc::new(AtomicU64::new(DEFAULT_MMAP_THRESHOLD)),
+                    operations: Arc::default(),
+                    path,
+                    _kind: Read {
+                        items: Arc::default(),
+                    },
+                })
+            } else {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "Sideload cache must have read-only permissions",
+                ))
+            }
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Path does not exist",
+            ))
+        }
+    }
+
+    /// Returns the number of items the directory was configured with, if any.
+    pub fn configured_items(&self) -> Option<usize> {
+        *self._kind.items.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Records the number of items the directory was configured with, which
+    /// is reported in [`FsStats::limits`] but not enforced.
+    pub(crate) fn set_configured_items(&self, items: Option<usize>) {
+        *self._kind.items.lock().unwrap_or_else(|e| e.into_inner()) = items;
     }
 }
 
 impl FsCache<ReadWrite> {
     /// Creates a directory for the cache with the specified permissions.
     ///
-    /// This method ensures the cache directory exists, creating it if necessary.
-    /// It uses a lock file to ensure thread safety during directory creation.
+    /// This method ensures the cache directory exists, creating it and any missing
+    /// parents if necessary. Concurrent creation by another thread or process is
+    /// treated as success.
     ///
     /// # Parameters
+    /// * `path`: The directory to create
     /// * `permissions`: The Unix permission mode to apply to the created directory
     ///
     /// # Returns
     /// * `Ok(())`: If the directory exists or was successfully created
-    /// * `Err(std::io::Error)`: If directory creation failed or if lock acquisition fails
-    ///
-    /// # Safety
-    /// This method creates a lock file, acquires an exclusive lock, creates the directory
-    /// if needed, then releases the lock and removes the lock file to clean up.
-    async fn create_dir(&self, permissions: u32) -> std::io::Result<()> {
-        if self.path.exists() {
+    /// * `Err(std::io::Error)`: If directory creation failed
+    fn create_dir(path: &Path, permissions: u32) -> std::io::Result<()> {
+        if path.exists() {
             return Ok(());
         }
 
-        let path = self.path.clone();
-
-        // Use spawn_blocking to avoid blocking the async runtime
-        tokio::task::spawn_blocking(move || {
-            let lock_path = path.join(".directory.lock");
-
-            let dir_lock_file = std::fs::File::open(&lock_path)?;
-
-            let _dir_lock_file_guard = UnlockGuard(&dir_lock_file);
-
-            FileExt::lock_exclusive(&dir_lock_file)?;
-
-            let _ = match std::fs::DirBuilder::new()
-                .recursive(true)
-                .mode(permissions)
-                .create(&path)
-            {
-                Ok(_) => Ok(()),
-                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
-                Err(e) => Err(e),
-            };
-
-            FileExt::unlock(&dir_lock_file)?;
-            std::fs::remove_file(&lock_path)?;
-
-            Ok(())
-        })
-        .await?
+        match std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(permissions)
+            .create(path)
+        {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+            Err(e) => Err(e),
+        }
     }
 
     /// Creates a new read-write filesystem cache with the specified capacity limit.
     ///
     /// This constructor initializes a filesystem-based cache that can both read and write data.
-    /// It creates the cache directory if it doesn't already exist.
+    /// It creates the cache directory if it doesn't already exist, and uses the
+    /// [layout](crate::layout) the directory is marked with, or [`Layout::Flat`]
+    /// for a new directory.
     ///
     /// # Parameters
     /// * `path`: Path to the directory that will contain cached items
@@ -270,31 +917,408 @@ impl FsCache<ReadWrite> {
     pub async fn new_write(path: impl Into<PathBuf>, limit: usize) -> std::io::Result<Self> {
         let path = path.into();
 
-        let cache = Self {
-            path: path.clone(),
-            _kind: ReadWrite { _limit: limit },
-        };
-
-        if !path.exists() {
-            cache.create_dir(0o700).await?;
-        }
-
-        Ok(cache)
+        // Use spawn_blocking to avoid blocking the async runtime
+        runtime::unblock(move || Self::new_write_blocking(path, limit, None)).await?
     }
 
-    /// Stores data in the filesystem cache with the provided key.
+    /// Creates a new read-write filesystem cache whose entries are arranged in `layout`.
     ///
-    /// This method takes a key and data, validates them, and stores the data
-    /// in the filesystem cache. It ensures thread safety during write operations
-    /// using file locks and handles file operations safely.
+    /// This behaves like [`FsCache::new_write`], and additionally marks a new
+    /// or empty directory with `layout`.
+    ///
+    /// # Parameters
+    /// * `path`: Path to the directory that will contain cached items
+    /// * `limit`: Maximum number of items that can be stored in the cache
+    /// * `layout`: How the items are arranged in the directory
+    ///
+    /// # Returns
+    /// * `Ok(FsCache<ReadWrite>)`: The created cache instance
+    /// * `Err(std::io::Error)`: If directory creation or verification failed, or
+    ///   the directory holds entries in another layout (see [`crate::layout::migrate`])
+    pub async fn new_write_with_layout(
+        path: impl Into<PathBuf>,
+        limit: usize,
+        layout: Layout,
+    ) -> std::io::Result<Self> {
+        let path = path.into();
+
+        runtime::unblock(move || Self::new_write_blocking(path, limit, Some(layout))).await?
+    }
+
+    /// Creates a new read-write filesystem cache on the calling thread.
+    ///
+    /// Performs the same steps as [`FsCache::new_write_with_layout`], or as
+    /// [`FsCache::new_write`] if `layout` is `None`.
+    pub(crate) fn new_write_blocking(
+        path: PathBuf,
+        limit: usize,
+        layout: Option<Layout>,
+    ) -> std::io::Result<Self> {
+        Self::create_dir(&path, 0o700)?;
+
+        if path.join(PACK_DIR).exists() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Cache directory holds a pack store, open it with FsCache::new_pack",
+            ));
+        }
+
+        let layout = match layout {
+            Some(layout) => {
+                layout.adopt(&path)?;
+                layout
+            }
+            None => Layout::detect(&path)?,
+        };
+
+        Self::with_store(path, limit, layout, None)
+    }
+
+    /// Creates a new read-write cache that keeps its entries in a [pack store](crate::pack).
+    ///
+    /// This behaves like [`FsCache::new_write`], but appends the entries to
+    /// segment files in the directory instead of storing a file per entry,
+    /// which suits caches of many small entries. The store is rebuilt from
+    /// its segments when the cache is opened.
+    ///
+    /// # Parameters
+    /// * `path`: Path to the directory that will contain cached items
+    /// * `limit`: Maximum number of items that can be stored in the cache
+    ///
+    /// # Returns
+    /// * `Ok(FsCache<ReadWrite>)`: The created cache instance
+    /// * `Err(std::io::Error)`: If directory creation failed, the directory
+    ///   holds entries stored as files, or another cache uses its pack store
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use omnecache::fs::{FsCache, ReadWrite};
+    ///
+    /// async fn create_cache() -> std::io::Result<FsCache<ReadWrite>> {
+    ///     FsCache::new_pack("cache_dir", 100_000).await
+    /// }
+    /// ```
+    pub async fn new_pack(path: impl Into<PathBuf>, limit: usize) -> std::io::Result<Self> {
+        let path = path.into();
+
+        runtime::unblock(move || Self::new_pack_blocking(path, limit)).await?
+    }
+
+    /// Creates a new pack store cache on the calling thread.
+    ///
+    /// Performs the same steps as [`FsCache::new_pack`].
+    pub(crate) fn new_pack_blocking(path: PathBuf, limit: usize) -> std::io::Result<Self> {
+        Self::create_dir(&path, 0o700)?;
+
+        if !list_entries(&path, Layout::detect(&path)?)?.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Cache directory holds entries stored as files",
+            ));
+        }
+
+        let pack = PackStore::open(&path)?;
+
+        Self::with_store(path, limit, Layout::Flat, Some(pack))
+    }
+
+    /// Assembles a read-write cache for the prepared directory `path`.
+    fn with_store(
+        path: PathBuf,
+        limit: usize,
+        layout: Layout,
+        pack: Option<PackStore>,
+    ) -> std::io::Result<Self> {
+        let cache = Self {
+            _kind: ReadWrite {
+                _limit: Arc::new(AtomicUsize::new(limit)),
+                max_bytes: Arc::new(AtomicU64::new(NO_BYTE_LIMIT)),
+                index: match pack {
+                    Some(_) => None,
+                    None => Some(Arc::new(Mutex::new(Index::open(&path, layout)?))),
+                },
+                eviction: Arc::new(Mutex::new(EvictionPolicy::default())),
+                compression: Arc::new(Mutex::new(None)),
+                encryption: Arc::new(Mutex::new(None)),
+                pack: pack.map(Arc::new),
+            },
+            path,
+            layout,
+            corruption: Arc::default(),
+            mmap_threshold: Arc::new(AtomicU64::new(DEFAULT_MMAP_THRESHOLD)),
+            operations: Arc::default(),
+        };
+
+        Ok(cache)
+    }
+
+    /// Returns whether the entries are stored as files or in a [pack store](crate::pack).
+    pub fn backend(&self) -> Backend {
+        match self._kind.pack {
+            Some(_) => Backend::Pack,
+            None => Backend::Files,
+        }
+    }
+
+    /// Returns the maximum number of items the cache may hold.
+    pub fn limit(&self) -> usize {
+        self._kind._limit.load(Ordering::Relaxed)
+    }
+
+    /// Changes the maximum number of items the cache may hold.
+    ///
+    /// The new limit applies to subsequent writes; entries beyond it are kept.
+    ///
+    /// # Parameters
+    /// * `limit`: Maximum number of items that can be stored in the cache
+    pub fn set_limit(&self, limit: usize) {
+        self._kind._limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Returns the maximum total size of the entries in bytes, if limited.
+    pub fn max_bytes(&self) -> Option<u64> {
+        match self._kind.max_bytes.load(Ordering::Relaxed) {
+            NO_BYTE_LIMIT => None,
+            max_bytes => Some(max_bytes),
+        }
+    }
+
+    /// Changes the maximum total size of the entries in bytes.
+    ///
+    /// Like [`FsCache::set_limit`], the new limit applies to subsequent writes.
+    ///
+    /// # Parameters
+    /// * `max_bytes`: Maximum total size of the entries, or `None` for no byte limit
+    pub fn set_max_bytes(&self, max_bytes: Option<u64>) {
+        self._kind
+            .max_bytes
+            .store(max_bytes.unwrap_or(NO_BYTE_LIMIT), Ordering::Relaxed);
+    }
+
+    /// Returns the total size of the entries in bytes.
+    ///
+    /// The size is taken from the directory's index, which includes the
+    /// writes and removals of other processes sharing the directory.
+    ///
+    /// # Returns
+    /// * `Ok(u64)`: The total size of the entries
+    /// * `Err(std::io::Error)`: If the index could not be read or rebuilt
+    pub fn used_bytes(&self) -> std::io::Result<u64> {
+        if let Some(pack) = &self._kind.pack {
+            return Ok(pack.used_bytes());
+        }
+
+        self._kind.index()?.view(|state| state.used_bytes)
+    }
+
+    /// Returns the policy applied when a new entry would exceed the limit.
+    pub fn eviction(&self) -> EvictionPolicy {
+        self._kind.eviction()
+    }
+
+    /// Changes the policy applied when a new entry would exceed the limit.
+    ///
+    /// # Parameters
+    /// * `policy`: Which entries to evict, or [`EvictionPolicy::Reject`] to fail the write
+    pub fn set_eviction(&self, policy: EvictionPolicy) {
+        *self
+            ._kind
+            .eviction
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = policy;
+    }
+
+    /// Returns how new entries are compressed, or `None` if they are stored uncompressed.
+    pub fn compression(&self) -> Option<Compression> {
+        self._kind.compression()
+    }
+
+    /// Changes how new entries are [compressed](crate::compression).
+    ///
+    /// Existing entries keep the form they were written in.
+    ///
+    /// # Parameters
+    /// * `compression`: The compression settings, or `None` to store entries uncompressed
+    pub fn set_compression(&self, compression: Option<Compression>) {
+        *self
+            ._kind
+            .compression
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = compression;
+    }
+
+    /// Returns the keys entries are encrypted with, or `None` if they are stored in plaintext.
+    pub fn encryption(&self) -> Option<Arc<Keyring>> {
+        self._kind.keyring()
+    }
+
+    /// Changes the keys entries are [encrypted](crate::encryption) with.
+    ///
+    /// New entries are encrypted with the current key of `keyring`; existing
+    /// entries encrypted with one of its previous keys are re-encrypted when
+    /// next read. Without a keyring, entries are written in plaintext, and
+    /// encrypted entries are misses.
+    ///
+    /// # Parameters
+    /// * `keyring`: The keys, or `None` to store entries in plaintext
+    pub fn set_encryption(&self, keyring: Option<Keyring>) {
+        *self
+            ._kind
+            .encryption
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = keyring.map(Arc::new);
+    }
+
+    /// Handles the entry for `key` at `file_path`, read with content not
+    /// matching its checksum or that could not be decoded, by quarantining it.
+    fn recheck(&self, key: &str, file_path: &Path, deadline: Instant) -> Recheck {
+        // Checked again under the key's lock, so an entry replaced while it
+        // was being read is not mistaken for a corrupt one.
+        let Ok(_key_lock) = lock_key(&sidecar_path(file_path, LOCK_EXTENSION), deadline) else {
+            return Recheck::Unknown;
+        };
+
+        let Ok(stored) = std::fs::read(file_path) else {
+            return Recheck::Unknown;
+        };
+
+        match decode_entry(
+            file_path,
+            key,
+            stored.into(),
+            self._kind.keyring().as_deref(),
+        ) {
+            Ok((data, stamp)) => return Recheck::Intact(data.into_vec(), stamp),
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {}
+            Err(_) => return Recheck::Unknown,
+        }
+
+        match checksum::quarantine(&self.path, file_path, key) {
+            Ok(quarantined) => {
+                if let Ok(mut index) = self._kind.index() {
+                    let _ = index.remove(key);
+                }
+                Recheck::Corrupt(Some(quarantined))
+            }
+            Err(_) => Recheck::Corrupt(None),
+        }
+    }
+
+    /// Rewrites the entry for `key` at `file_path`, holding `data` as decoded
+    /// with `stamp`, with the current encryption key, keeping its metadata.
+    ///
+    /// Re-encryption is best effort: an entry replaced meanwhile is left
+    /// alone, and on failure the entry is tried again when next read.
+    fn reencrypt(&self, key: &str, file_path: &Path, data: &[u8], stamp: Stamp, deadline: Instant) {
+        let info = stored_info(file_path, &content_version(data, stamp.checksum));
+
+        let _ = self.write_blocking(
+            key,
+            data,
+            Condition::Matches(EntryVersion::of(data)),
+            info.as_ref(),
+            deadline,
+        );
+    }
+
+    /// Reads an entry of a [pack store](crate::pack) and its metadata,
+    /// waiting no longer than `deadline`.
+    async fn read_packed_until(
+        &self,
+        key: &str,
+        deadline: Instant,
+    ) -> Option<(Vec<u8>, EntryInfo)> {
+        let cache = self.clone();
+        let entry_key = key.to_string();
+
+        match self
+            .unblock_until(deadline, move || {
+                cache.read_packed(&entry_key, deadline, true)
+            })
+            .await
+        {
+            Ok(result) => result,
+            Err(_) => {
+                eprintln!("Warning: Read operation timed out for key: {}", key);
+                None
+            }
+        }
+    }
+
+    /// Reads an entry of a [pack store](crate::pack) and its metadata on the
+    /// calling thread, and decrypts and decompresses it.
+    ///
+    /// Like an entry stored as a file, an entry that cannot be decoded is a
+    /// miss, and an entry encrypted with a previous key is re-encrypted. An
+    /// entry whose record does not match its hash is removed from the store
+    /// and reported, without a quarantined copy. The read is recorded for
+    /// eviction if `track` is set.
+    fn read_packed(
+        &self,
+        key: &str,
+        deadline: Instant,
+        track: bool,
+    ) -> Option<(Vec<u8>, EntryInfo)> {
+        let pack = self._kind.pack.as_ref()?;
+        let keyring = self._kind.keyring();
+
+        let decoded = pack
+            .get(key, track && self._kind.eviction().tracks_access())
+            .and_then(|found| {
+                found
+                    .map(|(stored, info)| {
+                        Ok((
+                            format::decode_vec(stored, Some(key), keyring.as_deref())?,
+                            info,
+                        ))
+                    })
+                    .transpose()
+            });
+
+        let ((data, stamp), info) = match decoded {
+            Ok(Some(decoded)) => decoded,
+            Ok(None) => return None,
+            Err(e) if e.kind() != std::io::ErrorKind::InvalidData => {
+                eprintln!("Warning: Cannot read cache entry for key: {}: {}", key, e);
+                return None;
+            }
+            Err(_) => {
+                let _ = pack.remove(key);
+                self.corruption.report(CorruptEntry {
+                    key: key.to_string(),
+                    path: pack.dir().to_path_buf(),
+                    quarantined: None,
+                });
+                return None;
+            }
+        };
+
+        if stamp.stale_key {
+            let _ = self.write_blocking(
+                key,
+                &data,
+                Condition::Matches(EntryVersion::of(&data)),
+                info.as_ref(),
+                deadline,
+            );
+        }
+
+        Some((data, info.unwrap_or_else(|| stamp.info())))
+    }
+
+    /// Stores data in the filesystem cache with the provided key.
+    ///
+    /// This method takes a key and data, validates them, and stores the data
+    /// in the filesystem cache. It ensures thread safety during write operations
+    /// using file locks and handles file operations safely.
     ///
     /// The method follows these steps:
     /// 1. Validate the key format and check that data is not empty
-    /// 2. Check resource limits including file descriptor count on Linux
-    /// 3. Ensure write permissions and check cache capacity limits
-    /// 4. Create temporary and lock files for thread-safe operations
-    /// 5. Use exclusive file locks to protect concurrent operations
-    /// 6. Write data to a temporary file and use atomic rename for durability
+    /// 2. Ensure write permissions and evict entries according to the
+    ///    [eviction policy](FsCache::eviction) to stay within the item and byte limits
+    /// 3. Create temporary and lock files for thread-safe operations
+    /// 4. Use exclusive file locks to protect concurrent operations
+    /// 5. Write data to a temporary file and use atomic rename for durability
     ///
     /// # Parameters
     /// * `key`: The unique identifier for the data (must be a valid filename)
@@ -308,8 +1332,9 @@ impl FsCache<ReadWrite> {
     /// This method returns an error in the following cases:
     /// - Key validation fails
     /// - The data is empty
-    /// - The cache is at capacity
-    /// - File descriptor limit is approaching (Linux only)
+    /// - The data is larger than the byte limit
+    /// - The cache is at capacity and its eviction policy is
+    ///   [`EvictionPolicy::Reject`], or no entry could be evicted
     /// - Filesystem has read-only permissions
     /// - File locking fails
     /// - File I/O operations fail
@@ -318,104 +1343,1065 @@ impl FsCache<ReadWrite> {
     /// This method includes protections against symlink attacks and path traversal.
     /// It also uses file locks to prevent race conditions during writes.
     pub async fn put(&self, key: &str, data: &[u8]) -> Result<()> {
-        validate_key(key).await?;
-        // On Linux check the file-descriptor limit to make sure that
-        #[cfg(target_os = "linux")]
+        self.write(key, data, Condition::Always, None, default_deadline())
+            .await
+            .map(|_| ())
+    }
+
+    /// Stores data together with its freshness metadata.
+    ///
+    /// This behaves like [`FsCache::put`], and additionally records `info` next
+    /// to the entry. A plain [`FsCache::put`] discards any previous metadata,
+    /// since it no longer describes the new content.
+    ///
+    /// # Parameters
+    /// * `key`: The unique identifier for the data (must be a valid filename)
+    /// * `data`: The byte data to store (must not be empty)
+    /// * `info`: Freshness and revalidation metadata of the data
+    ///
+    /// # Returns
+    /// * `Ok(())`: If the data and metadata were successfully stored
+    /// * `Err(CacheableError)`: Under the same conditions as [`FsCache::put`]
+    pub async fn put_with_info(&self, key: &str, data: &[u8], info: &EntryInfo) -> Result<()> {
+        self.write(key, data, Condition::Always, Some(info), default_deadline())
+            .await
+            .map(|_| ())
+    }
+
+    /// Replaces the freshness metadata of an existing entry without rewriting it.
+    ///
+    /// The metadata is written under the key's exclusive lock with the same
+    /// temporary-file-then-rename sequence as entries.
+    ///
+    /// # Parameters
+    /// * `key`: The unique identifier of the entry
+    /// * `info`: The new freshness and revalidation metadata
+    ///
+    /// # Returns
+    /// * `Ok(true)`: If the metadata was replaced
+    /// * `Ok(false)`: If no entry exists for the key
+    /// * `Err(CacheableError)`: If the key is invalid, the lock could not be
+    ///   acquired in time, or the metadata could not be written
+    pub async fn set_info(&self, key: &str, info: &EntryInfo) -> Result<bool> {
+        self.update_info(key, info, default_deadline()).await
+    }
+
+    /// Replaces the metadata of an entry, waiting for its lock no longer than `deadline`.
+    pub(crate) async fn update_info(
+        &self,
+        key: &str,
+        info: &EntryInfo,
+        deadline: Instant,
+    ) -> Result<bool> {
+        validate_key(key)?;
+
+        if let Some(pack) = self._kind.pack.clone() {
+            let info = info.clone();
+            let key = key.to_string();
+
+            return Ok(self
+                .unblock_until(deadline, move || pack.set_info(&key, &info))
+                .await??);
+        }
+
+        let file_path = self.entry_path(key);
+        let info = info.clone();
+        let keyring = self._kind.keyring();
+        let key = key.to_string();
+
+        Ok(self
+            .unblock_until(deadline, move || -> std::io::Result<bool> {
+                let _key_lock = match lock_key(&sidecar_path(&file_path, LOCK_EXTENSION), deadline)
+                {
+                    Ok(key_lock) => key_lock,
+                    // A missing shard directory holds no entry.
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+                    Err(e) => return Err(e),
+                };
+
+                let (data, stamp) = match std::fs::read(&file_path) {
+                    Ok(stored) => format::decode_vec(stored, Some(&key), keyring.as_deref())?,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+                    Err(e) => return Err(e),
+                };
+
+                write_info(&file_path, &content_version(&data, stamp.checksum), &info)?;
+                Ok(true)
+            })
+            .await??)
+    }
+
+    /// Stores data under the provided key only if no entry exists for it.
+    ///
+    /// The existence check and the write both happen while holding the key's
+    /// exclusive lock, so of several concurrent callers (in this or any other
+    /// process sharing the directory) exactly one succeeds.
+    ///
+    /// # Parameters
+    /// * `key`: The unique identifier for the data (must be a valid filename)
+    /// * `data`: The byte data to store (must not be empty)
+    ///
+    /// # Returns
+    /// * `Ok(true)`: If the data was stored
+    /// * `Ok(false)`: If an entry already existed and nothing was written
+    /// * `Err(CacheableError)`: Under the same conditions as [`FsCache::put`]
+    pub async fn put_if_absent(&self, key: &str, data: &[u8]) -> Result<bool> {
+        self.write(key, data, Condition::Absent, None, default_deadline())
+            .await
+    }
+
+    /// Replaces the entry under the provided key only if its current content
+    /// has the expected version.
+    ///
+    /// The current content is read and compared while holding the key's
+    /// exclusive lock, so no other writer can change it between the comparison
+    /// and the replacement.
+    ///
+    /// # Parameters
+    /// * `key`: The unique identifier for the data (must be a valid filename)
+    /// * `expected`: The version the current entry must have
+    /// * `data`: The byte data to store (must not be empty)
+    ///
+    /// # Returns
+    /// * `Ok(true)`: If the entry matched and was replaced
+    /// * `Ok(false)`: If the entry is missing or has a different version
+    /// * `Err(CacheableError)`: Under the same conditions as [`FsCache::put`]
+    pub async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &EntryVersion,
+        data: &[u8],
+    ) -> Result<bool> {
+        self.write(
+            key,
+            data,
+            Condition::Matches(*expected),
+            None,
+            default_deadline(),
+        )
+        .await
+    }
+
+    /// Shared implementation of the write operations.
+    ///
+    /// Performs the checks documented on [`FsCache::put`], then evaluates the
+    /// condition and writes the entry under the key's exclusive lock. Existing
+    /// metadata is removed before the entry is replaced, and `info` (if any) is
+    /// written afterwards, so metadata never describes content it was not
+    /// written for. The key's lock is awaited no longer than `deadline`.
+    ///
+    /// # Returns
+    /// * `Ok(true)`: If the condition held and the data was written
+    /// * `Ok(false)`: If the condition did not hold
+    pub(crate) async fn write(
+        &self,
+        key: &str,
+        data: &[u8],
+        condition: Condition,
+        info: Option<&EntryInfo>,
+        deadline: Instant,
+    ) -> Result<bool> {
+        validate_key(key)?;
+
+        let cache = self.clone();
+        let key = key.to_string();
+        let data = data.to_vec();
+        let info = info.cloned();
+
+        self.unblock_until(deadline, move || {
+            cache.write_blocking(&key, &data, condition, info.as_ref(), deadline)
+        })
+        .await?
+    }
+
+    /// Synchronous implementation of [`FsCache::write`], run on the calling thread.
+    ///
+    /// Used directly by [`BlockingOmneCache`](crate::blocking::BlockingOmneCache)
+    /// and through `spawn_blocking` by the async API, so both follow the same
+    /// locking protocol and on-disk format.
+    pub(crate) fn write_blocking(
+        &self,
+        key: &str,
+        data: &[u8],
+        condition: Condition,
+        info: Option<&EntryInfo>,
+        deadline: Instant,
+    ) -> Result<bool> {
+        validate_key(key)?;
+
+        if data.is_empty() {
+            return Err(CacheableError::EmptyBuffer);
+        }
+
+        if !self.path.exists() {
+            Self::create_dir(&self.path, 0o700)?;
+        } else if std::fs::metadata(&self.path)?.permissions().readonly() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::ReadOnlyFilesystem,
+                "Incorrect permissions for the disk cache.",
+            ))?;
+        }
+
+        let keyring = self._kind.keyring();
+        let stored = format::encode_entry(
+            data,
+            key,
+            info.and_then(|info| info.fresh_until),
+            self._kind.compression().as_ref(),
+            keyring.as_deref(),
+        )?;
+
+        if let Some(pack) = &self._kind.pack {
+            let limits = pack::Limits {
+                items: self.limit(),
+                max_bytes: self.max_bytes(),
+                policy: self.eviction(),
+            };
+
+            return Ok(pack.put(key, &stored, info, condition, keyring.as_deref(), limits)?);
+        }
+
+        let file_path = self.entry_path(key);
+
+        if let Some(shard) = file_path.parent()
+            && shard != self.path
         {
-            const FD_LIMIT_BUFFER: u64 = 10;
-            let (soft_limit, _) = getrlimit(Resource::RLIMIT_NOFILE)?;
+            Self::create_dir(shard, 0o700)?;
+        }
+
+        let key_lock_path = sidecar_path(&file_path, LOCK_EXTENSION);
+        let tmp_path = sidecar_path(&file_path, TMP_EXTENSION);
+
+        if tmp_path.exists() {
+            let metadata = tmp_path.metadata()?;
+
+            if metadata.is_symlink() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "Potential symlink attack detected",
+                ))?;
+            }
+
+            if metadata.permissions().readonly() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "Cannot write to the path provided. Invalid permissions.",
+                ))?;
+            }
+        }
+
+        let _key_lock = lock_key(&key_lock_path, deadline)?;
+
+        if !condition.holds(&file_path, key, keyring.as_deref())? {
+            return Ok(false);
+        }
+
+        // Only a write that will happen may evict, and under the key's lock
+        // no other write of the key can change the room it needs meanwhile.
+        self.make_room(key, stored.len() as u64)?;
+
+        let tmp_file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&tmp_path)?;
+        let _tmp_file_guard = UnlockGuard(&tmp_file);
+
+        FileExt::lock_exclusive(&tmp_file)?;
+
+        let modified = (|| -> std::io::Result<std::time::SystemTime> {
+            let mut writer = std::io::BufWriter::new(&tmp_file);
+            writer.write_all(&stored)?;
+            writer.flush()?;
+            tmp_file.sync_all()?;
+            tmp_file.metadata()?.modified()
+        })()?;
+
+        // The entry carries its own checksum; one left by a headerless
+        // entry it replaces would no longer match.
+        remove_if_exists(&sidecar_path(&file_path, META_EXTENSION))?;
+        remove_if_exists(&sidecar_path(&file_path, SUM_EXTENSION))?;
+        std::fs::rename(&tmp_path, &file_path)?;
+
+        // Still under the key's lock, so the index records writes of a key in order.
+        self._kind
+            .index()?
+            .put(key, stored.len() as u64, modified)?;
+
+        if let Some(info) = info {
+            let checksum = format::read_header(&stored)?.and_then(|header| header.checksum);
+            write_info(&file_path, &content_version(data, checksum), info)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Evicts entries until an entry of `size` bytes fits under `key` within
+    /// the item and byte limits.
+    ///
+    /// The entries and their sizes are taken from the directory's index.
+    /// Replacing an entry needs no room for another item, only for the
+    /// difference in size. Entries are removed in the order given by the
+    /// eviction policy, each under its per-key exclusive lock; the entry for
+    /// `key` itself is never evicted. Entries locked by another operation are
+    /// skipped rather than waited for.
+    ///
+    /// # Returns
+    /// * `Ok(())`: If the entry fits within the limits
+    /// * `Err(std::io::Error)`: Of kind [`std::io::ErrorKind::StorageFull`] if
+    ///   the entry is larger than the byte limit, the policy is
+    ///   [`EvictionPolicy::Reject`] or too few entries could be evicted
+    fn make_room(&self, key: &str, size: u64) -> std::io::Result<()> {
+        let limit = self.limit();
+        let max_bytes = self.max_bytes();
+        let policy = self.eviction();
+
+        if max_bytes.is_some_and(|max_bytes| size > max_bytes) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::StorageFull,
+                "Entry exceeds the cache byte limit",
+            ));
+        }
+
+        let (mut excess_items, mut excess_bytes, victims) = self._kind.index()?.view(|state| {
+            let replaced = state.entries.get(key).map(|entry| entry.size);
+
+            let excess_items = match replaced {
+                Some(_) => 0,
+                None => (state.entries.len() + 1).saturating_sub(limit),
+            };
+            let excess_bytes = max_bytes.map_or(0, |max_bytes| {
+                (state.used_bytes + size).saturating_sub(replaced.unwrap_or(0) + max_bytes)
+            });
+
+            let victims =
+                if (excess_items > 0 || excess_bytes > 0) && policy != EvictionPolicy::Reject {
+                    eviction::rank(&state.entries, policy)
+                        .into_iter()
+                        .filter(|(victim, _)| victim != key)
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+
+            (excess_items, excess_bytes, victims)
+        })?;
+
+        for (victim, victim_size) in victims {
+            if excess_items == 0 && excess_bytes == 0 {
+                break;
+            }
+
+            match self.remove_entry(&victim, Instant::now()) {
+                // An entry removed concurrently frees its place all the same.
+                Ok(_) => {
+                    excess_items = excess_items.saturating_sub(1);
+                    excess_bytes = excess_bytes.saturating_sub(victim_size);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        if excess_items > 0 || excess_bytes > 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::StorageFull,
+                "Cannot exceed cache limit",
+            ));
+        }
+
+        Ok(())
+    }
 
-            let open_fds = std::fs::read_dir("/proc/self/fd")
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
-                .count() as u64;
+    /// Removes the entry for `key`, its sidecar files and its index record
+    /// while holding the entry's per-key exclusive lock, awaited no longer
+    /// than `deadline`.
+    ///
+    /// The lock file is unlinked before it is unlocked; an operation already
+    /// waiting on it notices that it was replaced and locks a new one (see
+    /// [`crate::lock`]).
+    ///
+    /// # Returns
+    /// * `Ok(true)`: If the entry existed and was removed
+    /// * `Ok(false)`: If the entry was already gone
+    fn remove_entry(&self, key: &str, deadline: Instant) -> std::io::Result<bool> {
+        if let Some(pack) = &self._kind.pack {
+            return pack.remove(key);
+        }
+
+        let file_path = self.entry_path(key);
+        let _key_lock = match lock_key(&sidecar_path(&file_path, LOCK_EXTENSION), deadline) {
+            Ok(key_lock) => key_lock,
+            // A missing shard directory holds no entry.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self._kind.index()?.remove(key)?;
+                return Ok(false);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let removed = remove_locked(&file_path)?;
+        self._kind.index()?.remove(key)?;
+        remove_if_exists(&sidecar_path(&file_path, LOCK_EXTENSION))?;
+
+        Ok(removed)
+    }
+
+    /// Removes the entry for `key` from the filesystem cache.
+    ///
+    /// The entry is removed while holding its per-key exclusive lock, so a
+    /// concurrent [`FsCache::put`] for the same key, in this or any other
+    /// process sharing the directory, either completes before the entry is
+    /// removed or starts after it. The entry's metadata, checksum, orphaned
+    /// temporary file and lock file are removed with it.
+    ///
+    /// # Parameters
+    /// * `key`: The unique identifier of the entry
+    ///
+    /// # Returns
+    /// * `Ok(true)`: If the entry existed and was removed
+    /// * `Ok(false)`: If there was no entry for the key
+    /// * `Err(CacheableError)`: If the key is invalid, the lock could not be
+    ///   acquired in time, or the entry could not be removed
+    pub async fn remove(&self, key: &str) -> Result<bool> {
+        self.discard(key, default_deadline()).await
+    }
+
+    /// Removes an entry, waiting for its lock no longer than `deadline`.
+    pub(crate) async fn discard(&self, key: &str, deadline: Instant) -> Result<bool> {
+        let cache = self.clone();
+        let key = key.to_string();
+
+        self.unblock_until(deadline, move || cache.remove_blocking(&key, deadline))
+            .await?
+    }
+
+    /// Removes a single entry on the calling thread, under its per-key exclusive lock.
+    ///
+    /// # Returns
+    /// * `Ok(true)`: If the entry existed and was removed
+    /// * `Ok(false)`: If there was no entry for the key
+    pub(crate) fn remove_blocking(&self, key: &str, deadline: Instant) -> Result<bool> {
+        validate_key(key)?;
+
+        Ok(self.remove_entry(key, deadline)?)
+    }
+
+    /// Returns the process holding the per-key lock of `key`, as recorded in
+    /// its lock file (see [`crate::lock`]).
+    ///
+    /// # Returns
+    /// * `Ok(Some(LockOwner))`: The holder, or the last holder if it exited
+    ///   without releasing the lock
+    /// * `Ok(None)`: If the lock is not held, or the cache is a [pack
+    ///   store](crate::pack), which takes no per-key locks
+    pub fn lock_owner(&self, key: &str) -> Result<Option<LockOwner>> {
+        validate_key(key)?;
+
+        if self._kind.pack.is_some() {
+            return Ok(None);
+        }
+
+        Ok(lock::owner(&sidecar_path(
+            &self.entry_path(key),
+            LOCK_EXTENSION,
+        ))?)
+    }
+
+    /// Removes every entry from the filesystem cache.
+    ///
+    /// Each entry is removed as by [`FsCache::remove`], while holding its
+    /// per-key exclusive lock, so a concurrent [`FsCache::put`] for the same
+    /// key, in this or any other process sharing the directory, either
+    /// completes before the entry is removed or starts after it. Entries
+    /// written after the directory was listed are kept. Orphaned temporary
+    /// files left by interrupted writes are removed too, as are the per-key
+    /// lock files. A [pack store](crate::pack) deletes its segments instead.
+    ///
+    /// # Returns
+    /// * `Ok(usize)`: The number of entries removed
+    /// * `Err(CacheableError)`: If the directory could not be listed, a lock could not
+    ///   be acquired in time, or an entry could not be removed
+    pub async fn clear(&self) -> Result<usize> {
+        if let Some(pack) = self._kind.pack.clone() {
+            return Ok(self.unblock(move || pack.clear()).await??);
+        }
+
+        let mut removed = 0;
+
+        for entry in self.entries().await? {
+            let cache = self.clone();
+            let deadline = default_deadline();
+
+            let existed = self
+                .unblock_until(deadline, move || cache.remove_entry(&entry.key, deadline))
+                .await??;
+
+            if existed {
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+impl FsCache<ReadWrite> {
+    /// Checks the cache directory and removes what interrupted operations left behind.
+    ///
+    /// A crash during [`FsCache::put`] can leave temporary and lock files in
+    /// the directory. For every key, the repair:
+    ///
+    /// * removes temporary files, and lock files of keys without an entry,
+    ///   unless a live process holds the key's lock; such keys are skipped
+    ///   and listed in [`RepairReport::busy`]
+    /// * removes metadata and checksum files of keys without an entry
+    /// * verifies entries against their [checksum](crate::checksum),
+    ///   quarantining those that do not match
+    /// * removes metadata that cannot be read or was written for other content
+    ///
+    /// Afterwards the directory's index is rewritten from the remaining
+    /// entries. The repair may run while other processes use the directory.
+    ///
+    /// A [pack store](crate::pack) recovers from crashes when it is opened;
+    /// its repair compacts every segment that is at least half dead.
+    ///
+    /// # Returns
+    /// * `Ok(RepairReport)`: What was found and changed
+    /// * `Err(std::io::Error)`: If the directory could not be read or a file could not be removed
+    pub async fn repair(&self) -> std::io::Result<RepairReport> {
+        let cache = self.clone();
+
+        self.unblock(move || cache.repair_blocking()).await?
+    }
+
+    /// Synchronous implementation of [`FsCache::repair`], run on the calling thread.
+    pub(crate) fn repair_blocking(&self) -> std::io::Result<RepairReport> {
+        let mut report = RepairReport::default();
+
+        if let Some(pack) = &self._kind.pack {
+            pack.compact()?;
+            report.entries = pack.len();
+
+            return Ok(report);
+        }
+
+        for entry_dir in self.layout.entry_dirs(&self.path)? {
+            // Every key with a file in the directory, and whether it has a lock file
+            let mut keys = std::collections::BTreeMap::<String, bool>::new();
+
+            for dir_entry in std::fs::read_dir(&entry_dir)? {
+                let dir_entry = dir_entry?;
+
+                let Ok(name) = dir_entry.file_name().into_string() else {
+                    continue;
+                };
+
+                if name.starts_with('.') || dir_entry.file_type()?.is_dir() {
+                    continue;
+                }
+
+                let is_lock = name.ends_with(&format!(".{}", LOCK_EXTENSION));
+                *keys.entry(sidecar_key(&name).to_string()).or_default() |= is_lock;
+            }
+
+            for (key, had_lock) in keys {
+                self.repair_key(&entry_dir.join(&key), &key, had_lock, &mut report)?;
+            }
+        }
+
+        report.entries = self._kind.index()?.rebuild()?;
+
+        Ok(report)
+    }
+
+    /// Repairs the files of `key`, whose entry is at `file_path`, as described on [`FsCache::repair`].
+    fn repair_key(
+        &self,
+        file_path: &Path,
+        key: &str,
+        had_lock: bool,
+        report: &mut RepairReport,
+    ) -> std::io::Result<()> {
+        let lock_path = sidecar_path(file_path, LOCK_EXTENSION);
+
+        // A lock held by a live process means its files are still in use.
+        let _key_lock = match lock_key(&lock_path, Instant::now()) {
+            Ok(key_lock) => key_lock,
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                report.busy.push(key.to_string());
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut remove = |path: PathBuf| -> std::io::Result<()> {
+            match std::fs::remove_file(&path) {
+                Ok(()) => report.removed.push(path),
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                Err(_) => {}
+            }
+            Ok(())
+        };
+
+        // With the key lock held no write is in flight, so temporary files are orphaned.
+        for sidecar in [
+            file_path.to_path_buf(),
+            sidecar_path(file_path, META_EXTENSION),
+        ] {
+            remove(sidecar_path(&sidecar, TMP_EXTENSION))?;
+        }
+
+        let mut exists = std::fs::symlink_metadata(file_path).is_ok_and(|m| m.is_file());
+
+        if exists {
+            let stored = std::fs::read(file_path)?;
+
+            // An entry with a header carries its own checksum.
+            if stored.starts_with(format::MAGIC) {
+                remove(sidecar_path(file_path, SUM_EXTENSION))?;
+            }
+
+            match decode_entry(
+                file_path,
+                key,
+                stored.into(),
+                self._kind.keyring().as_deref(),
+            ) {
+                Ok((data, stamp)) => {
+                    if sidecar_path(file_path, META_EXTENSION).exists()
+                        && stored_info(file_path, &content_version(&data, stamp.checksum)).is_none()
+                    {
+                        remove(sidecar_path(file_path, META_EXTENSION))?;
+                    }
+                }
+                // Entries encrypted with an unknown key may become readable again.
+                Err(e) if e.kind() != std::io::ErrorKind::InvalidData => {}
+                Err(_) => {
+                    let corrupt = CorruptEntry {
+                        key: key.to_string(),
+                        path: file_path.to_path_buf(),
+                        quarantined: Some(checksum::quarantine(&self.path, file_path, key)?),
+                    };
+                    self.corruption.report(corrupt.clone());
+                    report.quarantined.push(corrupt);
+                    exists = false;
+                }
+            }
+        }
+
+        if !exists {
+            remove(sidecar_path(file_path, META_EXTENSION))?;
+            remove(sidecar_path(file_path, SUM_EXTENSION))?;
+        }
+
+        if !exists && had_lock {
+            remove(lock_path)?;
+        } else if !had_lock {
+            // Opened for this repair only
+            remove_if_exists(&lock_path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Rewrites the entries stored in an earlier [format](crate::format) with
+    /// the current header.
+    ///
+    /// Headerless entries and entries with a version 1 header stay readable,
+    /// but carry neither their key, expiry nor checksum. Each entry is rewritten
+    /// with the cache's current [compression](FsCache::set_compression) and
+    /// [encryption](FsCache::set_encryption) under its per-key lock, keeping
+    /// its metadata, and only if it was not
+    /// replaced meanwhile, so the migration may run while other processes use
+    /// the directory. Rewritten entries count as written at the time of the
+    /// migration. Entries that cannot be decoded are left for
+    /// [`FsCache::repair`].
+    ///
+    /// # Returns
+    /// * `Ok(usize)`: The number of entries rewritten
+    /// * `Err(CacheableError)`: If the directory could not be listed, a lock
+    ///   could not be acquired in time, or an entry could not be rewritten
+    pub async fn migrate_format(&self) -> Result<usize> {
+        let cache = self.clone();
+
+        self.unblock(move || cache.migrate_format_blocking())
+            .await?
+    }
+
+    /// Synchronous implementation of [`FsCache::migrate_format`], run on the calling thread.
+    pub(crate) fn migrate_format_blocking(&self) -> Result<usize> {
+        let keyring = self._kind.keyring();
+        let mut rewritten = 0;
+
+        for entry in list_entries(&self.path, self.layout)? {
+            let file_path = self.entry_path(&entry.key);
+
+            let stored = match std::fs::read(&file_path) {
+                Ok(stored) => stored,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e)?,
+            };
+
+            if format::read_header(&stored)
+                .is_ok_and(|header| header.is_some_and(|header| header.version == format::VERSION))
+            {
+                continue;
+            }
+
+            let Ok((data, stamp)) =
+                decode_entry(&file_path, &entry.key, stored.into(), keyring.as_deref())
+            else {
+                continue;
+            };
+
+            if self.write_blocking(
+                &entry.key,
+                &data,
+                Condition::Matches(EntryVersion::of(&data)),
+                stored_info(&file_path, &content_version(&data, stamp.checksum)).as_ref(),
+                default_deadline(),
+            )? {
+                rewritten += 1;
+            }
+        }
+
+        Ok(rewritten)
+    }
+}
+
+/// Returns the key a file in a cache directory belongs to: the name of an
+/// entry itself, or the name of the entry a sidecar file was written for.
+fn sidecar_key(name: &str) -> &str {
+    let name = name
+        .strip_suffix(&format!(".{}", TMP_EXTENSION))
+        .unwrap_or(name);
+
+    [LOCK_EXTENSION, META_EXTENSION, SUM_EXTENSION]
+        .iter()
+        .find_map(|extension| name.strip_suffix(&format!(".{}", extension)))
+        .unwrap_or(name)
+}
+
+/// Precondition evaluated under the per-key lock before a write
+#[derive(Clone, Copy)]
+pub(crate) enum Condition {
+    /// Write unconditionally
+    Always,
+    /// Write only if no entry exists
+    Absent,
+    /// Write only if the current entry has the given version
+    Matches(EntryVersion),
+}
 
-            if open_fds > soft_limit - FD_LIMIT_BUFFER {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Approaching file descriptor limit",
-                ))?;
-            }
+impl Condition {
+    /// Checks the condition against the entry for `key` at `file_path`,
+    /// decrypted with `keyring` if it is encrypted.
+    ///
+    /// Must be called while holding the entry's per-key exclusive lock.
+    fn holds(
+        &self,
+        file_path: &Path,
+        key: &str,
+        keyring: Option<&Keyring>,
+    ) -> std::io::Result<bool> {
+        match self {
+            Self::Always => Ok(true),
+            Self::Absent => Ok(!file_path.exists()),
+            Self::Matches(expected) => match std::fs::read(file_path) {
+                Ok(stored) => Ok(stores_version(&stored, key, keyring, expected)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+                Err(e) => Err(e),
+            },
         }
+    }
+}
 
-        if data.is_empty() {
-            return Err(CacheableError::EmptyBuffer);
+/// Returns `true` if `stored`, the stored form of the entry for `key`,
+/// decrypted with `keyring` if it is encrypted, holds a value of version `expected`.
+pub(crate) fn stores_version(
+    stored: &[u8],
+    key: &str,
+    keyring: Option<&Keyring>,
+    expected: &EntryVersion,
+) -> bool {
+    format::decode(stored, Some(key), keyring)
+        .is_ok_and(|current| EntryVersion::of(&current.data) == *expected)
+}
+
+/// Returns the deadline of an operation that uses [`DEFAULT_LOCK_TIMEOUT`].
+pub(crate) fn default_deadline() -> Instant {
+    Instant::now() + DEFAULT_LOCK_TIMEOUT
+}
+
+/// Locks `file` shared, retrying while a writer holds it exclusively.
+///
+/// Per-key locks are taken exclusively by [`lock_key`], which also breaks
+/// stale ones.
+///
+/// Unlike a blocking `flock`, the wait ends at `deadline`, after which an
+/// error of kind [`std::io::ErrorKind::TimedOut`] is returned. This keeps a
+/// timed-out operation from acquiring the lock (and writing) after its caller
+/// has already given up.
+fn lock_shared_until(file: &std::fs::File, deadline: Instant) -> std::io::Result<()> {
+    loop {
+        match FileExt::try_lock_shared(file) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => {}
+            Err(e) => return Err(e),
         }
 
-        if !self.path.exists() {
-            self.create_dir(0o700).await?;
-        } else if std::fs::metadata(&self.path)?.permissions().readonly() {
+        let now = Instant::now();
+        if now >= deadline {
             return Err(std::io::Error::new(
-                std::io::ErrorKind::ReadOnlyFilesystem,
-                "Incorrect permissions for the disk cache.",
-            ))?;
+                std::io::ErrorKind::TimedOut,
+                "Timed out waiting for the cache entry lock",
+            ));
         }
 
-        let file_path = self.path.join(key);
-        let data = data.to_vec();
+        std::thread::sleep(LOCK_RETRY_INTERVAL.min(deadline - now));
+    }
+}
 
-        // Make sure limit is enforced before we create the files.
-        if tokio::fs::read_dir(&self.path).await.iter().count()
-            >= self._kind._limit - WRITE_LOCK_COUNT
-            && self.get(&key).await.is_none()
-        {
-            // If not, error out. No space left.
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::StorageFull,
-                "Cannot exceed cache limit",
-            ))?;
+/// Removes an entry and any orphaned temporary file.
+///
+/// Must be called while holding the entry's per-key exclusive lock.
+///
+/// # Returns
+/// * `Ok(true)`: If the entry existed and was removed
+/// * `Ok(false)`: If the entry was already gone
+fn remove_locked(file_path: &Path) -> std::io::Result<bool> {
+    // With the key lock held no write is in flight, so a temporary file is orphaned.
+    remove_if_exists(&sidecar_path(file_path, TMP_EXTENSION))?;
+    remove_if_exists(&sidecar_path(file_path, META_EXTENSION))?;
+    remove_if_exists(&sidecar_path(file_path, SUM_EXTENSION))?;
+
+    match std::fs::remove_file(file_path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Lists the entries stored in the directory `path` in `layout`, as described on [`FsCache::entries`].
+pub(crate) fn list_entries(path: &Path, layout: Layout) -> std::io::Result<Vec<FsEntry>> {
+    let mut entries = Vec::new();
+
+    for dir_entry in layout
+        .entry_dirs(path)?
+        .iter()
+        .map(std::fs::read_dir)
+        .collect::<std::io::Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+    {
+        let dir_entry = dir_entry?;
+
+        let Ok(key) = dir_entry.file_name().into_string() else {
+            continue;
+        };
+
+        if is_internal_file(&key) {
+            continue;
         }
 
-        Ok(tokio::time::timeout(
-            std::time::Duration::from_secs(LOCK_RETRY_TIMEOUT),
-            tokio::task::spawn_blocking(move || -> std::io::Result<()> {
-                let key_lock_path = file_path.with_extension(".lock");
-                let tmp_path = file_path.with_extension(".tmp");
+        // An entry may be renamed over or removed between listing and stat.
+        let Ok(metadata) = dir_entry.metadata() else {
+            continue;
+        };
+
+        if !metadata.is_file() {
+            continue;
+        }
 
-                if tmp_path.exists() {
-                    let metadata = tmp_path.metadata()?;
+        entries.push(FsEntry {
+            key,
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        });
+    }
 
-                    if metadata.is_symlink() {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::InvalidInput,
-                            "Potential symlink attack detected",
-                        ));
-                    }
+    Ok(entries)
+}
 
-                    if metadata.permissions().readonly() {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::PermissionDenied,
-                            "Cannot write to the path provided. Invalid permissions.",
-                        ));
-                    }
-                }
+/// Counts the temporary files in the directory `path` in `layout` whose
+/// writer is gone, as described on [`FsCache::stats`].
+///
+/// # Returns
+/// * `Ok((usize, u64))`: The number of orphaned temporary files and their total size
+/// * `Err(std::io::Error)`: If the directory could not be read
+fn orphaned_tmp_files(path: &Path, layout: Layout) -> std::io::Result<(usize, u64)> {
+    let (mut files, mut bytes) = (0, 0);
 
-                let key_lock_file = std::fs::File::open(&key_lock_path)?;
-                let _key_lock_file_guard = UnlockGuard(&key_lock_file);
+    for entry_dir in layout.entry_dirs(path)? {
+        for dir_entry in std::fs::read_dir(&entry_dir)? {
+            let dir_entry = dir_entry?;
 
-                FileExt::lock_exclusive(&key_lock_file)?;
+            let Ok(name) = dir_entry.file_name().into_string() else {
+                continue;
+            };
 
-                let tmp_file = std::fs::File::open(&tmp_path)?;
-                let _tmp_file_guard = UnlockGuard(&tmp_file);
+            if name.starts_with('.') || !name.ends_with(&format!(".{}", TMP_EXTENSION)) {
+                continue;
+            }
 
-                FileExt::lock_exclusive(&tmp_file)?;
+            // A write in flight records its process in the key's lock file.
+            let lock_path = sidecar_path(&entry_dir.join(sidecar_key(&name)), LOCK_EXTENSION);
+            if lock::owner(&lock_path)?.is_some_and(|owner| owner.is_alive()) {
+                continue;
+            }
 
-                (|| -> std::io::Result<()> {
-                    let mut writer = std::io::BufWriter::new(&tmp_file);
-                    writer.write_all(&data)?;
-                    writer.flush()?;
-                    tmp_file.sync_all()?;
-                    Ok(())
-                })()?;
+            // The write may have completed and renamed the file since it was listed.
+            let Ok(metadata) = dir_entry.metadata() else {
+                continue;
+            };
 
-                std::fs::rename(&tmp_path, &file_path)?;
+            files += 1;
+            bytes += metadata.len();
+        }
+    }
 
-                Ok(())
-            }),
-        )
-        .await???)
+    Ok((files, bytes))
+}
+
+/// Reads the entry at `file_path` under a shared lock awaited no longer than
+/// `deadline`, mapping it into memory if it holds at least `mmap_threshold` bytes.
+fn read_entry(file_path: &Path, deadline: Instant, mmap_threshold: u64) -> Option<EntryBytes> {
+    let mut file = std::fs::File::open(file_path).ok()?;
+
+    // Use shared lock for reading to prevent reading during writes. A mapped
+    // file keeps it until the mapping is dropped and the file closed.
+    lock_shared_until(&file, deadline).ok()?;
+
+    if file.metadata().ok()?.len() >= mmap_threshold {
+        match EntryBytes::map(file) {
+            Ok(mapped) => return Some(mapped),
+            Err(unmapped) => file = unmapped,
+        }
+    }
+
+    let _file_guard = UnlockGuard(&file);
+
+    // Read through the locked handle, as the path may already name a newer entry.
+    let mut data = Vec::new();
+    (&file).read_to_end(&mut data).ok()?;
+
+    Some(data.into())
+}
+
+/// Verifies the stored form of the entry for `key` at `file_path` against
+/// its [checksum](crate::checksum), and decrypts it with `keyring` and
+/// decompresses it.
+///
+/// An entry with a header is checked against the checksum it carries, and
+/// only an entry without one against its `.sum` file.
+///
+/// # Returns
+/// * `Ok((EntryBytes, Stamp))`: The value of the entry, and what decoding
+///   learned about it
+/// * `Err(std::io::Error)`: Of kind [`std::io::ErrorKind::InvalidData`] if the
+///   entry is corrupt, or another kind if it cannot be decrypted
+fn decode_entry(
+    file_path: &Path,
+    key: &str,
+    stored: EntryBytes,
+    keyring: Option<&Keyring>,
+) -> std::io::Result<(EntryBytes, Stamp)> {
+    if !stored.starts_with(format::MAGIC) && checksum::check(file_path, &stored) == Check::Corrupt {
+        return Err(format::invalid("Cache entry does not match its checksum"));
+    }
+
+    stored.decode(Some(key), keyring)
+}
+
+/// Reads the metadata stored for the entry at `file_path`.
+///
+/// Metadata missing or written for different content than `data` is
+/// ignored, and the expiry recorded in the entry's header is returned
+/// instead, without a validator.
+fn read_info(file_path: &Path, data: &[u8], stamp: Stamp) -> EntryInfo {
+    stored_info(file_path, &content_version(data, stamp.checksum)).unwrap_or_else(|| stamp.info())
+}
+
+/// Reads the metadata stored for the entry at `file_path`, if it was written
+/// for the content of the given [`content_version`].
+fn stored_info(file_path: &Path, version: &EntryVersion) -> Option<EntryInfo> {
+    std::fs::read_to_string(sidecar_path(file_path, META_EXTENSION))
+        .ok()
+        .and_then(|text| toml::from_str::<StoredInfo>(&text).ok())
+        .filter(|stored| stored.version == version.to_string())
+        .map(|stored| stored.info)
+}
+
+/// Returns the version tying metadata to the entry holding `data`: the
+/// `checksum` recorded in the entry's header, which covers its stored form,
+/// or for an entry without one the version of `data`.
+///
+/// Metadata of an [encrypted](crate::encryption) entry thus holds no hash of
+/// its value.
+fn content_version(data: &[u8], checksum: Option<[u8; 32]>) -> EntryVersion {
+    checksum.map_or_else(|| EntryVersion::of(data), EntryVersion::from_bytes)
+}
+
+/// Freshness metadata as persisted next to an entry.
+///
+/// The version ties the metadata to the exact content it was written for,
+/// as given by [`content_version`].
+#[derive(Serialize, Deserialize)]
+struct StoredInfo {
+    version: String,
+    #[serde(flatten)]
+    info: EntryInfo,
+}
+
+/// Writes the metadata file of the entry at `file_path`, for the content of
+/// the given [`content_version`].
+///
+/// Must be called while holding the entry's per-key exclusive lock.
+fn write_info(file_path: &Path, version: &EntryVersion, info: &EntryInfo) -> std::io::Result<()> {
+    let meta_path = sidecar_path(file_path, META_EXTENSION);
+    let tmp_path = sidecar_path(&meta_path, TMP_EXTENSION);
+
+    let stored = StoredInfo {
+        version: version.to_string(),
+        info: info.clone(),
+    };
+    let text = toml::to_string(&stored)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+    std::fs::write(&tmp_path, text)?;
+    std::fs::rename(&tmp_path, &meta_path)
+}
+
+/// Removes a file, treating an already missing file as success.
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
     }
 }
 
+/// Builds the path of a sidecar file (lock, temporary, metadata or checksum file) for an entry.
+///
+/// The extension is appended to the full file name rather than replacing any
+/// existing extension, so keys containing dots never share a sidecar file.
+pub(crate) fn sidecar_path(file_path: &Path, extension: &str) -> PathBuf {
+    let mut name = file_path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// Returns `true` if the file name belongs to the cache's internal bookkeeping
+/// (hidden files, per-key locks, in-flight temporary files, freshness metadata
+/// and checksums) rather than to an entry.
+pub(crate) fn is_internal_file(name: &str) -> bool {
+    name.starts_with('.')
+        || [LOCK_EXTENSION, TMP_EXTENSION, META_EXTENSION, SUM_EXTENSION]
+            .iter()
+            .any(|extension| name.ends_with(&format!(".{}", extension)))
+}
+
 /// Validates that a key is safe to use as a filename.
 /// Prevents path traversal attacks and invalid filenames.
 ///
@@ -424,6 +2410,7 @@ impl FsCache<ReadWrite> {
 /// - Does not contain path traversal components (like '..' or '/')
 /// - Does not contain special path components (such as root or parent directory references)
 /// - Is not excessively long for filesystems (max 255 characters)
+/// - Does not collide with the cache's internal lock, temporary or hidden files
 ///
 /// # Parameters
 /// * `key`: The string key to validate
@@ -431,7 +2418,7 @@ impl FsCache<ReadWrite> {
 /// # Returns
 /// * `Ok(())`: If the key passes all validation checks
 /// * `Err(std::io::Error)`: If any validation check fails, with a descriptive error message
-async fn validate_key(key: &str) -> Result<()> {
+pub(crate) fn validate_key(key: &str) -> Result<()> {
     if key.is_empty() {
         return Err(CacheableError::EmptyKey);
     }
@@ -441,7 +2428,16 @@ async fn validate_key(key: &str) -> Result<()> {
         return Err(std::io::Error::new(
             std::io::ErrorKind::InvalidInput,
             "Cache key too long, must be fewer than 256 characters",
-        ))?;
+        )
+        .into());
+    }
+
+    if is_internal_file(key) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Cache key collides with an internal file name",
+        )
+        .into());
     }
 
     let path = PathBuf::from(key);
@@ -452,7 +2448,8 @@ async fn validate_key(key: &str) -> Result<()> {
                 return Err(std::io::Error::new(
                     std::io::ErrorKind::InvalidInput,
                     "Invalid characters in cache key",
-                ))?;
+                )
+                .into());
             }
         }
     }
@@ -461,29 +2458,78 @@ async fn validate_key(key: &str) -> Result<()> {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::lock::open_lock_file;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Copies the `test_cache_ro` fixture into a temporary directory and marks it
+    /// read-only, since git does not preserve directory permissions.
+    pub(crate) fn read_only_fixture() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let fixture = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test_cache_ro");
+
+        for entry in std::fs::read_dir(fixture).unwrap() {
+            let entry = entry.unwrap();
+            std::fs::copy(entry.path(), dir.path().join(entry.file_name())).unwrap();
+        }
+
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o555)).unwrap();
+        dir
+    }
+
+    /// Returns the size on disk of an uncompressed, unencrypted entry for
+    /// `key` holding `len` bytes.
+    pub(crate) fn stored_size(key: &str, len: usize) -> u64 {
+        (format::STAMPED_HEADER_LEN + key.len() + len) as u64
+    }
+
+    /// Reads the value of the unencrypted entry stored at `path`.
+    pub(crate) fn stored_value(path: impl AsRef<Path>) -> Vec<u8> {
+        format::decode_vec(std::fs::read(path).unwrap(), None, None)
+            .unwrap()
+            .0
+    }
 
     #[tokio::test]
     async fn test_fs_cache_read() {
-        let cache = FsCache::<Read>::new_read("test_cache_ro").await.unwrap();
-        assert_eq!(cache.path, PathBuf::from("test_cache_ro"));
+        let dir = read_only_fixture();
+        let cache = FsCache::<Read>::new_read(dir.path()).await.unwrap();
+        assert_eq!(cache.path, dir.path().to_path_buf());
+    }
+
+    #[tokio::test]
+    async fn test_fs_cache_read_rejects_writable_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = FsCache::<Read>::new_read(dir.path()).await;
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(std::io::ErrorKind::PermissionDenied)
+        );
     }
 
     #[tokio::test]
     async fn test_fs_cache_read_write() {
-        let cache = FsCache::<ReadWrite>::new_write("test_cache_rw", 100)
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
             .await
             .unwrap();
-        assert_eq!(cache.path, PathBuf::from("test_cache_rw"));
-        assert_eq!(cache._kind._limit, 100);
+        assert_eq!(cache.path, dir.path().to_path_buf());
+        assert_eq!(cache.limit(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_fs_cache_new_write_creates_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("cache");
+        FsCache::<ReadWrite>::new_write(&path, 100).await.unwrap();
+        assert!(path.is_dir());
     }
 
     #[tokio::test]
     async fn test_fs_cache_get() {
-        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        path.push("test_cache_ro");
-        let cache = FsCache::<Read>::new_read(path).await.unwrap();
+        let dir = read_only_fixture();
+        let cache = FsCache::<Read>::new_read(dir.path()).await.unwrap();
         let result = cache.get("key1").await;
         assert!(
             result.is_some(),
@@ -493,11 +2539,422 @@ mod tests {
 
     #[tokio::test]
     async fn test_fs_cache_put() {
-        let cache = FsCache::<ReadWrite>::new_write("test_cache_rw", 100)
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
             .await
             .unwrap();
         let result = cache.put("key1", b"Hello, world!").await;
         dbg!(&result);
         assert!(result.is_ok());
+        assert_eq!(cache.get("key1").await, Some(b"Hello, world!".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_fs_cache_entries_skips_internal_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        cache.put("key1", b"Hello").await.unwrap();
+        cache.put("key2", b"Hello, world!").await.unwrap();
+        std::fs::write(dir.path().join("key3.tmp"), b"partial").unwrap();
+
+        let mut entries = cache.entries().await.unwrap();
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+
+        let keys: Vec<_> = entries.iter().map(|e| (e.key.as_str(), e.size)).collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("key1", stored_size("key1", 5)),
+                ("key2", stored_size("key2", 13))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fs_cache_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        cache.put("key1", b"Hello").await.unwrap();
+        cache.put("key2", b"Hello, world!").await.unwrap();
+        std::fs::write(dir.path().join("key2.tmp"), b"partial").unwrap();
+
+        assert_eq!(cache.clear().await.unwrap(), 2);
+        assert!(cache.entries().await.unwrap().is_empty());
+        assert!(!dir.path().join("key2.tmp").exists());
+        assert!(!dir.path().join("key1.lock").exists());
+        assert!(dir.path().is_dir());
+
+        cache.put("key1", b"again").await.unwrap();
+        assert_eq!(cache.get("key1").await, Some(b"again".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_fs_cache_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        cache
+            .put_with_info("key1", b"Hello", &EntryInfo::default())
+            .await
+            .unwrap();
+
+        assert!(cache.remove("key1").await.unwrap());
+        assert!(!cache.remove("key1").await.unwrap());
+        assert_eq!(cache.get("key1").await, None);
+        assert_eq!(cache.used_bytes().unwrap(), 0);
+        for name in ["key1", "key1.lock", "key1.meta", "key1.sum"] {
+            assert!(!dir.path().join(name).exists(), "{} was left behind", name);
+        }
+        assert!(cache.remove("../key1").await.is_err());
+
+        // Writers racing removals of the same key lock the lock file in place.
+        let tasks: Vec<_> = (0..16)
+            .map(|i| {
+                let cache = cache.clone();
+                tokio::spawn(async move {
+                    if i % 2 == 0 {
+                        cache.put("key2", b"value").await.unwrap();
+                    } else {
+                        cache.remove("key2").await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let entries = cache.entries().await.unwrap();
+        match cache.get("key2").await {
+            Some(value) => {
+                assert_eq!(value, b"value");
+                assert_eq!(entries.len(), 1);
+            }
+            None => assert!(entries.is_empty()),
+        }
+        assert!(!dir.path().join("key2.tmp").exists());
+    }
+
+    #[tokio::test]
+    async fn test_fs_cache_max_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        // Room for two entries of four bytes and one of six
+        let max_bytes = stored_size("key1", 4) + stored_size("key2", 6);
+        cache.set_max_bytes(Some(max_bytes));
+
+        for key in ["key1", "key2", "key3"] {
+            // Keep the entries' access times apart for the LRU ranking.
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            cache.put(key, b"data").await.unwrap();
+        }
+        assert_eq!(cache.used_bytes().unwrap(), 2 * stored_size("key1", 4));
+        assert_eq!(cache.get("key1").await, None);
+
+        // Growing an entry within the limit evicts nothing.
+        cache.put("key2", b"larger").await.unwrap();
+        assert_eq!(cache.used_bytes().unwrap(), max_bytes);
+        assert_eq!(cache.entries().await.unwrap().len(), 2);
+
+        match cache.put("key4", &b"far too large".repeat(10)).await {
+            Err(CacheableError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::StorageFull),
+            other => panic!("expected StorageFull, got {:?}", other),
+        }
+
+        let reopened = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        assert_eq!(reopened.used_bytes().unwrap(), max_bytes);
+
+        cache.clear().await.unwrap();
+        assert_eq!(cache.used_bytes().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_fs_cache_put_if_absent() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+
+        assert!(cache.put_if_absent("key1", b"first").await.unwrap());
+        assert!(!cache.put_if_absent("key1", b"second").await.unwrap());
+        assert_eq!(cache.get("key1").await, Some(b"first".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_fs_cache_compare_and_swap() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        let first = EntryVersion::of(b"first");
+
+        assert!(
+            !cache
+                .compare_and_swap("key1", &first, b"second")
+                .await
+                .unwrap()
+        );
+
+        cache.put("key1", b"first").await.unwrap();
+        assert!(
+            cache
+                .compare_and_swap("key1", &first, b"second")
+                .await
+                .unwrap()
+        );
+        assert!(
+            !cache
+                .compare_and_swap("key1", &first, b"third")
+                .await
+                .unwrap()
+        );
+        assert_eq!(cache.get("key1").await, Some(b"second".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_fs_cache_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        let info = EntryInfo {
+            validator: Some("\"etag\"".to_string()),
+            fresh_until: Some(42),
+        };
+
+        assert!(!cache.set_info("key1", &info).await.unwrap());
+
+        cache.put_with_info("key1", b"Hello", &info).await.unwrap();
+        assert_eq!(
+            cache.get_with_info("key1").await,
+            Some((b"Hello".to_vec(), info.clone()))
+        );
+
+        let extended = EntryInfo {
+            fresh_until: Some(84),
+            ..info
+        };
+        assert!(cache.set_info("key1", &extended).await.unwrap());
+        assert_eq!(cache.get_with_info("key1").await.unwrap().1, extended);
+
+        // Without its metadata file, the expiry the entry was written with remains.
+        std::fs::remove_file(dir.path().join("key1.meta")).unwrap();
+        assert_eq!(
+            cache.get_with_info("key1").await.unwrap().1,
+            EntryInfo {
+                validator: None,
+                fresh_until: Some(42),
+            }
+        );
+
+        // A plain put discards metadata written for the previous content.
+        cache.put("key1", b"World").await.unwrap();
+        assert_eq!(
+            cache.get_with_info("key1").await,
+            Some((b"World".to_vec(), EntryInfo::default()))
+        );
+        assert_eq!(cache.entries().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fs_cache_untracked_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        cache.put("key1", b"Hello").await.unwrap();
+        let hits = || {
+            cache
+                ._kind
+                .index()
+                .unwrap()
+                .view(|state| state.entries["key1"].hits)
+                .unwrap()
+        };
+
+        assert!(
+            cache
+                .read_with_info("key1", default_deadline(), false)
+                .await
+                .is_some()
+        );
+        assert_eq!(hits(), 1);
+
+        assert!(cache.get_with_info("key1").await.is_some());
+        assert_eq!(hits(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fs_cache_counts_abandoned_operations() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        assert!(!cache.is_busy());
+
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let result = cache.unblock_until(deadline, move || released.recv()).await;
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+
+        // The caller gave up, but the blocking work is still running.
+        assert!(cache.clone().is_busy());
+        release.send(()).unwrap();
+        while cache.is_busy() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fs_cache_put_rejects_internal_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        assert!(cache.put("key1.lock", b"data").await.is_err());
+        assert!(cache.put(".hidden", b"data").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fs_cache_write_gives_up_at_deadline() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+
+        // Another holder keeps the key locked for the whole test.
+        let holder = open_lock_file(&dir.path().join("key1.lock")).unwrap();
+        FileExt::lock_exclusive(&holder).unwrap();
+
+        let started = Instant::now();
+        let deadline = started + Duration::from_millis(200);
+        let result = cache
+            .write("key1", b"data", Condition::Always, None, deadline)
+            .await;
+
+        assert!(result.is_err());
+        assert!(started.elapsed() < DEFAULT_LOCK_TIMEOUT);
+        assert!(!dir.path().join("key1").exists());
+
+        FileExt::unlock(&holder).unwrap();
+        assert!(cache.read("key1", default_deadline()).await.is_none());
+        cache.put("key1", b"data").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fs_cache_migrate_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        let info = EntryInfo {
+            validator: Some("\"etag\"".to_string()),
+            fresh_until: Some(42),
+        };
+        let value = b"{\"evidence\": \"repeated\"}".repeat(100);
+
+        // Entries written by earlier versions, without checksums
+        cache.put("key1", b"Hello").await.unwrap();
+        std::fs::write(dir.path().join("key1"), b"Hello").unwrap();
+        assert!(cache.set_info("key1", &info).await.unwrap());
+        std::fs::write(
+            dir.path().join("key2"),
+            Compression::default().encode(&value).unwrap(),
+        )
+        .unwrap();
+        cache.put("key3", b"world").await.unwrap();
+
+        assert_eq!(cache.migrate_format().await.unwrap(), 2);
+        assert_eq!(cache.migrate_format().await.unwrap(), 0);
+
+        for key in ["key1", "key2", "key3"] {
+            let stored = std::fs::read(dir.path().join(key)).unwrap();
+            let header = format::read_header(&stored).unwrap().unwrap();
+            assert_eq!(header.version, format::VERSION);
+            assert_eq!(header.key.as_deref(), Some(key));
+        }
+        let stored = std::fs::read(dir.path().join("key1")).unwrap();
+        assert_eq!(
+            format::read_header(&stored).unwrap().unwrap().expires,
+            Some(42)
+        );
+
+        assert_eq!(
+            cache.get_with_info("key1").await,
+            Some((b"Hello".to_vec(), info))
+        );
+        assert_eq!(cache.get("key2").await, Some(value));
+        assert_eq!(cache.get("key3").await, Some(b"world".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_fs_cache_repair() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        cache.on_corrupt_entry(Arc::new(|_| {}));
+        cache.put("key1", b"Hello").await.unwrap();
+        cache.put("key2", b"world").await.unwrap();
+
+        // Left behind by a crashed writer
+        std::fs::write(dir.path().join("key3.tmp"), b"torn").unwrap();
+        std::fs::write(dir.path().join("key3.lock"), b"").unwrap();
+        std::fs::write(dir.path().join("key3.meta"), b"").unwrap();
+        std::fs::write(dir.path().join("key2.meta"), b"garbage").unwrap();
+        let mut stored = std::fs::read(dir.path().join("key1")).unwrap();
+        *stored.last_mut().unwrap() = b'p';
+        std::fs::write(dir.path().join("key1"), &stored).unwrap();
+
+        // In use by a live writer
+        let holder = open_lock_file(&dir.path().join("key4.lock")).unwrap();
+        FileExt::lock_exclusive(&holder).unwrap();
+        std::fs::write(dir.path().join("key4.tmp"), b"partial").unwrap();
+
+        let report = cache.repair().await.unwrap();
+
+        let mut removed: Vec<_> = report
+            .removed
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        removed.sort();
+        assert_eq!(
+            removed,
+            [
+                "key1.lock",
+                "key2.meta",
+                "key3.lock",
+                "key3.meta",
+                "key3.tmp"
+            ]
+        );
+        assert_eq!(report.quarantined.len(), 1);
+        assert_eq!(report.quarantined[0].key, "key1");
+        assert_eq!(report.busy, ["key4"]);
+        assert_eq!(report.entries, 1);
+        assert!(report.changed());
+        assert!(dir.path().join("key4.tmp").exists());
+        assert_eq!(cache.used_bytes().unwrap(), stored_size("key2", 5));
+        assert_eq!(cache.corrupt_entries(), 1);
+
+        FileExt::unlock(&holder).unwrap();
+        drop(holder);
+
+        let report = cache.repair().await.unwrap();
+        assert!(report.changed());
+        assert!(!dir.path().join("key4.tmp").exists());
+        assert!(!dir.path().join("key4.lock").exists());
+        assert!(!cache.repair().await.unwrap().changed());
+        assert_eq!(cache.get("key2").await, Some(b"world".to_vec()));
     }
 }
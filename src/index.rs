@@ -0,0 +1,745 @@
+//! # OmneCache Disk Index
+//!
+//! A record of the entries of a disk layer, kept in its cache directory so
+//! that enforcing the item and byte limits does not list the directory on
+//! every write.
+//!
+//! The index is an append-only journal, `.index`, that starts with a version
+//! line followed by one line per change:
+//!
+//! * `put <size> <modified> <last access> <hits> <key>`: an entry was written
+//! * `hit <time> <count> <key>`: an entry was read `count` times, last at `time`
+//! * `del <key>`: an entry was removed
+//!
+//! Times are nanoseconds since the Unix epoch; `%`, line feeds and carriage
+//! returns in keys are percent-encoded. Lines are appended under an exclusive
+//! lock on `.index.lock`, and every process sharing the directory replays the
+//! lines appended by the others before using its copy of the index.
+//!
+//! Reads are frequent, so they are counted in memory and appended together
+//! with the next change, when the index is next used to evict, or once
+//! [`HIT_FLUSH_INTERVAL`] has passed since they were last appended. A read
+//! therefore takes the lock at most once per interval, and other processes
+//! see it with that much delay.
+//!
+//! Once the journal has grown well beyond the number of entries, it is
+//! compacted: the current state is written to a temporary file that is then
+//! renamed over the journal, so a crash never leaves a partial index behind.
+//! A journal that is missing, has an unknown version or a malformed line is
+//! rebuilt from the directory, and entries added or removed behind the
+//! index's back are reconciled whenever a cache opens the directory.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use fs2::FileExt;
+
+use crate::{
+    fs::{is_internal_file, list_entries},
+    layout::Layout,
+};
+
+/// Name of the journal file in the cache directory
+pub(crate) const INDEX_FILE: &str = ".index";
+/// Name of the file locked while the journal is read or appended to
+const INDEX_LOCK_FILE: &str = ".index.lock";
+/// Name of the file a compacted journal is written to before replacing the journal
+const INDEX_TMP_FILE: &str = ".index.tmp";
+/// First line of a journal in the current format
+const HEADER: &str = "omnecache-index 2";
+/// Number of lines a journal may exceed twice its entries by before it is compacted
+const COMPACTION_SLACK: usize = 1024;
+/// Longest time reads are only counted in memory before they are appended to the journal
+pub(crate) const HIT_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What the index knows about a single entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct IndexEntry {
+    /// Size of the entry in bytes
+    pub(crate) size: u64,
+    /// Time the entry was last written
+    pub(crate) modified: SystemTime,
+    /// Time the entry was last read or written
+    pub(crate) last_access: SystemTime,
+    /// Number of reads and writes of the entry
+    pub(crate) hits: u64,
+}
+
+/// The entries of a cache directory, as of the last replayed journal line.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct IndexState {
+    /// The entries by key
+    pub(crate) entries: HashMap<String, IndexEntry>,
+    /// Total size of the entries in bytes
+    pub(crate) used_bytes: u64,
+}
+
+impl IndexState {
+    /// Applies a journal record.
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::Put(key, entry) => {
+                self.used_bytes += entry.size;
+                if let Some(previous) = self.entries.insert(key, entry) {
+                    self.used_bytes -= previous.size;
+                }
+            }
+            Record::Hit(key, time, count) => {
+                if let Some(entry) = self.entries.get_mut(&key) {
+                    entry.last_access = time;
+                    entry.hits = entry.hits.saturating_add(count);
+                }
+            }
+            Record::Del(key) => {
+                if let Some(previous) = self.entries.remove(&key) {
+                    self.used_bytes -= previous.size;
+                }
+            }
+        }
+    }
+}
+
+/// A line of the journal
+enum Record {
+    /// An entry was written
+    Put(String, IndexEntry),
+    /// An entry was read the given number of times, last at the given time
+    Hit(String, SystemTime, u64),
+    /// An entry was removed
+    Del(String),
+}
+
+impl Record {
+    /// Parses a journal line, without its line feed.
+    fn parse(line: &str) -> Option<Self> {
+        let (kind, rest) = line.split_once(' ')?;
+
+        match kind {
+            "put" => {
+                let mut fields = rest.splitn(5, ' ');
+                let size = fields.next()?.parse().ok()?;
+                let modified = parse_time(fields.next()?)?;
+                let last_access = parse_time(fields.next()?)?;
+                let hits = fields.next()?.parse().ok()?;
+                let key = unescape(fields.next()?);
+
+                Some(Self::Put(
+                    key,
+                    IndexEntry {
+                        size,
+                        modified,
+                        last_access,
+                        hits,
+                    },
+                ))
+            }
+            "hit" => {
+                let mut fields = rest.splitn(3, ' ');
+                let time = parse_time(fields.next()?)?;
+                let count = fields.next()?.parse().ok()?;
+                Some(Self::Hit(unescape(fields.next()?), time, count))
+            }
+            "del" => Some(Self::Del(unescape(rest))),
+            _ => None,
+        }
+    }
+
+    /// Formats the record as a journal line, including its line feed.
+    fn line(&self) -> String {
+        match self {
+            Self::Put(key, entry) => format!(
+                "put {} {} {} {} {}\n",
+                entry.size,
+                format_time(entry.modified),
+                format_time(entry.last_access),
+                entry.hits,
+                escape(key)
+            ),
+            Self::Hit(key, time, count) => {
+                format!("hit {} {} {}\n", format_time(*time), count, escape(key))
+            }
+            Self::Del(key) => format!("del {}\n", escape(key)),
+        }
+    }
+}
+
+/// The index of a cache directory, shared with other processes through its journal.
+pub(crate) struct Index {
+    /// The cache directory
+    dir: PathBuf,
+    /// How the entries are arranged in the directory
+    layout: Layout,
+    /// Held exclusively while the journal is read or appended to
+    lock: Arc<File>,
+    /// The journal, opened for reading and appending
+    journal: File,
+    /// Number of journal bytes replayed into `state`
+    offset: u64,
+    /// Number of lines in the journal, excluding the version line
+    lines: usize,
+    /// The entries as of the last replayed line
+    state: IndexState,
+    /// Reads not appended yet: the time of the last read and the number of reads, by key
+    hits: HashMap<String, (SystemTime, u64)>,
+    /// When reads were last appended
+    hits_flushed: Instant,
+}
+
+/// Releases the index lock when dropped.
+struct IndexLockGuard<'a>(&'a File);
+
+impl Drop for IndexLockGuard<'_> {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(self.0);
+    }
+}
+
+impl Index {
+    /// Opens the index of the cache directory `dir`, rebuilding it if it is
+    /// missing or corrupt and reconciling it with the entries in the directory.
+    pub(crate) fn open(dir: &Path, layout: Layout) -> std::io::Result<Self> {
+        let lock = Arc::new(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(dir.join(INDEX_LOCK_FILE))?,
+        );
+        let _guard = IndexLockGuard(&lock);
+        FileExt::lock_exclusive(&*lock)?;
+
+        let (journal, offset, lines, state) = match load(dir)? {
+            Some(loaded) => loaded,
+            None => compact(dir, rebuild(dir, layout)?)?,
+        };
+
+        let mut index = Self {
+            dir: dir.to_path_buf(),
+            layout,
+            lock: lock.clone(),
+            journal,
+            offset,
+            lines,
+            state,
+            hits: HashMap::new(),
+            hits_flushed: Instant::now(),
+        };
+
+        index.reconcile()?;
+
+        Ok(index)
+    }
+
+    /// Runs `f` on the current entries, after replaying the lines appended by others.
+    pub(crate) fn view<R>(&mut self, f: impl FnOnce(&IndexState) -> R) -> std::io::Result<R> {
+        let lock = self.lock.clone();
+        let _guard = IndexLockGuard(&lock);
+        FileExt::lock_exclusive(&*lock)?;
+
+        self.sync()?;
+        self.write(Vec::new())?;
+        Ok(f(&self.state))
+    }
+
+    /// Records that `key` was written with `size` bytes at `modified`.
+    ///
+    /// A write counts as an access of the entry.
+    pub(crate) fn put(
+        &mut self,
+        key: &str,
+        size: u64,
+        modified: SystemTime,
+    ) -> std::io::Result<()> {
+        self.append(|state| {
+            let hits = state.entries.get(key).map_or(0, |entry| entry.hits);
+
+            Some(Record::Put(
+                key.to_string(),
+                IndexEntry {
+                    size,
+                    modified,
+                    last_access: SystemTime::now(),
+                    hits: hits.saturating_add(1),
+                },
+            ))
+        })
+    }
+
+    /// Records that `key` was read.
+    ///
+    /// The read is counted in memory, and only appended to the journal once
+    /// [`HIT_FLUSH_INTERVAL`] has passed since reads were last appended.
+    pub(crate) fn hit(&mut self, key: &str) -> std::io::Result<()> {
+        let now = SystemTime::now();
+        let (time, count) = self.hits.entry(key.to_string()).or_insert((now, 0));
+        *time = now;
+        *count += 1;
+
+        if self.hits_flushed.elapsed() < HIT_FLUSH_INTERVAL {
+            return Ok(());
+        }
+
+        self.append(|_| None)
+    }
+
+    /// Records that `key` was removed.
+    pub(crate) fn remove(&mut self, key: &str) -> std::io::Result<()> {
+        self.append(|state| {
+            state
+                .entries
+                .contains_key(key)
+                .then(|| Record::Del(key.to_string()))
+        })
+    }
+
+    /// Appends the record returned by `record` for the current entries, if any.
+    fn append(
+        &mut self,
+        record: impl FnOnce(&IndexState) -> Option<Record>,
+    ) -> std::io::Result<()> {
+        let lock = self.lock.clone();
+        let _guard = IndexLockGuard(&lock);
+        FileExt::lock_exclusive(&*lock)?;
+
+        self.sync()?;
+        self.write(Vec::new())?;
+
+        match record(&self.state) {
+            Some(record) => self.write(vec![record]),
+            None => Ok(()),
+        }
+    }
+
+    /// Appends `records`, preceded by the pending reads, in a single write.
+    ///
+    /// Must be called while holding the index lock, after [`Index::sync`].
+    fn write(&mut self, mut records: Vec<Record>) -> std::io::Result<()> {
+        let hits = std::mem::take(&mut self.hits);
+        self.hits_flushed = Instant::now();
+
+        // Reads of entries removed meanwhile are dropped.
+        let hits = hits
+            .into_iter()
+            .filter(|(key, _)| self.state.entries.contains_key(key))
+            .map(|(key, (time, count))| Record::Hit(key, time, count));
+        records.splice(0..0, hits);
+
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let text: String = records.iter().map(Record::line).collect();
+        self.journal.write_all(text.as_bytes())?;
+        self.offset += text.len() as u64;
+        self.lines += records.len();
+
+        for record in records {
+            self.state.apply(record);
+        }
+
+        if self.lines > 2 * self.state.entries.len() + COMPACTION_SLACK {
+            self.replace_journal(compact(&self.dir, self.state.clone())?);
+        }
+
+        Ok(())
+    }
+
+    /// Replays the lines appended since the last sync, reloading the journal if
+    /// it was compacted and rebuilding it if it is missing or corrupt.
+    ///
+    /// Must be called while holding the index lock.
+    fn sync(&mut self) -> std::io::Result<()> {
+        let current = match std::fs::metadata(self.dir.join(INDEX_FILE)) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return self.reset(None);
+            }
+            Err(e) => return Err(e),
+        };
+        let opened = self.journal.metadata()?;
+
+        if (current.dev(), current.ino()) != (opened.dev(), opened.ino()) {
+            return self.reset(load(&self.dir)?);
+        }
+
+        let mut tail = String::new();
+        self.journal.seek(SeekFrom::Start(self.offset))?;
+        if self.journal.read_to_string(&mut tail).is_err() {
+            return self.reset(None);
+        }
+
+        // With the lock held no line is half written, unless a writer crashed.
+        if !tail.is_empty() && !tail.ends_with('\n') {
+            return self.reset(None);
+        }
+
+        for line in tail.lines() {
+            match Record::parse(line) {
+                Some(record) => self.state.apply(record),
+                None => return self.reset(None),
+            }
+            self.lines += 1;
+        }
+        self.offset += tail.len() as u64;
+
+        Ok(())
+    }
+
+    /// Replaces the index with a loaded journal, or with one rebuilt from the directory.
+    fn reset(&mut self, loaded: Option<Loaded>) -> std::io::Result<()> {
+        let loaded = match loaded {
+            Some(loaded) => loaded,
+            None => compact(&self.dir, rebuild(&self.dir, self.layout)?)?,
+        };
+
+        self.replace_journal(loaded);
+        Ok(())
+    }
+
+    /// Switches to a newly loaded or compacted journal.
+    fn replace_journal(&mut self, (journal, offset, lines, state): Loaded) {
+        self.journal = journal;
+        self.offset = offset;
+        self.lines = lines;
+        self.state = state;
+    }
+
+    /// Brings the index in line with the entries in the directory.
+    ///
+    /// Only the names of the entries are compared; entries missing from the
+    /// index are added with their size and modification time, and index
+    /// entries without a file are removed.
+    ///
+    /// Must be called while holding the index lock.
+    fn reconcile(&mut self) -> std::io::Result<()> {
+        let mut names = HashSet::new();
+
+        for entry_dir in self.layout.entry_dirs(&self.dir)? {
+            for dir_entry in std::fs::read_dir(entry_dir)? {
+                let dir_entry = dir_entry?;
+
+                if let Ok(name) = dir_entry.file_name().into_string()
+                    && !is_internal_file(&name)
+                    && dir_entry.file_type()?.is_file()
+                {
+                    names.insert(name);
+                }
+            }
+        }
+
+        let known: HashSet<_> = self.state.entries.keys().cloned().collect();
+        if names == known {
+            return Ok(());
+        }
+
+        self.replace_from_directory()
+    }
+
+    /// Rewrites the index from the entries in the directory, keeping the
+    /// access statistics of the entries it already knows.
+    ///
+    /// # Returns
+    /// The number of entries in the rewritten index
+    pub(crate) fn rebuild(&mut self) -> std::io::Result<usize> {
+        let lock = self.lock.clone();
+        let _guard = IndexLockGuard(&lock);
+        FileExt::lock_exclusive(&*lock)?;
+
+        self.sync()?;
+        self.replace_from_directory()?;
+        Ok(self.state.entries.len())
+    }
+
+    /// Replaces the journal with the entries listed from the directory.
+    ///
+    /// Must be called while holding the index lock.
+    fn replace_from_directory(&mut self) -> std::io::Result<()> {
+        let mut state = IndexState::default();
+        for entry in list_entries(&self.dir, self.layout)? {
+            let record = match self.state.entries.get(&entry.key) {
+                Some(known) => Record::Put(entry.key, *known),
+                None => Record::Put(entry.key, unused(entry.size, entry.modified)),
+            };
+            state.apply(record);
+        }
+
+        self.replace_journal(compact(&self.dir, state)?);
+        Ok(())
+    }
+}
+
+impl Drop for Index {
+    fn drop(&mut self) {
+        // Best effort: lost reads only affect which entries are evicted later.
+        if !self.hits.is_empty() {
+            let _ = self.append(|_| None);
+        }
+    }
+}
+
+/// An opened journal: the file, the number of bytes and lines replayed, and the resulting entries
+type Loaded = (File, u64, usize, IndexState);
+
+/// Opens and replays the journal of `dir`.
+///
+/// # Returns
+/// * `Ok(Some(Loaded))`: If the journal was replayed
+/// * `Ok(None)`: If the journal is missing or corrupt
+fn load(dir: &Path) -> std::io::Result<Option<Loaded>> {
+    let mut journal = match std::fs::OpenOptions::new()
+        .read(true)
+        .append(true)
+        .open(dir.join(INDEX_FILE))
+    {
+        Ok(journal) => journal,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let mut text = String::new();
+    if journal.read_to_string(&mut text).is_err() || !text.ends_with('\n') {
+        return Ok(None);
+    }
+
+    let mut lines = text.lines();
+    if lines.next() != Some(HEADER) {
+        return Ok(None);
+    }
+
+    let mut state = IndexState::default();
+    let mut count = 0;
+    for line in lines {
+        match Record::parse(line) {
+            Some(record) => state.apply(record),
+            None => return Ok(None),
+        }
+        count += 1;
+    }
+
+    Ok(Some((journal, text.len() as u64, count, state)))
+}
+
+/// Collects the entries of `dir` from the directory itself.
+///
+/// Access statistics are lost; every entry is treated as last used when it was written.
+fn rebuild(dir: &Path, layout: Layout) -> std::io::Result<IndexState> {
+    let mut state = IndexState::default();
+
+    for entry in list_entries(dir, layout)? {
+        state.apply(Record::Put(entry.key, unused(entry.size, entry.modified)));
+    }
+
+    Ok(state)
+}
+
+/// Writes `state` as a new journal and atomically replaces the journal of `dir` with it.
+///
+/// Must be called while holding the index lock.
+fn compact(dir: &Path, state: IndexState) -> std::io::Result<Loaded> {
+    let tmp_path = dir.join(INDEX_TMP_FILE);
+    let mut text = format!("{}\n", HEADER);
+
+    for (key, entry) in &state.entries {
+        text.push_str(&Record::Put(key.clone(), *entry).line());
+    }
+
+    let mut tmp_file = File::create(&tmp_path)?;
+    tmp_file.write_all(text.as_bytes())?;
+    tmp_file.sync_all()?;
+    std::fs::rename(&tmp_path, dir.join(INDEX_FILE))?;
+
+    let journal = std::fs::OpenOptions::new()
+        .read(true)
+        .append(true)
+        .open(dir.join(INDEX_FILE))?;
+
+    Ok((journal, text.len() as u64, state.entries.len(), state))
+}
+
+/// Returns the index entry of a file without recorded accesses.
+fn unused(size: u64, modified: SystemTime) -> IndexEntry {
+    IndexEntry {
+        size,
+        modified,
+        last_access: modified,
+        hits: 0,
+    }
+}
+
+/// Formats a time as nanoseconds since the Unix epoch.
+fn format_time(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+/// Parses nanoseconds since the Unix epoch.
+fn parse_time(text: &str) -> Option<SystemTime> {
+    let nanos: u128 = text.parse().ok()?;
+    let duration = Duration::new(
+        u64::try_from(nanos / 1_000_000_000).ok()?,
+        (nanos % 1_000_000_000) as u32,
+    );
+
+    UNIX_EPOCH.checked_add(duration)
+}
+
+/// Percent-encodes the characters of a key that would break a journal line.
+fn escape(key: &str) -> String {
+    key.replace('%', "%25")
+        .replace('\n', "%0A")
+        .replace('\r', "%0D")
+}
+
+/// Reverses [`escape`].
+fn unescape(text: &str) -> String {
+    text.replace("%0A", "\n")
+        .replace("%0D", "\r")
+        .replace("%25", "%")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn used_bytes(index: &mut Index) -> u64 {
+        index.view(|state| state.used_bytes).unwrap()
+    }
+
+    #[test]
+    fn test_index_shares_changes_through_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = Index::open(dir.path(), Layout::Flat).unwrap();
+        let mut reader = Index::open(dir.path(), Layout::Flat).unwrap();
+
+        writer.put("a", 5, SystemTime::now()).unwrap();
+        writer.put("b", 7, SystemTime::now()).unwrap();
+        writer.hit("a").unwrap();
+        assert_eq!(used_bytes(&mut reader), 12);
+        assert_eq!(reader.view(|state| state.entries["a"].hits).unwrap(), 1);
+
+        // Reads are shared once the writer next uses its index.
+        assert_eq!(used_bytes(&mut writer), 12);
+        assert_eq!(reader.view(|state| state.entries["a"].hits).unwrap(), 2);
+
+        reader.remove("b").unwrap();
+        assert_eq!(used_bytes(&mut writer), 5);
+    }
+
+    #[test]
+    fn test_index_batches_hits() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a"), b"alpha").unwrap();
+        let mut index = Index::open(dir.path(), Layout::Flat).unwrap();
+        index.put("a", 5, SystemTime::now()).unwrap();
+        let journal_len = || {
+            std::fs::metadata(dir.path().join(INDEX_FILE))
+                .unwrap()
+                .len()
+        };
+        let len = journal_len();
+
+        for _ in 0..100 {
+            index.hit("a").unwrap();
+        }
+        assert_eq!(journal_len(), len);
+
+        // The first read after the interval appends every read since the last flush.
+        index.hits_flushed -= HIT_FLUSH_INTERVAL;
+        index.hit("a").unwrap();
+        let journal = std::fs::read_to_string(dir.path().join(INDEX_FILE)).unwrap();
+        assert_eq!(journal.lines().filter(|l| l.starts_with("hit ")).count(), 1);
+
+        let mut reopened = Index::open(dir.path(), Layout::Flat).unwrap();
+        assert_eq!(reopened.view(|state| state.entries["a"].hits).unwrap(), 102);
+    }
+
+    #[test]
+    fn test_index_rebuilds_corrupt_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a"), b"alpha").unwrap();
+        let mut index = Index::open(dir.path(), Layout::Flat).unwrap();
+        assert_eq!(used_bytes(&mut index), 5);
+
+        // A line torn by a crash is detected by the next reader.
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.path().join(INDEX_FILE))
+            .unwrap()
+            .write_all(b"put 3")
+            .unwrap();
+        std::fs::write(dir.path().join("b"), b"beta").unwrap();
+        assert_eq!(used_bytes(&mut index), 9);
+
+        std::fs::write(dir.path().join(INDEX_FILE), "garbage\n").unwrap();
+        assert_eq!(
+            used_bytes(&mut Index::open(dir.path(), Layout::Flat).unwrap()),
+            9
+        );
+
+        std::fs::remove_file(dir.path().join(INDEX_FILE)).unwrap();
+        assert_eq!(used_bytes(&mut index), 9);
+    }
+
+    #[test]
+    fn test_index_reconciles_directory_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a"), b"alpha").unwrap();
+        std::fs::write(dir.path().join("b"), b"beta").unwrap();
+        Index::open(dir.path(), Layout::Flat)
+            .unwrap()
+            .hit("a")
+            .unwrap();
+
+        std::fs::remove_file(dir.path().join("b")).unwrap();
+        std::fs::write(dir.path().join("c"), b"gamma").unwrap();
+
+        let mut index = Index::open(dir.path(), Layout::Flat).unwrap();
+        let (keys, hits) = index
+            .view(|state| {
+                let mut keys: Vec<_> = state.entries.keys().cloned().collect();
+                keys.sort();
+                (keys, state.entries["a"].hits)
+            })
+            .unwrap();
+        assert_eq!(keys, ["a", "c"]);
+        assert_eq!(hits, 1);
+        assert_eq!(used_bytes(&mut index), 10);
+    }
+
+    #[test]
+    fn test_index_compacts_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut index = Index::open(dir.path(), Layout::Flat).unwrap();
+
+        for _ in 0..=COMPACTION_SLACK + 2 {
+            index.put("a", 1, SystemTime::now()).unwrap();
+        }
+
+        let journal = std::fs::read_to_string(dir.path().join(INDEX_FILE)).unwrap();
+        assert!(journal.lines().count() < 10);
+        assert_eq!(
+            index.view(|state| state.entries["a"].hits).unwrap(),
+            COMPACTION_SLACK as u64 + 3
+        );
+    }
+
+    #[test]
+    fn test_record_escapes_keys() {
+        let key = "odd %0A key\nwith\r breaks %";
+        let line = Record::Del(key.to_string()).line();
+        assert_eq!(line.lines().count(), 1);
+
+        match Record::parse(line.trim_end_matches('\n')) {
+            Some(Record::Del(parsed)) => assert_eq!(parsed, key),
+            _ => panic!("expected a del record"),
+        }
+    }
+}
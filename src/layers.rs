@@ -0,0 +1,352 @@
+//! # OmneCache Layer Control
+//!
+//! Adjusting the layers of a running [`OmneCache`] without rebuilding it:
+//!
+//! * [`OmneCache::resize_memory`] changes the capacity of the memory layer,
+//!   keeping its most recently used entries.
+//! * [`OmneCache::set_disk_limit`] and [`OmneCache::set_disk_max_bytes`]
+//!   change the item and byte limits of the disk layer.
+//! * [`OmneCache::set_ttl`] changes how long newly stored values stay fresh.
+//! * [`OmneCache::attach_memory`], [`OmneCache::attach_disk`] and
+//!   [`OmneCache::attach_sideload`] enable layers, and the matching `detach_*`
+//!   methods disable them.
+//!
+//! Every cache operation works on the layers attached when it started. A
+//! detached layer is no longer used by new operations, and the detaching call
+//! returns only once the operations already using it (including
+//! [`OmneCache::warm`] tasks) have finished. Filesystem work cannot be
+//! interrupted, so this includes work whose call gave up at its deadline or
+//! was cancelled: detaching waits, without a bound of its own, until that work
+//! completes, and until a [pack store](crate::pack) finishes compacting.
+
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use lru::LruCache;
+
+use crate::{
+    MemoryEntry, OmneCache,
+    configuration::{DiskCfg, MemoryCfg, SideloadCfg},
+    error::ConfigurationError,
+    fs::{AccessMode, FsCache, LOCK_RETRY_INTERVAL},
+    lock_memory, runtime,
+};
+
+/// A layer that may keep working for operations that no longer hold it.
+pub(crate) trait Detachable {
+    /// Returns `true` while work started for an operation is still running.
+    fn is_busy(&self) -> bool;
+}
+
+impl Detachable for Mutex<LruCache<String, MemoryEntry>> {
+    fn is_busy(&self) -> bool {
+        false
+    }
+}
+
+impl<T: AccessMode> Detachable for FsCache<T> {
+    fn is_busy(&self) -> bool {
+        FsCache::is_busy(self)
+    }
+}
+
+/// A cache layer that can be attached and detached while the cache is in use.
+///
+/// Operations hold a clone of the layer's `Arc` for their duration, and the
+/// layer counts the blocking work they start, which is what detaching waits for.
+pub(crate) struct LayerSlot<T>(RwLock<Option<Arc<T>>>);
+
+impl<T: Detachable> LayerSlot<T> {
+    /// Creates a slot holding `layer`.
+    pub(crate) fn new(layer: Option<Arc<T>>) -> Self {
+        Self(RwLock::new(layer))
+    }
+
+    /// Returns the attached layer, if any.
+    pub(crate) fn get(&self) -> Option<Arc<T>> {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Returns `true` if a layer is attached.
+    pub(crate) fn is_attached(&self) -> bool {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).is_some()
+    }
+
+    /// Puts `layer` in the slot, then waits until no operation uses the
+    /// previously attached layer.
+    ///
+    /// # Returns
+    /// * `true`: If a layer was attached before
+    /// * `false`: If the slot was empty
+    pub(crate) async fn replace(&self, layer: Option<Arc<T>>) -> bool {
+        let previous = std::mem::replace(
+            &mut *self.0.write().unwrap_or_else(|e| e.into_inner()),
+            layer,
+        );
+
+        match previous {
+            Some(previous) => {
+                while Arc::strong_count(&previous) > 1 || previous.is_busy() {
+                    runtime::sleep(LOCK_RETRY_INTERVAL).await;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl OmneCache {
+    /// Changes the number of items the memory layer can hold.
+    ///
+    /// When shrinking, the least recently used entries are evicted; the rest
+    /// are kept.
+    ///
+    /// # Parameters
+    /// * `items`: The new capacity of the memory layer
+    ///
+    /// # Returns
+    /// * `true`: If the memory layer was resized
+    /// * `false`: If the memory layer is disabled
+    pub fn resize_memory(&self, items: NonZeroUsize) -> bool {
+        match self.memory() {
+            Some(memory) => {
+                lock_memory(&memory).resize(items);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Changes how long values stored from now on stay fresh.
+    ///
+    /// Values already cached keep the freshness they were stored with.
+    ///
+    /// # Parameters
+    /// * `ttl`: The new time to live, or `None` for values that never go stale
+    pub fn set_ttl(&self, ttl: Option<Duration>) {
+        *self.ttl.write().unwrap_or_else(|e| e.into_inner()) = ttl;
+    }
+
+    /// Attaches an empty memory layer created from `cfg`.
+    ///
+    /// A previously attached memory layer and its entries are discarded once
+    /// the operations using it have finished.
+    ///
+    /// # Parameters
+    /// * `cfg`: The configuration of the memory layer
+    ///
+    /// # Returns
+    /// * `Ok(())`: If the layer was attached
+    /// * `Err(ConfigurationError)`: If the configuration is invalid
+    pub async fn attach_memory(&self, cfg: MemoryCfg) -> Result<(), ConfigurationError> {
+        let memory = Arc::new(Mutex::new(LruCache::new(cfg.capacity()?)));
+        self.memory.replace(Some(memory)).await;
+
+        Ok(())
+    }
+
+    /// Detaches the memory layer, discarding its entries.
+    ///
+    /// New operations stop using the memory layer immediately; this call waits
+    /// for operations already using it to finish.
+    ///
+    /// # Returns
+    /// * `true`: If a memory layer was attached
+    /// * `false`: If there was no memory layer
+    pub async fn detach_memory(&self) -> bool {
+        self.memory.replace(None).await
+    }
+
+    /// Changes the number of items the disk layer can hold.
+    ///
+    /// The limit applies to subsequent writes; existing entries beyond it are
+    /// not removed.
+    ///
+    /// # Parameters
+    /// * `items`: The new item limit of the disk layer
+    ///
+    /// # Returns
+    /// * `true`: If the limit was changed
+    /// * `false`: If no disk layer is attached
+    pub fn set_disk_limit(&self, items: usize) -> bool {
+        match self.disk.get() {
+            Some(disk) => {
+                disk.set_limit(items);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Changes the maximum total size of the entries in the disk layer.
+    ///
+    /// Like [`OmneCache::set_disk_limit`], the limit applies to subsequent writes.
+    ///
+    /// # Parameters
+    /// * `max_bytes`: The new byte limit, or `None` for no byte limit
+    ///
+    /// # Returns
+    /// * `true`: If the limit was changed
+    /// * `false`: If no disk layer is attached
+    pub fn set_disk_max_bytes(&self, max_bytes: Option<u64>) -> bool {
+        match self.disk.get() {
+            Some(disk) => {
+                disk.set_max_bytes(max_bytes);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Attaches a disk layer created from `cfg`.
+    ///
+    /// A previously attached disk layer is detached as by
+    /// [`OmneCache::detach_disk`].
+    ///
+    /// # Parameters
+    /// * `cfg`: The configuration of the disk layer
+    ///
+    /// # Returns
+    /// * `Ok(())`: If the layer was attached
+    /// * `Err(ConfigurationError)`: If the layer failed to initialize
+    pub async fn attach_disk(&self, cfg: DiskCfg) -> Result<(), ConfigurationError> {
+        let disk = Arc::new(cfg.as_fs_cache().await?);
+        self.disk.replace(Some(disk)).await;
+
+        Ok(())
+    }
+
+    /// Detaches the disk layer.
+    ///
+    /// New operations stop using the disk layer immediately; this call waits
+    /// for operations already using it to finish. Entries on disk are kept.
+    ///
+    /// # Returns
+    /// * `true`: If a disk layer was attached
+    /// * `false`: If there was no disk layer
+    pub async fn detach_disk(&self) -> bool {
+        self.disk.replace(None).await
+    }
+
+    /// Attaches a sideload layer created from `cfg`.
+    ///
+    /// A previously attached sideload layer is detached as by
+    /// [`OmneCache::detach_sideload`].
+    ///
+    /// # Parameters
+    /// * `cfg`: The configuration of the sideload layer
+    ///
+    /// # Returns
+    /// * `Ok(())`: If the layer was attached
+    /// * `Err(ConfigurationError)`: If the layer failed to initialize
+    pub async fn attach_sideload(&self, cfg: SideloadCfg) -> Result<(), ConfigurationError> {
+        let sideload = Arc::new(cfg.as_fs_cache().await?);
+        self.sideload.replace(Some(sideload)).await;
+
+        Ok(())
+    }
+
+    /// Detaches the sideload layer.
+    ///
+    /// New operations stop using the sideload layer immediately; this call
+    /// waits for operations already using it to finish.
+    ///
+    /// # Returns
+    /// * `true`: If a sideload layer was attached
+    /// * `false`: If there was no sideload layer
+    pub async fn detach_sideload(&self) -> bool {
+        self.sideload.replace(None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use lru::LruCache;
+
+    use super::*;
+    use crate::{fs::FsCache, tests::Bytes};
+
+    fn disk_cfg(path: &std::path::Path) -> DiskCfg {
+        DiskCfg {
+            disabled: false,
+            path: Some(path.to_string_lossy().to_string()),
+            items: Some(100),
+            max_bytes: None,
+            eviction: Default::default(),
+            layout: Default::default(),
+            repair: false,
+            compression: None,
+            encryption: None,
+            backend: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resize_memory_and_set_disk_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = OmneCache::with_layers(
+            Some(Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(3).unwrap(),
+            )))),
+            None,
+            Some(Arc::new(FsCache::new_write(dir.path(), 100).await.unwrap())),
+        );
+        for key in ["a", "b", "c"] {
+            cache.put(key.to_string(), key.as_bytes()).await.unwrap();
+        }
+
+        assert!(cache.resize_memory(NonZeroUsize::new(1).unwrap()));
+        {
+            let memory = cache.memory().unwrap();
+            let memory = lock_memory(&memory);
+            assert_eq!(memory.len(), 1);
+            assert!(memory.contains("CustomString_c"));
+        }
+
+        assert!(cache.set_disk_limit(10));
+        assert_eq!(cache.disk.get().unwrap().limit(), 10);
+        assert!(cache.set_disk_max_bytes(Some(1024)));
+        assert_eq!(cache.disk.get().unwrap().max_bytes(), Some(1024));
+        assert!(cache.detach_disk().await);
+        assert!(!cache.set_disk_limit(10));
+        assert!(!cache.set_disk_max_bytes(None));
+    }
+
+    #[tokio::test]
+    async fn test_detach_disk_waits_for_in_flight_operations() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Arc::new(OmneCache::with_layers(
+            None,
+            None,
+            Some(Arc::new(FsCache::new_write(dir.path(), 100).await.unwrap())),
+        ));
+        cache.put("a".to_string(), b"alpha").await.unwrap();
+
+        // Stands in for an operation that is still using the disk layer.
+        let in_flight = cache.disk.get().unwrap();
+        let detach = tokio::spawn({
+            let cache = cache.clone();
+            async move { cache.detach_disk().await }
+        });
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!detach.is_finished());
+        assert!(cache.get("a".to_string()).await.is_err());
+
+        drop(in_flight);
+        assert!(detach.await.unwrap());
+        assert!(!cache.detach_disk().await);
+
+        cache.attach_disk(disk_cfg(dir.path())).await.unwrap();
+        assert_eq!(
+            cache.get("a".to_string()).await.unwrap(),
+            Bytes(b"alpha".to_vec())
+        );
+    }
+}
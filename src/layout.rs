@@ -0,0 +1,355 @@
+//! # OmneCache Directory Layout
+//!
+//! Where the entries of a cache directory are stored:
+//!
+//! * [`Layout::Flat`] (default): every entry is a file directly in the cache
+//!   directory, named after its key.
+//! * [`Layout::Sharded`]: entries are spread over up to 65,536 subdirectories
+//!   named after the first four hex digits of the BLAKE3 hash of their key, as
+//!   in `ab/cd/<key>`. This keeps directories small when a cache holds
+//!   millions of entries, which flat directories on ext4 or xfs handle poorly.
+//!
+//! A sharded directory is marked by a `.layout` file, so every
+//! [`FsCache`](crate::fs::FsCache) opened on it, including read-only sideload
+//! caches, finds its entries without being configured. [`migrate`] converts an
+//! existing directory from one layout to the other.
+//!
+//! # Example
+//! ```rust,no_run
+//! use omnecache::layout::{self, Layout};
+//!
+//! // With no cache using the directory:
+//! let moved = layout::migrate("/var/cache/SomeOmneCacheApp/evidence", Layout::Sharded)?;
+//! println!("moved {} entries", moved);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::{
+    io::Write,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+};
+
+use const_default::ConstDefault;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    checksum::SUM_EXTENSION,
+    fs::{LOCK_EXTENSION, META_EXTENSION, TMP_EXTENSION, list_entries, sidecar_path},
+};
+
+/// Name of the file marking a sharded cache directory
+pub(crate) const LAYOUT_FILE: &str = ".layout";
+/// Content of the marker file of a sharded cache directory
+const SHARDED_MARKER: &str = "sharded";
+/// Number of hex digits of the key hash naming each level of subdirectories
+const SHARD_DIGITS: usize = 2;
+
+/// Arrangement of the entries in a cache directory.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// Every entry directly in the cache directory
+    #[default]
+    Flat,
+    /// Entries in two levels of subdirectories named after their key's hash
+    Sharded,
+}
+
+impl ConstDefault for Layout {
+    const DEFAULT: Self = Self::Flat;
+}
+
+impl Layout {
+    /// Reads the layout of the cache directory `dir` from its marker file.
+    ///
+    /// # Returns
+    /// * `Ok(Layout)`: The layout, [`Layout::Flat`] if the directory has no marker
+    /// * `Err(std::io::Error)`: If the marker could not be read or is not recognized
+    pub fn detect(dir: impl AsRef<Path>) -> std::io::Result<Self> {
+        match std::fs::read_to_string(dir.as_ref().join(LAYOUT_FILE)) {
+            Ok(marker) if marker.trim() == SHARDED_MARKER => Ok(Self::Sharded),
+            Ok(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Unknown cache directory layout",
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::Flat),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the path of the entry for `key` in the cache directory `dir`.
+    pub(crate) fn entry_path(&self, dir: &Path, key: &str) -> PathBuf {
+        match self {
+            Self::Flat => dir.join(key),
+            Self::Sharded => {
+                let hash = blake3::hash(key.as_bytes()).to_hex();
+                let (first, rest) = hash.split_at(SHARD_DIGITS);
+
+                dir.join(first).join(&rest[..SHARD_DIGITS]).join(key)
+            }
+        }
+    }
+
+    /// Returns the directories of `dir` that hold entries.
+    pub(crate) fn entry_dirs(&self, dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+        match self {
+            Self::Flat => Ok(vec![dir.to_path_buf()]),
+            Self::Sharded => {
+                let mut dirs = Vec::new();
+
+                for shard in shard_dirs(dir)? {
+                    dirs.extend(shard_dirs(&shard)?);
+                }
+
+                Ok(dirs)
+            }
+        }
+    }
+
+    /// Makes the cache directory `dir` use this layout.
+    ///
+    /// An empty directory, or one with no entries yet, takes on the layout;
+    /// a directory with entries in the other layout must be [migrated](migrate) first.
+    ///
+    /// # Returns
+    /// * `Ok(())`: If the directory uses this layout
+    /// * `Err(std::io::Error)`: Of kind [`std::io::ErrorKind::InvalidInput`] if
+    ///   the directory holds entries in the other layout, or if the marker
+    ///   could not be read or written
+    pub(crate) fn adopt(&self, dir: &Path) -> std::io::Result<()> {
+        let current = Self::detect(dir)?;
+
+        if current == *self {
+            return Ok(());
+        }
+
+        if !list_entries(dir, current)?.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Cache directory uses the {:?} layout; migrate it before opening it as {:?}",
+                    current, self
+                ),
+            ));
+        }
+
+        self.mark(dir)
+    }
+
+    /// Writes (or removes) the marker of this layout in `dir`.
+    fn mark(&self, dir: &Path) -> std::io::Result<()> {
+        let marker_path = dir.join(LAYOUT_FILE);
+
+        match self {
+            Self::Flat => match std::fs::remove_file(marker_path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+            Self::Sharded => {
+                let tmp_path = sidecar_path(&marker_path, TMP_EXTENSION);
+                let mut tmp_file = std::fs::File::create(&tmp_path)?;
+                writeln!(tmp_file, "{}", SHARDED_MARKER)?;
+                tmp_file.sync_all()?;
+
+                std::fs::rename(tmp_path, marker_path)
+            }
+        }
+    }
+}
+
+/// Moves every entry of the cache directory `dir` into the layout `to`.
+///
+/// Entries are moved together with their metadata and checksums; their access statistics
+/// are kept, since the index does not depend on the layout. The directory is
+/// marked with the new layout once every entry has been moved, so rerunning
+/// an interrupted migration completes it.
+///
+/// No cache may use the directory during the migration, as it would look for
+/// entries in the old layout.
+///
+/// # Parameters
+/// * `dir`: The cache directory
+/// * `to`: The layout to move the entries into
+///
+/// # Returns
+/// * `Ok(usize)`: The number of entries moved
+/// * `Err(std::io::Error)`: If the directory could not be read or an entry could not be moved
+pub fn migrate(dir: impl AsRef<Path>, to: Layout) -> std::io::Result<usize> {
+    let dir = dir.as_ref();
+    let mut moved = 0;
+
+    for from in [Layout::Flat, Layout::Sharded] {
+        if from == to {
+            continue;
+        }
+
+        for entry in list_entries(dir, from)? {
+            let source = from.entry_path(dir, &entry.key);
+            let target = to.entry_path(dir, &entry.key);
+
+            if let Some(parent) = target.parent() {
+                std::fs::DirBuilder::new()
+                    .recursive(true)
+                    .mode(0o700)
+                    .create(parent)?;
+            }
+
+            // The metadata goes first: an entry whose move is interrupted is at
+            // worst left without metadata, which marks it stale.
+            for extension in [META_EXTENSION, SUM_EXTENSION] {
+                match std::fs::rename(
+                    sidecar_path(&source, extension),
+                    sidecar_path(&target, extension),
+                ) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            std::fs::rename(&source, &target)?;
+
+            for extension in [LOCK_EXTENSION, TMP_EXTENSION] {
+                let _ = std::fs::remove_file(sidecar_path(&source, extension));
+            }
+
+            moved += 1;
+        }
+    }
+
+    if to == Layout::Flat {
+        // Empty shards are left behind by the move; anything else stays.
+        for shard in shard_dirs(dir)? {
+            for leaf in shard_dirs(&shard)? {
+                let _ = std::fs::remove_dir(leaf);
+            }
+            let _ = std::fs::remove_dir(shard);
+        }
+    }
+
+    to.mark(dir)?;
+
+    Ok(moved)
+}
+
+/// Lists the subdirectories of `dir` named like a level of shards.
+fn shard_dirs(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut shards = Vec::new();
+
+    for dir_entry in std::fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+
+        let is_shard = dir_entry.file_name().to_str().is_some_and(|name| {
+            name.len() == SHARD_DIGITS
+                && name
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        });
+
+        if is_shard && dir_entry.file_type()?.is_dir() {
+            shards.push(dir_entry.path());
+        }
+    }
+
+    Ok(shards)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::unix::fs::PermissionsExt,
+        time::{Instant, SystemTime},
+    };
+
+    use super::*;
+    use crate::{
+        fs::{
+            FsCache, Read, ReadWrite,
+            tests::{stored_size, stored_value},
+        },
+        meta::EntryInfo,
+    };
+
+    async fn keys<T: crate::fs::AccessMode>(cache: &FsCache<T>) -> Vec<String> {
+        let mut keys: Vec<_> = cache
+            .entries()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.key)
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn test_sharded_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write_with_layout(dir.path(), 100, Layout::Sharded)
+            .await
+            .unwrap();
+        cache.put("key1", b"Hello").await.unwrap();
+        cache.put("key2", b"world").await.unwrap();
+
+        let file_path = Layout::Sharded.entry_path(dir.path(), "key1");
+        assert_eq!(
+            file_path.strip_prefix(dir.path()).unwrap().iter().count(),
+            3
+        );
+        assert_eq!(stored_value(file_path), b"Hello");
+        assert_eq!(keys(&cache).await, ["key1", "key2"]);
+        assert!(!cache.remove_blocking("missing", Instant::now()).unwrap());
+
+        // A cache opened without a layout finds the entries through the marker.
+        let reopened = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        assert_eq!(reopened.layout(), Layout::Sharded);
+        assert_eq!(reopened.get("key2").await, Some(b"world".to_vec()));
+
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o555)).unwrap();
+        let sideload = FsCache::<Read>::new_read(dir.path()).await.unwrap();
+        assert_eq!(sideload.get("key1").await, Some(b"Hello".to_vec()));
+        assert_eq!(keys(&sideload).await, ["key1", "key2"]);
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o700)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_migrate() {
+        let dir = tempfile::tempdir().unwrap();
+        let info = EntryInfo::new(Some("v1".to_string()), SystemTime::now(), None);
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        cache.put_with_info("key1", b"Hello", &info).await.unwrap();
+        cache.put("key2", b"world").await.unwrap();
+        drop(cache);
+
+        let opened =
+            FsCache::<ReadWrite>::new_write_with_layout(dir.path(), 100, Layout::Sharded).await;
+        assert_eq!(
+            opened.err().unwrap().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+
+        assert_eq!(migrate(dir.path(), Layout::Sharded).unwrap(), 2);
+        assert_eq!(migrate(dir.path(), Layout::Sharded).unwrap(), 0);
+
+        let cache = FsCache::<ReadWrite>::new_write_with_layout(dir.path(), 100, Layout::Sharded)
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.get_with_info("key1").await,
+            Some((b"Hello".to_vec(), info))
+        );
+        assert_eq!(
+            cache.used_bytes().unwrap(),
+            stored_size("key1", 5) + stored_size("key2", 5)
+        );
+        drop(cache);
+
+        assert_eq!(migrate(dir.path(), Layout::Flat).unwrap(), 2);
+        assert_eq!(Layout::detect(dir.path()).unwrap(), Layout::Flat);
+        assert_eq!(stored_value(dir.path().join("key2")), b"world");
+        assert!(shard_dirs(dir.path()).unwrap().is_empty());
+    }
+}
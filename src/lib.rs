@@ -24,6 +24,14 @@
 //! * Define custom paths for disk and sideload caches
 //! * Load and save configurations from/to TOML files
 //!
+//! ## Async Runtimes
+//!
+//! Blocking filesystem work, timeouts and background tasks are delegated to the
+//! runtime selected by cargo features: `tokio` (the default) or `smol`. The
+//! `smol` backend does not depend on the caller's executor, so it also serves
+//! async-std and other runtimes; enable it with `default-features = false,
+//! features = ["smol"]`. The [`blocking`] module needs no runtime at all.
+//!
 //! ## Example Usage
 //!
 //! To use OmneCache, implement the [`Cacheable`] trait for your data type and
@@ -43,28 +51,111 @@
 //!         disabled: false,
 //!         path: Some("/var/cache/SomeOmneCacheApp/evidence".into()),
 //!         items: Some(10000),
+//!         max_bytes: None,
+//!         eviction: Default::default(),
+//!         layout: Default::default(),
+//!         repair: false,
+//!         compression: None,
+//!         encryption: None,
+//!         backend: Default::default(),
 //!     }),
 //!     sideload: Some(SideloadCfg {
 //!         disabled: false,
 //!         path: Some("/var/sideload/SomeOmneCacheApp/evidence".into()),
 //!         items: Some(5000),
 //!     }),
+//!     ttl: None,
 //! };
 //! ```
 
+/// Synchronous cache API for consumers without an async runtime
+pub mod blocking;
+/// Checksums verifying disk and sideload entries on read
+pub mod checksum;
+/// Compression of disk entries
+pub mod compression;
 /// Configuration components for OmneCache's storage layers
 pub mod configuration;
+/// Encryption of disk entries at rest
+pub mod encryption;
 /// Error types for OmneCache operations
 pub mod error;
+/// Eviction policies of the disk layer
+pub mod eviction;
+/// On-disk form of disk entries
+pub mod format;
 /// File system operations for OmneCache
 pub mod fs;
+/// Persistent index of the disk layer's entries
+mod index;
+/// Runtime resizing, attaching and detaching of cache layers
+mod layers;
+/// Arrangement of entries in cache directories
+pub mod layout;
+/// Enumeration of cached entries across layers
+pub mod listing;
+/// Cross-process locking of cache entries
+pub mod lock;
+/// Memory-mapped reads of large entries
+pub mod mapped;
+/// Metadata and versions of cached entries
+pub mod meta;
+/// Per-call cache control options
+pub mod options;
+/// Log-structured storage of disk entries in segment files
+pub mod pack;
+/// Live reloading of the cache configuration
+pub mod reload;
 /// Result type for OmneCache
 pub mod result;
+/// Executor services provided by the selected async runtime
+mod runtime;
+/// Snapshot export and import of the cache as an archive
+pub mod snapshot;
+/// Size and capacity statistics of the cache layers
+pub mod stats;
+/// Background warming of the memory layer
+pub mod warm;
+
+use std::{
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
 
 use crate::error::*;
 use configuration::OmneCacheCfg;
 use fs::{FsCache, Read, ReadWrite};
+use layers::LayerSlot;
 use lru::LruCache;
+use meta::{EntryInfo, EntryMeta, EntryVersion};
+use options::CacheOptions;
+use serde::{Deserialize, Serialize};
+
+/// In-memory LRU layer shared between the cache and its background tasks
+type MemoryLayer = Arc<Mutex<LruCache<String, MemoryEntry>>>;
+
+/// A value held by the memory layer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MemoryEntry {
+    /// The cached bytes
+    pub(crate) data: Vec<u8>,
+    /// Freshness and revalidation metadata of the bytes
+    pub(crate) info: EntryInfo,
+}
+
+/// Result of asking the origin whether a cached value is still current.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Revalidation {
+    /// The cached value is still current and may be served again
+    NotModified,
+    /// The value changed; the new bytes replace the cached value
+    Modified {
+        /// The current value
+        data: Vec<u8>,
+        /// Validator for the current value, if the origin supplied one
+        validator: Option<String>,
+    },
+}
 
 /// Trait for types which can be retrieved from an external source and stored in a [`OmneCache`].
 ///
@@ -76,8 +167,9 @@ use lru::LruCache;
 /// from its original source, as well as a method to deserialize the data
 /// from the cache.
 ///
-/// **Note**: This trait inherits its types from [`Cacheable`]. It is not automatically
-/// used by `OmneCache.get` - you would need to manually call `fetch()` when handling cache misses.
+/// **Note**: This trait inherits its types from [`Cacheable`]. It is not used by
+/// `OmneCache.get`; [`OmneCache::get_or_fetch`] calls it on cache misses and to
+/// revalidate stale values.
 ///
 pub trait Request: Cacheable {
     /// Downloads the data from its original external source.
@@ -85,6 +177,37 @@ pub trait Request: Cacheable {
     /// This method is called when there is a cache miss in all layers
     /// and the data needs to be fetched from its authoritative source.
     fn fetch(&self) -> impl std::future::Future<Output = Result<Vec<u8>, Self::Error>>;
+
+    /// Downloads the data together with a validator for it.
+    ///
+    /// The validator is an opaque string, such as an HTTP `ETag` or
+    /// `Last-Modified` value, that [`Request::revalidate`] later receives to
+    /// check whether the data changed. The default implementation calls
+    /// [`Request::fetch`] and supplies no validator.
+    fn fetch_with_validator(
+        &self,
+    ) -> impl std::future::Future<Output = Result<(Vec<u8>, Option<String>), Self::Error>> {
+        async move { Ok((self.fetch().await?, None)) }
+    }
+
+    /// Checks with the original source whether the data behind `validator` changed.
+    ///
+    /// This is called by [`OmneCache::get_or_fetch`] when a cached value is no
+    /// longer fresh but has a validator, and allows an unchanged value to be kept
+    /// without downloading it again. The default implementation cannot perform a
+    /// conditional request, so it downloads the data with [`Request::fetch`].
+    fn revalidate(
+        &self,
+        validator: &str,
+    ) -> impl std::future::Future<Output = Result<Revalidation, Self::Error>> {
+        let _ = validator;
+        async move {
+            Ok(Revalidation::Modified {
+                data: self.fetch().await?,
+                validator: None,
+            })
+        }
+    }
 }
 
 /// Trait for types that can be cached by OmneCache.
@@ -118,6 +241,150 @@ pub trait Cacheable: Clone + Send + Sync {
     fn key(&self) -> impl std::future::Future<Output = String>;
 }
 
+/// Builds the complete cache key for an entry in the format "PREFIX_key".
+///
+/// This is the single place where the key layout is defined, so every layer
+/// and every helper (such as [`warm::WarmStrategy::keys`]) agree on it.
+pub(crate) async fn cache_key<C: Cacheable>(entry: &C) -> String {
+    format!("{}_{}", C::PREFIX, entry.key().await)
+}
+
+/// Locks the memory layer.
+///
+/// A poisoned lock is recovered rather than propagated: the LRU holds plain
+/// byte buffers, so a panic in another holder cannot leave it inconsistent.
+pub(crate) fn lock_memory(memory: &MemoryLayer) -> MutexGuard<'_, LruCache<String, MemoryEntry>> {
+    memory.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Identifies one of the layers of an [`OmneCache`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layer {
+    /// The in-memory LRU layer
+    Memory,
+    /// The read-only sideload layer
+    Sideload,
+    /// The persistent disk layer
+    Disk,
+}
+
+impl Layer {
+    /// Every layer, in the order [`OmneCache::get`] consults them
+    pub const ALL: [Layer; 3] = [Layer::Memory, Layer::Sideload, Layer::Disk];
+
+    /// Returns the lowercase name of the layer, as used in configuration and archives.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Memory => "memory",
+            Self::Sideload => "sideload",
+            Self::Disk => "disk",
+        }
+    }
+}
+
+impl std::fmt::Display for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for Layer {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> std::io::Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|layer| layer.name() == s)
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, "Unknown cache layer")
+            })
+    }
+}
+
+/// A set of cache layers that an operation applies to.
+///
+/// # Example
+/// ```rust
+/// use omnecache::{Layer, LayerSelector};
+///
+/// let selector = LayerSelector::ALL.without(Layer::Sideload);
+/// assert!(selector.contains(Layer::Disk));
+/// assert!(!selector.contains(Layer::Sideload));
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LayerSelector {
+    memory: bool,
+    sideload: bool,
+    disk: bool,
+}
+
+impl LayerSelector {
+    /// Selects every layer
+    pub const ALL: Self = Self {
+        memory: true,
+        sideload: true,
+        disk: true,
+    };
+
+    /// Selects no layer
+    pub const NONE: Self = Self {
+        memory: false,
+        sideload: false,
+        disk: false,
+    };
+
+    /// Selects a single layer.
+    pub const fn only(layer: Layer) -> Self {
+        Self::NONE.with(layer)
+    }
+
+    /// Returns a copy of this selection that also includes `layer`.
+    pub const fn with(self, layer: Layer) -> Self {
+        self.set(layer, true)
+    }
+
+    /// Returns a copy of this selection that excludes `layer`.
+    pub const fn without(self, layer: Layer) -> Self {
+        self.set(layer, false)
+    }
+
+    /// Returns `true` if `layer` is part of the selection.
+    pub const fn contains(&self, layer: Layer) -> bool {
+        match layer {
+            Layer::Memory => self.memory,
+            Layer::Sideload => self.sideload,
+            Layer::Disk => self.disk,
+        }
+    }
+
+    const fn set(mut self, layer: Layer, selected: bool) -> Self {
+        match layer {
+            Layer::Memory => self.memory = selected,
+            Layer::Sideload => self.sideload = selected,
+            Layer::Disk => self.disk = selected,
+        }
+        self
+    }
+}
+
+impl From<Layer> for LayerSelector {
+    fn from(layer: Layer) -> Self {
+        Self::only(layer)
+    }
+}
+
+/// Summary of an [`OmneCache::clear`] call.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ClearReport {
+    /// Entries removed from the memory layer
+    pub memory: usize,
+    /// Entries removed from the disk layer
+    pub disk: usize,
+    /// Selected layers that cannot be cleared, such as the read-only sideload layer
+    pub not_clearable: Vec<Layer>,
+}
+
 /// Multi-layer caching system for byte-oriented data.
 ///
 /// OmneCache provides a hierarchical caching system with three optional layers:
@@ -130,13 +397,16 @@ pub trait Cacheable: Clone + Send + Sync {
 /// is returned.
 pub struct OmneCache {
     /// In-memory LRU cache for fast access to recently used items
-    memory: Option<LruCache<String, Vec<u8>>>,
+    memory: LayerSlot<Mutex<LruCache<String, MemoryEntry>>>,
 
     /// Path to the sideloaded content directory
-    sideload: Option<FsCache<Read>>,
+    sideload: LayerSlot<FsCache<Read>>,
 
     /// Path to the disk cache directory
-    disk: Option<FsCache<ReadWrite>>,
+    disk: LayerSlot<FsCache<ReadWrite>>,
+
+    /// How long stored values stay fresh before `get_or_fetch` revalidates them
+    ttl: RwLock<Option<Duration>>,
 }
 
 impl OmneCache {
@@ -164,27 +434,42 @@ impl OmneCache {
     pub async fn try_from(cfg: OmneCacheCfg) -> Result<Self, ConfigurationError> {
         // Memory cache initialization
         let memory = match cfg.memory {
-            Some(memory) if !memory.disabled => Some(memory.lru_cache().await?),
+            Some(memory) if !memory.disabled => {
+                Some(Arc::new(Mutex::new(LruCache::new(memory.capacity()?))))
+            }
             _ => None,
         };
 
         // Sideload cache initialization
         let sideload = match cfg.sideload {
-            Some(s) => Some(s.as_fs_cache().await?),
+            Some(s) => Some(Arc::new(s.as_fs_cache().await?)),
             _ => None,
         };
 
         // Disk cache initialization
         let disk = match cfg.disk {
-            Some(d) => Some(d.as_fs_cache().await?),
+            Some(d) => Some(Arc::new(d.as_fs_cache().await?)),
             _ => None,
         };
 
-        Ok(Self {
-            memory,
-            disk,
-            sideload,
-        })
+        let cache = Self::with_layers(memory, sideload, disk);
+        cache.set_ttl(cfg.ttl.map(Duration::from_secs));
+
+        Ok(cache)
+    }
+
+    /// Assembles a cache from already initialized layers, with no TTL.
+    pub(crate) fn with_layers(
+        memory: Option<MemoryLayer>,
+        sideload: Option<Arc<FsCache<Read>>>,
+        disk: Option<Arc<FsCache<ReadWrite>>>,
+    ) -> Self {
+        Self {
+            memory: LayerSlot::new(memory),
+            sideload: LayerSlot::new(sideload),
+            disk: LayerSlot::new(disk),
+            ttl: RwLock::new(None),
+        }
     }
 
     /// Builds a complete cache key by combining the type prefix with the instance key.
@@ -198,7 +483,17 @@ impl OmneCache {
     /// # Returns
     /// A string containing the complete cache key
     async fn build_key<C: Cacheable>(&self, entry: C) -> String {
-        format!("{}_{}", C::PREFIX, entry.key().await)
+        cache_key(&entry).await
+    }
+
+    /// Returns the memory layer, if it is enabled.
+    fn memory(&self) -> Option<MemoryLayer> {
+        self.memory.get()
+    }
+
+    /// Returns how long stored values stay fresh, if they expire at all.
+    fn ttl(&self) -> Option<Duration> {
+        *self.ttl.read().unwrap_or_else(|e| e.into_inner())
     }
 
     /// Attempts to retrieve the requested data from the cache.
@@ -216,41 +511,131 @@ impl OmneCache {
     /// # Returns
     /// * `Ok(C::Value)`: The successfully retrieved and deserialized value
     /// * `Err(C::Error)`: If retrieval or deserialization failed, including when data is not found in any cache
-    pub async fn get<C: Cacheable>(&mut self, entry: C) -> Result<C::Value, C::Error> {
+    pub async fn get<C: Cacheable>(&self, entry: C) -> Result<C::Value, C::Error> {
+        self.get_with(entry, &CacheOptions::default()).await
+    }
+
+    /// Attempts to retrieve the requested data, as controlled by `options`.
+    ///
+    /// Skipped layers are neither consulted nor populated, and disk and sideload
+    /// reads give up once the call's deadline has passed. A value found in a
+    /// lower layer is still promoted into memory unless memory is skipped.
+    ///
+    /// # Parameters
+    /// * `entry`: The Cacheable object that identifies the needed data
+    /// * `options`: Per-call cache control
+    ///
+    /// # Returns
+    /// * `Ok(C::Value)`: The successfully retrieved and deserialized value
+    /// * `Err(C::Error)`: If retrieval or deserialization failed, including when data is not found in any used layer
+    pub async fn get_with<C: Cacheable>(
+        &self,
+        entry: C,
+        options: &CacheOptions,
+    ) -> Result<C::Value, C::Error> {
         let key: String = self.build_key(entry).await;
 
-        // Check if the memory cache was enabled during construction. If so, check if the data is in memory.
-        if let Some(memory) = &mut self.memory {
-            if let Some(data) = memory.get(&key) {
-                return C::Value::try_from(data.clone());
+        match self.lookup(&key, options, options.lock_deadline()).await {
+            Some((data, _, _)) => C::Value::try_from(data),
+            None => Err(C::Error::from(CacheableError::NotFound)),
+        }
+    }
+
+    /// Retrieves the requested data together with metadata describing it.
+    ///
+    /// This follows the same retrieval sequence as [`OmneCache::get`]. The returned
+    /// [`EntryMeta`] names the layer that served the value and carries its
+    /// [`EntryVersion`], which can be passed to [`OmneCache::compare_and_swap`].
+    ///
+    /// # Parameters
+    /// * `entry`: The Cacheable object that identifies the needed data
+    ///
+    /// # Returns
+    /// * `Ok((C::Value, EntryMeta))`: The deserialized value and its metadata
+    /// * `Err(C::Error)`: If retrieval or deserialization failed, including when data is not found in any cache
+    pub async fn get_with_meta<C: Cacheable>(
+        &self,
+        entry: C,
+    ) -> Result<(C::Value, EntryMeta), C::Error> {
+        let key: String = self.build_key(entry).await;
+        let options = CacheOptions::default();
+
+        match self.lookup(&key, &options, options.lock_deadline()).await {
+            Some((data, layer, _)) => {
+                let meta = EntryMeta::new(layer, &data);
+                Ok((C::Value::try_from(data)?, meta))
             }
+            None => Err(C::Error::from(CacheableError::NotFound)),
+        }
+    }
+
+    /// Looks a complete key up in every enabled layer that `options` uses, in order.
+    ///
+    /// Values found in the sideload or disk layer are promoted into memory,
+    /// unless the memory layer is skipped.
+    ///
+    /// # Returns
+    /// * `Some((Vec<u8>, Layer, EntryInfo))`: The raw value, the layer that held it and its metadata
+    /// * `None`: If no used layer holds the key
+    async fn lookup(
+        &self,
+        key: &str,
+        options: &CacheOptions,
+        deadline: Instant,
+    ) -> Option<(Vec<u8>, Layer, EntryInfo)> {
+        // Check if the memory cache was enabled during construction. If so, check if the data is in memory.
+        if options.uses(Layer::Memory)
+            && let Some(memory) = self.memory()
+            && let Some(entry) = lock_memory(&memory).get(key)
+        {
+            return Some((entry.data.clone(), Layer::Memory, entry.info.clone()));
         }
 
         // Check if the sideload cache was enabled during construction. If so, check if the data is in the sideload cache.
-        if let Some(sideload) = &self.sideload {
-            if let Some(data) = sideload.get(&key).await {
-                // If the data is found in the sideload cache, but it wasn't found in memory, and the memory cache is enabled, write it to memory.
-                if let Some(memory) = &mut self.memory {
-                    memory.put(key.clone(), data.clone());
-                }
-
-                return C::Value::try_from(data);
+        if options.uses(Layer::Sideload)
+            && let Some(sideload) = self.sideload.get()
+            && let Some((data, info)) = sideload.read_with_info(key, deadline, true).await
+        {
+            // If the data is found in the sideload cache, but it wasn't found in memory, and the memory cache is enabled, write it to memory.
+            if options.uses(Layer::Memory) {
+                self.remember(key, &data, &info);
             }
+
+            return Some((data, Layer::Sideload, info));
         }
 
         // Check if the disk cache was enabled during construction. If so, check if the data is in the disk cache.
-        if let Some(disk) = &self.disk {
-            if let Some(data) = disk.get(&key).await {
-                // If the data is found in the disk cache, but it wasn't found in memory, and the memory cache is enabled, write it to memory.
-                if let Some(memory) = &mut self.memory {
-                    memory.put(key.clone(), data.clone());
-                }
-
-                return C::Value::try_from(data);
+        if options.uses(Layer::Disk)
+            && let Some(disk) = self.disk.get()
+            && let Some((data, info)) = disk.read_with_info(key, deadline, true).await
+        {
+            // If the data is found in the disk cache, but it wasn't found in memory, and the memory cache is enabled, write it to memory.
+            if options.uses(Layer::Memory) {
+                self.remember(key, &data, &info);
             }
+
+            return Some((data, Layer::Disk, info));
         }
 
-        Err(C::Error::from(CacheableError::NotFound))
+        None
+    }
+
+    /// Stores a value in the memory layer, if it is enabled.
+    fn remember(&self, key: &str, data: &[u8], info: &EntryInfo) {
+        if let Some(memory) = self.memory() {
+            lock_memory(&memory).put(
+                key.to_string(),
+                MemoryEntry {
+                    data: data.to_vec(),
+                    info: info.clone(),
+                },
+            );
+        }
+    }
+
+    /// Builds the metadata of a value stored now without a validator.
+    fn fresh_info(&self, validator: Option<String>) -> EntryInfo {
+        EntryInfo::new(validator, SystemTime::now(), self.ttl())
     }
 
     /// Stores data in the cache for later retrieval.
@@ -272,39 +657,422 @@ impl OmneCache {
     /// This method returns a `WriteError` if:
     /// - No cache layers are enabled (both memory and disk caches are None)
     /// - Writing to the enabled cache layers fails
-    pub async fn put<C: Cacheable>(&mut self, entry: C, value: &[u8]) -> Result<(), C::Error> {
+    pub async fn put<C: Cacheable>(&self, entry: C, value: &[u8]) -> Result<(), C::Error> {
+        self.put_with(entry, value, &CacheOptions::default()).await
+    }
+
+    /// Stores data in the cache, as controlled by `options`.
+    ///
+    /// Skipped layers are not written, and with [`CacheOptions::no_store`] the
+    /// value is kept in memory only. The disk write gives up once the call's
+    /// deadline has passed.
+    ///
+    /// # Parameters
+    /// * `entry`: The Cacheable object that provides the key for the data
+    /// * `value`: The byte data to store in the cache
+    /// * `options`: Per-call cache control
+    ///
+    /// # Returns
+    /// * `Ok(())`: If the data was successfully stored in at least one cache layer
+    /// * `Err(C::Error)`: A `WriteError` if no enabled layer may be written, or the
+    ///   error of a failed write
+    pub async fn put_with<C: Cacheable>(
+        &self,
+        entry: C,
+        value: &[u8],
+        options: &CacheOptions,
+    ) -> Result<(), C::Error> {
+        let key: String = self.build_key(entry).await;
+        let info = self.fresh_info(None);
+
+        Ok(self
+            .store(&key, value, &info, options, options.lock_deadline())
+            .await?)
+    }
+
+    /// Returns `true` if `options` allow writing to at least one enabled layer.
+    fn writable(&self, options: &CacheOptions) -> bool {
+        (self.memory.is_attached() && options.uses(Layer::Memory))
+            || (self.disk.is_attached() && options.stores_on_disk())
+    }
+
+    /// Stores a value and its metadata in the memory and disk layers.
+    ///
+    /// The memory layer is written first, then the disk layer if it is enabled
+    /// and `options` allow it. When [`CacheOptions::no_store`] keeps the value
+    /// off disk, an older disk entry for the key is removed, so it cannot be
+    /// served once memory no longer holds the value.
+    async fn store(
+        &self,
+        key: &str,
+        value: &[u8],
+        info: &EntryInfo,
+        options: &CacheOptions,
+        deadline: Instant,
+    ) -> result::Result<()> {
+        if !self.writable(options) {
+            return Err(CacheableError::WriteError);
+        }
+
+        if options.uses(Layer::Memory) {
+            self.remember(key, value, info);
+        }
+
+        if options.stores_on_disk()
+            && let Some(disk) = self.disk.get()
+        {
+            disk.write(key, value, fs::Condition::Always, Some(info), deadline)
+                .await?;
+        } else if options.no_store
+            && options.uses(Layer::Disk)
+            && let Some(disk) = self.disk.get()
+        {
+            disk.discard(key, deadline).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Retrieves the requested data, fetching it from its source when needed.
+    ///
+    /// Cached values are served while they are fresh, as configured by
+    /// [`OmneCacheCfg::ttl`]; without a TTL every cached value is fresh. When a
+    /// value from the memory or disk layer is no longer fresh:
+    ///
+    /// * If it has a validator, [`Request::revalidate`] is asked whether it
+    ///   changed. If not, the value's freshness is extended without rewriting the
+    ///   stored entry; otherwise the new bytes replace it.
+    /// * Without a validator, the value is fetched again.
+    ///
+    /// Sideloaded values are curated ahead of time and are always served as-is.
+    /// On a miss the value is fetched with [`Request::fetch_with_validator`] and
+    /// stored together with its validator.
+    ///
+    /// # Parameters
+    /// * `request`: The Request object that identifies and can fetch the needed data
+    ///
+    /// # Returns
+    /// * `Ok(R::Value)`: The cached, revalidated or freshly fetched value
+    /// * `Err(R::Error)`: If fetching, storing or deserialization failed
+    pub async fn get_or_fetch<R: Request>(&self, request: R) -> Result<R::Value, R::Error> {
+        self.get_or_fetch_with(request, &CacheOptions::default())
+            .await
+    }
+
+    /// Retrieves the requested data like [`OmneCache::get_or_fetch`], as controlled by `options`.
+    ///
+    /// * [`CacheOptions::force_refresh`] ignores cached values and always fetches.
+    /// * [`CacheOptions::only_if_cached`] never contacts the origin: cached values
+    ///   are served even when stale, and a miss is reported as `NotFound`.
+    /// * Skipped layers are neither consulted nor written, and with
+    ///   [`CacheOptions::no_store`] fetched values are not persisted to disk.
+    ///
+    /// If no enabled layer may be written, fetched values are returned without
+    /// being cached.
+    ///
+    /// # Parameters
+    /// * `request`: The Request object that identifies and can fetch the needed data
+    /// * `options`: Per-call cache control
+    ///
+    /// # Returns
+    /// * `Ok(R::Value)`: The cached, revalidated or freshly fetched value
+    /// * `Err(R::Error)`: If fetching, storing or deserialization failed, or the
+    ///   value is not cached and `only_if_cached` is set
+    pub async fn get_or_fetch_with<R: Request>(
+        &self,
+        request: R,
+        options: &CacheOptions,
+    ) -> Result<R::Value, R::Error> {
+        let key: String = self.build_key(request.clone()).await;
+        let deadline = options.lock_deadline();
+        let now = SystemTime::now();
+
+        let cached = if options.force_refresh {
+            None
+        } else {
+            self.lookup(&key, options, deadline).await
+        };
+
+        if let Some((data, layer, info)) = cached {
+            if options.only_if_cached
+                || self.ttl().is_none()
+                || layer == Layer::Sideload
+                || info.is_fresh(now)
+            {
+                return R::Value::try_from(data);
+            }
+
+            if let Some(validator) = info.validator {
+                match request.revalidate(&validator).await? {
+                    Revalidation::NotModified => {
+                        let info = EntryInfo::new(Some(validator), now, self.ttl());
+                        self.refresh(&key, &data, &info, options, deadline).await?;
+                        return R::Value::try_from(data);
+                    }
+                    Revalidation::Modified { data, validator } => {
+                        let info = EntryInfo::new(validator, now, self.ttl());
+                        if self.writable(options) {
+                            self.store(&key, &data, &info, options, deadline).await?;
+                        }
+                        return R::Value::try_from(data);
+                    }
+                }
+            }
+        }
+
+        if options.only_if_cached {
+            return Err(R::Error::from(CacheableError::NotFound));
+        }
+
+        let (data, validator) = request.fetch_with_validator().await?;
+
+        if self.writable(options) {
+            let info = EntryInfo::new(validator, now, self.ttl());
+            self.store(&key, &data, &info, options, deadline).await?;
+        }
+
+        R::Value::try_from(data)
+    }
+
+    /// Replaces the metadata of a cached value without rewriting the value.
+    async fn refresh(
+        &self,
+        key: &str,
+        data: &[u8],
+        info: &EntryInfo,
+        options: &CacheOptions,
+        deadline: Instant,
+    ) -> result::Result<()> {
+        if options.uses(Layer::Memory) {
+            if let Some(memory) = self.memory()
+                && let Some(entry) = lock_memory(&memory).get_mut(key)
+            {
+                entry.info = info.clone();
+            } else {
+                self.remember(key, data, info);
+            }
+        }
+
+        if options.stores_on_disk()
+            && let Some(disk) = self.disk.get()
+        {
+            disk.update_info(key, info, deadline).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Stores data only if no cache layer holds the key yet.
+    ///
+    /// When the disk layer is enabled it is authoritative: the existence check
+    /// and the write happen under the key's exclusive file lock (see
+    /// [`FsCache::put_if_absent`]), so two workers racing to store the same value,
+    /// even in different processes, cannot both succeed. Without a disk layer the
+    /// check and insert are atomic with respect to the memory layer's lock.
+    ///
+    /// # Parameters
+    /// * `entry`: The Cacheable object that provides the key for the data
+    /// * `value`: The byte data to store in the cache
+    ///
+    /// # Returns
+    /// * `Ok(true)`: If the data was stored
+    /// * `Ok(false)`: If some layer already held the key and nothing was written
+    /// * `Err(C::Error)`: If no writable layer is enabled or the write failed
+    pub async fn put_if_absent<C: Cacheable>(
+        &self,
+        entry: C,
+        value: &[u8],
+    ) -> Result<bool, C::Error> {
         let key: String = self.build_key(entry).await;
+        let options = CacheOptions::default();
+        let deadline = options.lock_deadline();
+
+        if self.lookup(&key, &options, deadline).await.is_some() {
+            return Ok(false);
+        }
+
+        let info = self.fresh_info(None);
 
-        // Use a sequential approach that prioritizes memory cache first
+        if let Some(disk) = self.disk.get() {
+            if !disk
+                .write(&key, value, fs::Condition::Absent, Some(&info), deadline)
+                .await?
+            {
+                return Ok(false);
+            }
+
+            self.remember(&key, value, &info);
+            return Ok(true);
+        }
+
+        if let Some(memory) = self.memory() {
+            let mut memory = lock_memory(&memory);
+            if memory.contains(&key) {
+                return Ok(false);
+            }
+
+            memory.put(
+                key,
+                MemoryEntry {
+                    data: value.to_vec(),
+                    info,
+                },
+            );
+            return Ok(true);
+        }
+
+        Err(CacheableError::WriteError)?
+    }
+
+    /// Replaces a stored value only if its current version matches `expected`.
+    ///
+    /// `expected` is typically the [`EntryMeta::version`] returned by
+    /// [`OmneCache::get_with_meta`]. When the disk layer is enabled, the
+    /// comparison and replacement happen under the key's exclusive file lock
+    /// (see [`FsCache::compare_and_swap`]); otherwise they are atomic with
+    /// respect to the memory layer's lock. Values served from the read-only
+    /// sideload layer cannot be swapped.
+    ///
+    /// If the swap fails, the memory layer is refreshed from disk so that a
+    /// following [`OmneCache::get_with_meta`] observes the current version.
+    ///
+    /// # Parameters
+    /// * `entry`: The Cacheable object that provides the key for the data
+    /// * `expected`: The version the stored value must currently have
+    /// * `value`: The byte data to store in the cache
+    ///
+    /// # Returns
+    /// * `Ok(true)`: If the value matched and was replaced
+    /// * `Ok(false)`: If the value is missing or was changed by another writer
+    /// * `Err(C::Error)`: If no writable layer is enabled or the write failed
+    pub async fn compare_and_swap<C: Cacheable>(
+        &self,
+        entry: C,
+        expected: &EntryVersion,
+        value: &[u8],
+    ) -> Result<bool, C::Error> {
+        let key: String = self.build_key(entry).await;
 
-        // Check if the memory cache was enabled during construction. If so, write to the memory cache.
-        if let Some(memory) = &mut self.memory {
-            memory.put(key.clone(), value.to_vec());
+        let info = self.fresh_info(None);
 
-            // If disk cache is also enabled, asynchronously update it without waiting
-            if let Some(disk) = &self.disk {
-                // Clone needed data for the async task
-                let key_clone = key.clone();
-                let value_vec = value.to_vec();
-                let disk_clone = disk;
+        if let Some(disk) = self.disk.get() {
+            let swapped = disk
+                .write(
+                    &key,
+                    value,
+                    fs::Condition::Matches(*expected),
+                    Some(&info),
+                    fs::default_deadline(),
+                )
+                .await?;
 
-                disk_clone.put(&key_clone, &value_vec).await?
+            if swapped {
+                self.remember(&key, value, &info);
+            } else if let Some(memory) = self.memory() {
+                match disk.get_with_info(&key).await {
+                    Some((current, info)) => self.remember(&key, &current, &info),
+                    None => {
+                        lock_memory(&memory).pop(&key);
+                    }
+                }
             }
 
-            return Ok(());
+            return Ok(swapped);
         }
 
-        // If memory cache is disabled, write to the disk cache.
-        if let Some(disk) = &self.disk {
-            return Ok(disk.put(&key, value).await?);
+        if let Some(memory) = self.memory() {
+            let mut memory = lock_memory(&memory);
+            if memory
+                .peek(&key)
+                .map(|current| EntryVersion::of(&current.data))
+                != Some(*expected)
+            {
+                return Ok(false);
+            }
+
+            memory.put(
+                key,
+                MemoryEntry {
+                    data: value.to_vec(),
+                    info,
+                },
+            );
+            return Ok(true);
         }
 
         Err(CacheableError::WriteError)?
     }
+
+    /// Removes the requested data from the memory and disk layers.
+    ///
+    /// The disk entry is removed under its per-key exclusive lock (see
+    /// [`FsCache::remove`]). Sideloaded content is read-only and is never removed.
+    ///
+    /// # Parameters
+    /// * `entry`: The Cacheable object that identifies the data
+    ///
+    /// # Returns
+    /// * `Ok(true)`: If the memory or disk layer held the data
+    /// * `Ok(false)`: If neither layer held it
+    /// * `Err(C::Error)`: If the disk entry could not be removed
+    pub async fn remove<C: Cacheable>(&self, entry: C) -> Result<bool, C::Error> {
+        let key: String = self.build_key(entry).await;
+
+        let in_memory = self
+            .memory()
+            .is_some_and(|memory| lock_memory(&memory).pop(&key).is_some());
+
+        let on_disk = match self.disk.get() {
+            Some(disk) => disk.remove(&key).await?,
+            None => false,
+        };
+
+        Ok(in_memory || on_disk)
+    }
+
+    /// Removes every entry from the selected cache layers.
+    ///
+    /// The memory layer is emptied in place and the disk layer's directory is kept,
+    /// so a running process never loses the directory it writes to. Disk entries
+    /// are removed under their per-key locks (see [`FsCache::clear`]). The sideload
+    /// layer is read-only; selecting it is not an error, but it is listed in
+    /// [`ClearReport::not_clearable`] instead of being cleared. Disabled layers are
+    /// ignored.
+    ///
+    /// # Parameters
+    /// * `layers`: The layers to clear
+    ///
+    /// # Returns
+    /// * `Ok(ClearReport)`: How many entries were removed from each layer
+    /// * `Err(CacheableError)`: If the disk layer could not be cleared
+    pub async fn clear(&self, layers: impl Into<LayerSelector>) -> result::Result<ClearReport> {
+        let layers = layers.into();
+        let mut report = ClearReport::default();
+
+        if layers.contains(Layer::Memory)
+            && let Some(memory) = self.memory()
+        {
+            let mut memory = lock_memory(&memory);
+            report.memory = memory.len();
+            memory.clear();
+        }
+
+        if layers.contains(Layer::Sideload) && self.sideload.is_attached() {
+            report.not_clearable.push(Layer::Sideload);
+        }
+
+        if layers.contains(Layer::Disk)
+            && let Some(disk) = self.disk.get()
+        {
+            report.disk = disk.clear().await?;
+        }
+
+        Ok(report)
+    }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use std::num::NonZeroUsize;
     use std::string::String;
 
@@ -321,9 +1089,9 @@ mod tests {
         }
     }
 
-    impl ToString for Bytes {
-        fn to_string(&self) -> String {
-            String::from_utf8_lossy(&self.0).to_string()
+    impl std::fmt::Display for Bytes {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", String::from_utf8_lossy(&self.0))
         }
     }
 
@@ -333,27 +1101,29 @@ mod tests {
         type Error = CacheableError;
         type Value = Bytes;
 
-        fn key(&self) -> impl std::future::Future<Output = String> {
+        async fn key(&self) -> String {
             // Return the string directly without debug formatting
             // This ensures the key doesn't have quotes when formatted in the cache
-            async move { self.clone() }
+            self.clone()
         }
     }
 
     impl Request for String {
-        fn fetch(&self) -> impl std::future::Future<Output = Result<Vec<u8>, Self::Error>> {
-            async move { Ok(self.as_bytes().to_vec()) }
+        async fn fetch(&self) -> Result<Vec<u8>, Self::Error> {
+            Ok(self.as_bytes().to_vec())
         }
     }
 
     // Generate a test which will test key collisions.
     #[tokio::test]
     async fn test_key_collision() {
-        let mut cache = OmneCache {
-            memory: Some(LruCache::new(NonZeroUsize::new(100).unwrap())),
-            disk: None,
-            sideload: None,
-        };
+        let cache = OmneCache::with_layers(
+            Some(Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(100).unwrap(),
+            )))),
+            None,
+            None,
+        );
 
         let key1 = "key1".to_string();
         let key2 = "key2".to_string();
@@ -366,14 +1136,11 @@ mod tests {
             .await
             .unwrap();
 
-        let memory_len = cache.memory.as_ref().map(|m| m.len()).unwrap_or(0);
+        let memory_len = cache.memory().map(|m| lock_memory(&m).len()).unwrap_or(0);
         assert!(memory_len == 1);
 
         assert!(
-            cache
-                .memory
-                .as_ref()
-                .unwrap()
+            lock_memory(&cache.memory().unwrap())
                 .iter()
                 .filter(|(k, _)| **k == format!("{}_{}", String::PREFIX, key1))
                 .count()
@@ -381,12 +1148,10 @@ mod tests {
         );
 
         assert_eq!(
-            *cache
-                .memory
-                .as_mut()
-                .unwrap()
+            lock_memory(&cache.memory().unwrap())
                 .get(&format!("{}_{}", String::PREFIX, key1))
-                .unwrap(),
+                .unwrap()
+                .data,
             b"hello world!".as_slice()
         );
 
@@ -405,13 +1170,204 @@ mod tests {
         assert_eq!(something_else, Bytes(b"hello world 2!".to_vec()));
     }
 
+    #[tokio::test]
+    async fn test_clear_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = OmneCache::with_layers(
+            Some(Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(100).unwrap(),
+            )))),
+            None,
+            Some(Arc::new(FsCache::new_write(dir.path(), 100).await.unwrap())),
+        );
+
+        cache
+            .put("key1".to_string(), b"hello world!")
+            .await
+            .unwrap();
+        cache
+            .put("key2".to_string(), b"hello world!")
+            .await
+            .unwrap();
+
+        let report = cache.clear(Layer::Memory).await.unwrap();
+        assert_eq!(report.memory, 2);
+        assert_eq!(report.disk, 0);
+
+        // Still served from disk after memory was cleared.
+        assert!(cache.get("key1".to_string()).await.is_ok());
+
+        let report = cache.clear(LayerSelector::ALL).await.unwrap();
+        assert_eq!(report.memory, 1);
+        assert_eq!(report.disk, 2);
+        assert!(cache.get("key1".to_string()).await.is_err());
+        assert!(dir.path().is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = OmneCache::with_layers(
+            Some(Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(100).unwrap(),
+            )))),
+            None,
+            Some(Arc::new(FsCache::new_write(dir.path(), 100).await.unwrap())),
+        );
+
+        cache.put("key1".to_string(), b"hello").await.unwrap();
+        assert!(cache.remove("key1".to_string()).await.unwrap());
+        assert!(!cache.remove("key1".to_string()).await.unwrap());
+        assert!(cache.get("key1".to_string()).await.is_err());
+        assert!(!dir.path().join("CustomString_key1").exists());
+    }
+
+    #[tokio::test]
+    async fn test_clear_reports_sideload_not_clearable() {
+        let sideload = crate::fs::tests::read_only_fixture();
+        let cache = OmneCache::with_layers(
+            None,
+            Some(Arc::new(FsCache::new_read(sideload.path()).await.unwrap())),
+            None,
+        );
+
+        let report = cache.clear(LayerSelector::ALL).await.unwrap();
+        assert_eq!(report.not_clearable, vec![Layer::Sideload]);
+        assert!(sideload.path().join("key1").exists());
+    }
+
+    #[tokio::test]
+    async fn test_get_with_meta() {
+        let sideload = crate::fs::tests::read_only_fixture();
+        let cache = OmneCache::with_layers(
+            Some(Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(100).unwrap(),
+            )))),
+            Some(Arc::new(FsCache::new_read(sideload.path()).await.unwrap())),
+            None,
+        );
+
+        cache.put("key".to_string(), b"hello world!").await.unwrap();
+        let (value, meta) = cache.get_with_meta("key".to_string()).await.unwrap();
+
+        assert_eq!(value, Bytes(b"hello world!".to_vec()));
+        assert_eq!(meta.layer, Layer::Memory);
+        assert_eq!(meta.size, 12);
+        assert_eq!(meta.version, EntryVersion::of(b"hello world!"));
+    }
+
+    #[tokio::test]
+    async fn test_put_if_absent_races() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Arc::new(OmneCache::with_layers(
+            Some(Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(100).unwrap(),
+            )))),
+            None,
+            Some(Arc::new(FsCache::new_write(dir.path(), 100).await.unwrap())),
+        ));
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for i in 0..8 {
+            let cache = cache.clone();
+            tasks.spawn(async move {
+                cache
+                    .put_if_absent("key".to_string(), format!("worker {}", i).as_bytes())
+                    .await
+                    .unwrap()
+            });
+        }
+
+        let winners = tasks
+            .join_all()
+            .await
+            .into_iter()
+            .filter(|won| *won)
+            .count();
+        assert_eq!(winners, 1);
+    }
+
+    #[tokio::test]
+    async fn test_compare_and_swap() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = OmneCache::with_layers(
+            Some(Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(100).unwrap(),
+            )))),
+            None,
+            Some(Arc::new(FsCache::new_write(dir.path(), 100).await.unwrap())),
+        );
+
+        cache.put("key".to_string(), b"v1").await.unwrap();
+        let (_, meta) = cache.get_with_meta("key".to_string()).await.unwrap();
+
+        // Another process replaces the value behind this cache's memory layer.
+        cache
+            .disk
+            .get()
+            .unwrap()
+            .put("CustomString_key", b"v2")
+            .await
+            .unwrap();
+
+        assert!(
+            !cache
+                .compare_and_swap("key".to_string(), &meta.version, b"v3")
+                .await
+                .unwrap()
+        );
+
+        let (value, meta) = cache.get_with_meta("key".to_string()).await.unwrap();
+        assert_eq!(value, Bytes(b"v2".to_vec()));
+        assert!(
+            cache
+                .compare_and_swap("key".to_string(), &meta.version, b"v3")
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            cache.get("key".to_string()).await.unwrap(),
+            Bytes(b"v3".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compare_and_swap_memory_only() {
+        let cache = OmneCache::with_layers(
+            Some(Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(100).unwrap(),
+            )))),
+            None,
+            None,
+        );
+
+        let stale = EntryVersion::of(b"stale");
+        cache.put("key".to_string(), b"v1").await.unwrap();
+
+        assert!(
+            !cache
+                .compare_and_swap("key".to_string(), &stale, b"v2")
+                .await
+                .unwrap()
+        );
+        assert!(
+            cache
+                .compare_and_swap("key".to_string(), &EntryVersion::of(b"v1"), b"v2")
+                .await
+                .unwrap()
+        );
+        assert!(!cache.put_if_absent("key".to_string(), b"v3").await.unwrap());
+    }
+
     #[tokio::test]
     async fn test_insert_duplicate_key() {
-        let mut cache = OmneCache {
-            memory: Some(LruCache::new(NonZeroUsize::new(100).unwrap())),
-            disk: None,
-            sideload: None,
-        };
+        let cache = OmneCache::with_layers(
+            Some(Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(100).unwrap(),
+            )))),
+            None,
+            None,
+        );
 
         let key = "key".to_string();
 
@@ -420,9 +1376,9 @@ mod tests {
         assert!(test.is_err());
         cache.put("key".to_string(), b"hello world!").await.unwrap();
 
-        let memory_len = cache.memory.as_ref().map(|m| m.len()).unwrap_or(0);
+        let memory_len = cache.memory().map(|m| lock_memory(&m).len()).unwrap_or(0);
         assert_eq!(memory_len, 1);
-        assert_eq!(cache.memory.iter().len(), 1);
+        assert_eq!(cache.memory().iter().len(), 1);
 
         let _ = cache.get(key.clone()).await;
 
@@ -431,14 +1387,277 @@ mod tests {
             .await
             .unwrap();
 
-        let memory_len = cache.memory.as_ref().map(|m| m.len()).unwrap_or(0);
+        let memory_len = cache.memory().map(|m| lock_memory(&m).len()).unwrap_or(0);
         assert_eq!(memory_len, 1);
 
-        assert_eq!(cache.memory.iter().len(), 1);
+        assert_eq!(cache.memory().iter().len(), 1);
 
         assert_eq!(
             cache.get("key".to_string()).await.unwrap(),
             Bytes(b"hello world 2!".to_vec())
         );
     }
+
+    /// A request whose origin counts its calls and serves a changeable value
+    #[derive(Clone)]
+    struct Origin {
+        body: Arc<Mutex<(Vec<u8>, String)>>,
+        fetches: Arc<std::sync::atomic::AtomicUsize>,
+        revalidations: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl Origin {
+        fn new(body: &[u8], etag: &str) -> Self {
+            Self {
+                body: Arc::new(Mutex::new((body.to_vec(), etag.to_string()))),
+                fetches: Default::default(),
+                revalidations: Default::default(),
+            }
+        }
+
+        fn counts(&self) -> (usize, usize) {
+            use std::sync::atomic::Ordering::SeqCst;
+            (self.fetches.load(SeqCst), self.revalidations.load(SeqCst))
+        }
+    }
+
+    impl Cacheable for Origin {
+        const PREFIX: &'static str = "Origin";
+
+        type Error = CacheableError;
+        type Value = Bytes;
+
+        async fn key(&self) -> String {
+            "artifact".to_string()
+        }
+    }
+
+    impl Request for Origin {
+        async fn fetch(&self) -> Result<Vec<u8>, Self::Error> {
+            Ok(self.fetch_with_validator().await?.0)
+        }
+
+        async fn fetch_with_validator(&self) -> Result<(Vec<u8>, Option<String>), Self::Error> {
+            self.fetches
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let (body, etag) = self.body.lock().unwrap().clone();
+            Ok((body, Some(etag)))
+        }
+
+        async fn revalidate(&self, validator: &str) -> Result<Revalidation, Self::Error> {
+            self.revalidations
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let (body, etag) = self.body.lock().unwrap().clone();
+            if etag == validator {
+                return Ok(Revalidation::NotModified);
+            }
+            Ok(Revalidation::Modified {
+                data: body,
+                validator: Some(etag),
+            })
+        }
+    }
+
+    async fn cache_with_ttl(dir: &std::path::Path, ttl: Option<Duration>) -> OmneCache {
+        let cache = OmneCache::with_layers(
+            Some(Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(10).unwrap(),
+            )))),
+            None,
+            Some(Arc::new(FsCache::new_write(dir, 100).await.unwrap())),
+        );
+        cache.set_ttl(ttl);
+        cache
+    }
+
+    #[tokio::test]
+    async fn test_get_or_fetch_serves_fresh_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_with_ttl(dir.path(), Some(Duration::from_secs(3600))).await;
+        let origin = Origin::new(b"v1 body", "v1");
+
+        for _ in 0..2 {
+            let value = cache.get_or_fetch(origin.clone()).await.unwrap();
+            assert_eq!(value, Bytes(b"v1 body".to_vec()));
+        }
+
+        assert_eq!(origin.counts(), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_get_or_fetch_not_modified_extends_freshness() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_with_ttl(dir.path(), Some(Duration::ZERO)).await;
+        let origin = Origin::new(b"v1 body", "v1");
+        cache.get_or_fetch(origin.clone()).await.unwrap();
+
+        let entry = dir.path().join("Origin_artifact");
+        let written = std::fs::metadata(&entry).unwrap().modified().unwrap();
+
+        cache.set_ttl(Some(Duration::from_secs(3600)));
+        let value = cache.get_or_fetch(origin.clone()).await.unwrap();
+
+        assert_eq!(value, Bytes(b"v1 body".to_vec()));
+        assert_eq!(origin.counts(), (1, 1));
+        assert_eq!(
+            std::fs::metadata(&entry).unwrap().modified().unwrap(),
+            written
+        );
+
+        let (_, info) = cache
+            .disk
+            .get()
+            .unwrap()
+            .get_with_info("Origin_artifact")
+            .await
+            .unwrap();
+        assert!(info.is_fresh(SystemTime::now()));
+        assert_eq!(info.validator.as_deref(), Some("v1"));
+
+        // Fresh again, so the origin is not contacted.
+        cache.get_or_fetch(origin.clone()).await.unwrap();
+        assert_eq!(origin.counts(), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_get_or_fetch_modified_replaces_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_with_ttl(dir.path(), Some(Duration::ZERO)).await;
+        let origin = Origin::new(b"v1 body", "v1");
+        cache.get_or_fetch(origin.clone()).await.unwrap();
+
+        *origin.body.lock().unwrap() = (b"v2 body".to_vec(), "v2".to_string());
+        let value = cache.get_or_fetch(origin.clone()).await.unwrap();
+
+        assert_eq!(value, Bytes(b"v2 body".to_vec()));
+        assert_eq!(origin.counts(), (1, 1));
+
+        let (data, info) = cache
+            .disk
+            .get()
+            .unwrap()
+            .get_with_info("Origin_artifact")
+            .await
+            .unwrap();
+        assert_eq!(data, b"v2 body");
+        assert_eq!(info.validator.as_deref(), Some("v2"));
+    }
+
+    #[tokio::test]
+    async fn test_get_with_skips_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_with_ttl(dir.path(), None).await;
+        cache.put("key".to_string(), b"on disk").await.unwrap();
+        cache.remember("CustomString_key", b"in memory", &EntryInfo::default());
+
+        let skip_memory = CacheOptions {
+            skip: LayerSelector::only(Layer::Memory),
+            ..Default::default()
+        };
+        let value = cache
+            .get_with("key".to_string(), &skip_memory)
+            .await
+            .unwrap();
+        assert_eq!(value, Bytes(b"on disk".to_vec()));
+
+        // Bypassing memory must not repopulate it either.
+        assert_eq!(
+            cache.get("key".to_string()).await.unwrap(),
+            Bytes(b"in memory".to_vec())
+        );
+
+        let skip_all = CacheOptions {
+            skip: LayerSelector::ALL,
+            ..Default::default()
+        };
+        assert!(cache.get_with("key".to_string(), &skip_all).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_put_with_no_store_keeps_value_off_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_with_ttl(dir.path(), None).await;
+        let no_store = CacheOptions {
+            no_store: true,
+            ..Default::default()
+        };
+
+        cache
+            .put_with("secret".to_string(), b"token", &no_store)
+            .await
+            .unwrap();
+
+        assert!(!dir.path().join("CustomString_secret").exists());
+        assert_eq!(
+            cache.get("secret".to_string()).await.unwrap(),
+            Bytes(b"token".to_vec())
+        );
+
+        // An older disk entry does not outlive the value kept in memory.
+        cache.put("stale".to_string(), b"old").await.unwrap();
+        cache
+            .put_with("stale".to_string(), b"new", &no_store)
+            .await
+            .unwrap();
+        assert!(!dir.path().join("CustomString_stale").exists());
+        let reopened = cache_with_ttl(dir.path(), None).await;
+        assert!(reopened.get("stale".to_string()).await.is_err());
+
+        let nowhere = CacheOptions {
+            no_store: true,
+            skip: LayerSelector::only(Layer::Memory),
+            ..Default::default()
+        };
+        assert!(
+            cache
+                .put_with("secret".to_string(), b"token", &nowhere)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_or_fetch_with_cache_control() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_with_ttl(dir.path(), Some(Duration::ZERO)).await;
+        let origin = Origin::new(b"v1 body", "v1");
+        let only_if_cached = CacheOptions {
+            only_if_cached: true,
+            ..Default::default()
+        };
+
+        assert!(
+            cache
+                .get_or_fetch_with(origin.clone(), &only_if_cached)
+                .await
+                .is_err()
+        );
+        assert_eq!(origin.counts(), (0, 0));
+
+        cache.get_or_fetch(origin.clone()).await.unwrap();
+
+        // Stale, but served without contacting the origin.
+        cache
+            .get_or_fetch_with(origin.clone(), &only_if_cached)
+            .await
+            .unwrap();
+        assert_eq!(origin.counts(), (1, 0));
+
+        *origin.body.lock().unwrap() = (b"v2 body".to_vec(), "v1".to_string());
+        let force_refresh = CacheOptions {
+            force_refresh: true,
+            ..Default::default()
+        };
+        let value = cache
+            .get_or_fetch_with(origin.clone(), &force_refresh)
+            .await
+            .unwrap();
+
+        assert_eq!(value, Bytes(b"v2 body".to_vec()));
+        assert_eq!(origin.counts(), (2, 0));
+        assert_eq!(
+            crate::fs::tests::stored_value(dir.path().join("Origin_artifact")),
+            b"v2 body"
+        );
+    }
 }
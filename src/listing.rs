@@ -0,0 +1,192 @@
+//! # OmneCache Listing
+//!
+//! Enumeration of the entries held by each cache layer.
+//!
+//! [`OmneCache::entries`] walks every enabled layer in the order [`OmneCache::get`]
+//! consults them (memory, sideload, disk) and yields one [`CacheEntry`] per stored
+//! value. A key cached in several layers is reported once per layer. The internal
+//! `.lock` and `.tmp` files that [`FsCache::put`](crate::fs::FsCache::put) creates
+//! are never reported.
+//!
+//! Each layer is listed when the stream reaches it, so the result reflects the
+//! cache at that moment rather than a single point in time.
+//!
+//! Sizes are reported as each layer stores its values, so the same value is
+//! usually larger on disk than in memory; see [`CacheEntry::size`].
+
+use futures::{Stream, StreamExt, stream};
+
+use crate::{Cacheable, Layer, OmneCache, lock_memory, result::Result};
+
+/// A single value stored in one cache layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    /// Layer holding the value
+    pub layer: Layer,
+    /// Complete cache key of the entry (in the format "PREFIX_key")
+    pub key: String,
+    /// Size of the value as its layer stores it, in bytes: the length of the
+    /// value for the memory layer, and the size of the entry in its
+    /// [stored form](crate::format) (header, key, and the possibly compressed
+    /// or encrypted value) for the sideload and disk layers
+    pub size: u64,
+}
+
+impl OmneCache {
+    /// Streams every entry held by any enabled layer.
+    ///
+    /// # Returns
+    /// A stream yielding `Ok(CacheEntry)` per stored value, or an `Err` if a
+    /// filesystem layer could not be read. An error ends that layer's listing
+    /// but the following layers are still listed.
+    pub fn entries(&self) -> impl Stream<Item = Result<CacheEntry>> + '_ {
+        self.entries_with_prefix(None)
+    }
+
+    /// Streams the entries stored for the cacheable type `C`.
+    ///
+    /// Only keys built from `C::PREFIX` are yielded; see [`OmneCache::entries`].
+    pub fn keys<C: Cacheable>(&self) -> impl Stream<Item = Result<CacheEntry>> + '_ {
+        self.entries_with_prefix(Some(C::PREFIX))
+    }
+
+    /// Streams every entry, optionally restricted to keys stored under `prefix`.
+    fn entries_with_prefix(
+        &self,
+        prefix: Option<&'static str>,
+    ) -> impl Stream<Item = Result<CacheEntry>> + '_ {
+        let prefix = prefix.map(|p| format!("{}_", p));
+
+        stream::iter(Layer::ALL)
+            .then(move |layer| self.layer_entries(layer))
+            .flat_map(|listed| match listed {
+                Ok(entries) => stream::iter(entries.into_iter().map(Ok)).left_stream(),
+                Err(error) => stream::once(async { Err(error) }).right_stream(),
+            })
+            .filter(move |entry| {
+                let keep = match (entry, &prefix) {
+                    (Ok(entry), Some(prefix)) => entry.key.starts_with(prefix.as_str()),
+                    _ => true,
+                };
+                async move { keep }
+            })
+    }
+
+    /// Lists the entries of a single layer; disabled layers are empty.
+    async fn layer_entries(&self, layer: Layer) -> Result<Vec<CacheEntry>> {
+        let listed = match layer {
+            Layer::Memory => {
+                return Ok(self
+                    .memory()
+                    .map(|memory| {
+                        lock_memory(&memory)
+                            .iter()
+                            .map(|(key, value)| CacheEntry {
+                                layer,
+                                key: key.clone(),
+                                size: value.data.len() as u64,
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default());
+            }
+            Layer::Sideload => match self.sideload.get() {
+                Some(sideload) => sideload.entries().await?,
+                None => Vec::new(),
+            },
+            Layer::Disk => match self.disk.get() {
+                Some(disk) => disk.entries().await?,
+                None => Vec::new(),
+            },
+        };
+
+        Ok(listed
+            .into_iter()
+            .map(|entry| CacheEntry {
+                layer,
+                key: entry.key,
+                size: entry.size,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        num::NonZeroUsize,
+        sync::{Arc, Mutex},
+    };
+
+    use futures::TryStreamExt;
+    use lru::LruCache;
+
+    use super::*;
+    use crate::fs::{
+        FsCache,
+        tests::{read_only_fixture, stored_size},
+    };
+
+    async fn cache(disk: &std::path::Path, sideload: &std::path::Path) -> OmneCache {
+        OmneCache::with_layers(
+            Some(Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(10).unwrap(),
+            )))),
+            Some(Arc::new(FsCache::new_read(sideload).await.unwrap())),
+            Some(Arc::new(FsCache::new_write(disk, 100).await.unwrap())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_entries_lists_every_layer() {
+        let disk = tempfile::tempdir().unwrap();
+        let sideload = read_only_fixture();
+        let cache = cache(disk.path(), sideload.path()).await;
+        cache.put("a".to_string(), b"alpha").await.unwrap();
+        std::fs::write(disk.path().join("CustomString_b.tmp"), b"partial").unwrap();
+
+        let entries: Vec<CacheEntry> = cache.entries().try_collect().await.unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                CacheEntry {
+                    layer: Layer::Memory,
+                    key: "CustomString_a".to_string(),
+                    size: 5,
+                },
+                CacheEntry {
+                    layer: Layer::Sideload,
+                    key: "key1".to_string(),
+                    size: 13,
+                },
+                // The disk layer reports the size of the stored form.
+                CacheEntry {
+                    layer: Layer::Disk,
+                    key: "CustomString_a".to_string(),
+                    size: stored_size("CustomString_a", 5),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keys_filters_by_prefix() {
+        let disk = tempfile::tempdir().unwrap();
+        let sideload = read_only_fixture();
+        let cache = cache(disk.path(), sideload.path()).await;
+        cache.put("a".to_string(), b"alpha").await.unwrap();
+        cache
+            .disk
+            .get()
+            .unwrap()
+            .put("Other_b", b"beta")
+            .await
+            .unwrap();
+
+        let entries: Vec<CacheEntry> = cache.keys::<String>().try_collect().await.unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| e.key == "CustomString_a"));
+    }
+}
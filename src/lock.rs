@@ -0,0 +1,423 @@
+//! # OmneCache Locking Protocol
+//!
+//! How processes sharing a disk cache directory coordinate their work on an
+//! entry. Every process follows the same protocol, whether it uses
+//! [`OmneCache`](crate::OmneCache) or
+//! [`BlockingOmneCache`](crate::blocking::BlockingOmneCache).
+//!
+//! ## Per-key locks
+//!
+//! Writes, removals, metadata updates and repairs of the entry for a key
+//! happen under an exclusive `flock` of its lock file, named after the key
+//! with a `.lock` extension:
+//!
+//! 1. The lock file is opened, and created if it does not exist.
+//! 2. Its lock is tried until the operation's deadline passes; the operation
+//!    then fails with [`std::io::ErrorKind::TimedOut`].
+//! 3. Once locked, the lock file must still be the one at its path. Removals
+//!    and repairs delete lock files, so a lock on a deleted file guards
+//!    nothing, and the steps are repeated with the file now at the path.
+//! 4. The holder records itself in the lock file as a [`LockOwner`]: its
+//!    process ID and the time it took the lock, as `<pid> <seconds since the
+//!    Unix epoch>`.
+//! 5. On release, the record is cleared before the file is unlocked.
+//!
+//! Readers of an entry do not take its per-key lock. They take a shared lock
+//! of the entry file itself, which writers hold exclusively while they write
+//! a new file, before renaming it over the entry.
+//!
+//! ## Stale locks
+//!
+//! The kernel releases the lock of a process that exits, so a crashed holder
+//! only leaves its record behind, which the next holder overwrites. A lock
+//! outlives its holder when the lock file's descriptor was inherited by a
+//! process the holder forked. Such a lock is stale: its record names a
+//! process that no longer exists while the lock is still held.
+//!
+//! A waiting operation breaks a stale lock by deleting the lock file, so it
+//! and all later operations lock a new one (step 3). To make sure the record
+//! is not merely left over from a crash while a new holder has yet to write
+//! its own, the same record must be seen on two attempts in a row. Lock files
+//! are only broken while holding the exclusive lock of the `.breaker.lock`
+//! file in the same directory, after checking again that the path still
+//! names the stale file, so that two operations never break the same lock
+//! twice and delete a live lock file in the process.
+//!
+//! Locks held by live processes are never broken, and a process ID reused
+//! by a new process keeps a stale lock in place until the deadline passes.
+//! Process IDs are only meaningful on one host, so the processes sharing a
+//! directory are expected to run on the same host.
+
+use std::{
+    fs::File,
+    os::unix::fs::{FileExt as _, MetadataExt},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use fs2::FileExt;
+use nix::{errno::Errno, sys::signal::kill, unistd::Pid};
+
+use crate::{fs::LOCK_RETRY_INTERVAL, meta::unix_secs};
+
+/// Name of the file locked while breaking a stale lock in a directory
+pub(crate) const BREAKER_FILE: &str = ".breaker.lock";
+
+/// The process holding a per-key lock, as recorded in its lock file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockOwner {
+    /// ID of the process holding the lock
+    pub pid: u32,
+    /// When the process took the lock, to the second
+    pub since: SystemTime,
+}
+
+impl LockOwner {
+    /// Describes the calling process as taking a lock now.
+    fn current() -> Self {
+        Self {
+            pid: std::process::id(),
+            since: UNIX_EPOCH + Duration::from_secs(unix_secs(SystemTime::now())),
+        }
+    }
+
+    /// Reads the record of the lock file `file`.
+    ///
+    /// # Returns
+    /// * `Ok(Some(LockOwner))`: The recorded owner
+    /// * `Ok(None)`: If the file holds no record or not a valid one
+    fn read(file: &File) -> std::io::Result<Option<Self>> {
+        let mut record = [0; 64];
+        let len = file.read_at(&mut record, 0)?;
+
+        let Ok(record) = std::str::from_utf8(&record[..len]) else {
+            return Ok(None);
+        };
+        let mut fields = record.split_whitespace().map(str::parse::<u64>);
+
+        Ok(match (fields.next(), fields.next()) {
+            (Some(Ok(pid)), Some(Ok(secs))) => u32::try_from(pid).ok().map(|pid| Self {
+                pid,
+                since: UNIX_EPOCH + Duration::from_secs(secs),
+            }),
+            _ => None,
+        })
+    }
+
+    /// Replaces the record of the lock file `file` with this owner.
+    fn write(&self, file: &File) -> std::io::Result<()> {
+        let record = format!("{} {}\n", self.pid, unix_secs(self.since));
+
+        file.set_len(0)?;
+        file.write_all_at(record.as_bytes(), 0)
+    }
+
+    /// Returns `true` unless the owning process is known to have exited.
+    pub fn is_alive(&self) -> bool {
+        if self.pid == std::process::id() {
+            return true;
+        }
+
+        match i32::try_from(self.pid) {
+            Ok(pid) => !matches!(kill(Pid::from_raw(pid), None), Err(Errno::ESRCH)),
+            Err(_) => false,
+        }
+    }
+}
+
+/// An exclusively held per-key lock, released when dropped.
+pub(crate) struct KeyLock {
+    /// The locked lock file
+    file: File,
+}
+
+impl Drop for KeyLock {
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+/// Opens (creating it if necessary) the per-key lock file at `lock_path`.
+pub(crate) fn open_lock_file(lock_path: &Path) -> std::io::Result<File> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .read(true)
+        .open(lock_path)
+}
+
+/// Takes the per-key lock at `lock_path` as described in the [module
+/// documentation](self), waiting no longer than `deadline`.
+///
+/// # Returns
+/// * `Ok(KeyLock)`: The lock, recorded as held by the calling process
+/// * `Err(std::io::Error)`: Of kind [`std::io::ErrorKind::TimedOut`] if the
+///   lock is still held at `deadline`, naming its owner if it is known, or
+///   if the lock file could not be opened
+pub(crate) fn lock_key(lock_path: &Path, deadline: Instant) -> std::io::Result<KeyLock> {
+    // The dead owner seen on the previous attempt, if any
+    let mut suspect = None;
+
+    'open: loop {
+        let file = open_lock_file(lock_path)?;
+
+        loop {
+            match FileExt::try_lock_exclusive(&file) {
+                Ok(()) => break,
+                Err(e) if e.kind() == fs2::lock_contended_error().kind() => {}
+                Err(e) => return Err(e),
+            }
+
+            let owner = LockOwner::read(&file)?;
+            match owner.filter(|owner| !owner.is_alive()) {
+                Some(dead) if suspect == Some(dead) => {
+                    suspect = None;
+                    break_lock(lock_path, &file, dead)?;
+                    continue 'open;
+                }
+                dead => suspect = dead,
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                let message = match owner {
+                    Some(owner) => format!(
+                        "Timed out waiting for the cache entry lock held by process {} since {}",
+                        owner.pid,
+                        unix_secs(owner.since)
+                    ),
+                    None => "Timed out waiting for the cache entry lock".to_string(),
+                };
+                return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, message));
+            }
+
+            std::thread::sleep(LOCK_RETRY_INTERVAL.min(deadline - now));
+        }
+
+        let lock = KeyLock { file };
+
+        match std::fs::metadata(lock_path) {
+            Ok(current) if current.ino() == lock.file.metadata()?.ino() => {
+                LockOwner::current().write(&lock.file)?;
+                return Ok(lock);
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Deletes the lock file at `lock_path`, opened as `stale`, if it is still
+/// held and still records the exited process `dead`.
+fn break_lock(lock_path: &Path, stale: &File, dead: LockOwner) -> std::io::Result<()> {
+    let Some(dir) = lock_path.parent() else {
+        return Ok(());
+    };
+
+    let breaker = open_lock_file(&dir.join(BREAKER_FILE))?;
+    FileExt::lock_exclusive(&breaker)?;
+
+    let still_stale = std::fs::metadata(lock_path).is_ok_and(|current| {
+        stale
+            .metadata()
+            .is_ok_and(|stale| stale.ino() == current.ino())
+    }) && LockOwner::read(stale)? == Some(dead)
+        && FileExt::try_lock_exclusive(stale).is_err();
+
+    if still_stale {
+        eprintln!(
+            "Warning: Breaking lock {} of exited process {}",
+            lock_path.display(),
+            dead.pid
+        );
+
+        match std::fs::remove_file(lock_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+
+    FileExt::unlock(&breaker)
+}
+
+/// Reads the owner recorded in the per-key lock file at `lock_path`.
+///
+/// # Returns
+/// * `Ok(Some(LockOwner))`: The process holding the lock, or the last one to
+///   hold it if it exited without releasing it
+/// * `Ok(None)`: If the lock is not held
+pub(crate) fn owner(lock_path: &Path) -> std::io::Result<Option<LockOwner>> {
+    match File::open(lock_path) {
+        Ok(file) => LockOwner::read(&file),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{Condition, FsCache, ReadWrite, default_deadline};
+
+    /// Returns the ID of a process that has exited.
+    fn exited_pid() -> u32 {
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        child.id()
+    }
+
+    #[test]
+    fn test_lock_records_owner() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("key1.lock");
+
+        let lock = lock_key(&lock_path, Instant::now()).unwrap();
+        let holder = owner(&lock_path).unwrap().unwrap();
+        assert_eq!(holder.pid, std::process::id());
+        assert!(holder.is_alive());
+
+        // A live owner is waited for.
+        let e = lock_key(&lock_path, Instant::now() + Duration::from_millis(120))
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+        assert!(e.to_string().contains(&std::process::id().to_string()));
+
+        drop(lock);
+        assert_eq!(owner(&lock_path).unwrap(), None);
+        assert!(lock_path.exists());
+    }
+
+    #[test]
+    fn test_breaks_stale_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("key1.lock");
+
+        // A lock inherited by a forked process from a holder that exited
+        let inherited = open_lock_file(&lock_path).unwrap();
+        FileExt::lock_exclusive(&inherited).unwrap();
+        let dead = LockOwner {
+            pid: exited_pid(),
+            since: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        };
+        dead.write(&inherited).unwrap();
+        assert!(!dead.is_alive());
+
+        let lock = lock_key(&lock_path, Instant::now() + Duration::from_secs(5)).unwrap();
+        assert_ne!(
+            lock.file.metadata().unwrap().ino(),
+            inherited.metadata().unwrap().ino()
+        );
+        assert_eq!(owner(&lock_path).unwrap().unwrap().pid, std::process::id());
+
+        // A record left by a crash does not keep the free lock from being taken.
+        drop(lock);
+        dead.write(&open_lock_file(&lock_path).unwrap()).unwrap();
+        assert!(lock_key(&lock_path, Instant::now()).is_ok());
+    }
+
+    /// Number of writer processes in [`test_writer_processes`]
+    const WRITERS: usize = 4;
+    /// Number of rounds of writes each writer process makes
+    const ROUNDS: usize = 20;
+    /// Set, to the directory to write to, in the writer processes of
+    /// [`test_writer_processes`]
+    const WRITER_DIR: &str = "OMNECACHE_TEST_WRITER_DIR";
+    /// Set, to the number of the writer, in the writer processes of
+    /// [`test_writer_processes`]
+    const WRITER: &str = "OMNECACHE_TEST_WRITER";
+
+    /// Writes to `dir` as writer number `writer` of [`test_writer_processes`].
+    fn write_rounds(dir: &std::path::Path, writer: usize) {
+        let cache = FsCache::<ReadWrite>::new_write_blocking(dir.into(), 100, None).unwrap();
+        let value = format!("written by writer {}", writer);
+
+        for round in 0..ROUNDS {
+            let deadline = default_deadline();
+            cache
+                .write_blocking(
+                    "shared",
+                    value.as_bytes(),
+                    Condition::Always,
+                    None,
+                    deadline,
+                )
+                .unwrap();
+            cache
+                .write_blocking(
+                    &format!("own-{}-{}", writer, round),
+                    value.as_bytes(),
+                    Condition::Always,
+                    None,
+                    deadline,
+                )
+                .unwrap();
+            if round % 2 == 0 {
+                cache.remove_blocking("removed", deadline).unwrap();
+            } else {
+                cache
+                    .write_blocking("removed", b"short-lived", Condition::Always, None, deadline)
+                    .unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_writer_processes() {
+        // Run again by the test itself as one of the writers.
+        if let (Ok(dir), Ok(writer)) = (std::env::var(WRITER_DIR), std::env::var(WRITER)) {
+            write_rounds(dir.as_ref(), writer.parse().unwrap());
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let children = (0..WRITERS)
+            .map(|writer| {
+                std::process::Command::new(std::env::current_exe().unwrap())
+                    .args(["--exact", "lock::tests::test_writer_processes", "--quiet"])
+                    .env(WRITER_DIR, dir.path())
+                    .env(WRITER, writer.to_string())
+                    .stdout(std::process::Stdio::null())
+                    .spawn()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        for mut child in children {
+            assert!(child.wait().unwrap().success());
+        }
+
+        let cache = FsCache::<ReadWrite>::new_write_blocking(dir.path().into(), 100, None).unwrap();
+        let (shared, _) = cache.get_blocking("shared", default_deadline()).unwrap();
+        assert!(
+            String::from_utf8(shared)
+                .unwrap()
+                .starts_with("written by writer ")
+        );
+
+        let mut entries = crate::fs::list_entries(dir.path(), crate::layout::Layout::Flat).unwrap();
+        entries.retain(|entry| entry.key.starts_with("own-"));
+        assert_eq!(entries.len(), WRITERS * ROUNDS);
+
+        // Every write was recorded in the shared index, and no lock is left held.
+        let total: u64 = crate::fs::list_entries(dir.path(), crate::layout::Layout::Flat)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.size)
+            .sum();
+        assert_eq!(cache.used_bytes().unwrap(), total);
+        for entry in std::fs::read_dir(dir.path()).unwrap() {
+            let path = entry.unwrap().path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "lock")
+            {
+                assert_eq!(owner(&path).unwrap(), None);
+            }
+            assert_ne!(path.extension().unwrap_or_default(), "tmp");
+        }
+    }
+}
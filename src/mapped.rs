@@ -0,0 +1,227 @@
+//! # OmneCache Mapped Reads
+//!
+//! Reading an entry normally copies its file into a fresh buffer. For large,
+//! read-mostly entries, such as those of sideloaded bundles,
+//! [`FsCache::get_mapped`](crate::fs::FsCache::get_mapped) instead maps the
+//! file into memory once it holds at least
+//! [`FsCache::mmap_threshold`](crate::fs::FsCache::mmap_threshold) bytes.
+//!
+//! It hands out the value as [`EntryBytes`], which borrows from the mapping
+//! when the entry is stored uncompressed and unencrypted, and owns a decoded
+//! copy otherwise. The mapped file stays under the same shared lock every
+//! read takes until the [`EntryBytes`] are dropped. Reads returning a
+//! `Vec<u8>`, such as [`FsCache::get`](crate::fs::FsCache::get) and those of
+//! [`OmneCache`](crate::OmneCache), never map entries, since they would only
+//! copy the mapping.
+//!
+//! Entries are never modified in place: writers, repairs and migrations
+//! replace an entry by renaming a new file over it, so a mapping keeps showing
+//! the entry as it was read, and holding it blocks no writer. A file truncated
+//! by a process ignoring this protocol would fault the reader, as with any
+//! memory-mapped file.
+
+use std::{borrow::Cow, fs::File, ops::Deref};
+
+use memmap2::Mmap;
+
+use crate::{
+    encryption::Keyring,
+    format::{self, Stamp},
+};
+
+/// Size in bytes from which entries are memory-mapped rather than copied
+pub const DEFAULT_MMAP_THRESHOLD: u64 = 1 << 20;
+
+/// The value of a cache entry, either borrowed from a memory mapping of its
+/// file or owned.
+///
+/// Dereferences to the value's bytes.
+pub struct EntryBytes {
+    /// Where the bytes are
+    repr: Repr,
+}
+
+/// Storage of [`EntryBytes`]
+enum Repr {
+    /// Bytes of a mapped file, from `offset` to its end
+    Mapped(Mapped),
+    /// Bytes in memory
+    Owned(Vec<u8>),
+}
+
+/// A memory-mapped entry file.
+///
+/// Fields drop in order: the mapping goes before the file, whose closing
+/// releases the shared lock.
+struct Mapped {
+    /// The mapping of the whole file
+    map: Mmap,
+    /// Offset of the bytes within the mapping
+    offset: usize,
+    /// The file, locked shared
+    _file: File,
+}
+
+impl EntryBytes {
+    /// Maps `file`, which must be locked shared, in its entirety.
+    ///
+    /// # Returns
+    /// * `Ok(EntryBytes)`: The file's content, held under its lock
+    /// * `Err(File)`: The file, if it could not be mapped
+    pub(crate) fn map(file: File) -> Result<Self, File> {
+        // SAFETY: Entry files are replaced by renaming new files over them and
+        // never modified in place, and the caller holds the file's shared lock.
+        match unsafe { Mmap::map(&file) } {
+            Ok(map) => Ok(Self {
+                repr: Repr::Mapped(Mapped {
+                    map,
+                    offset: 0,
+                    _file: file,
+                }),
+            }),
+            Err(_) => Err(file),
+        }
+    }
+
+    /// Returns `true` if the bytes are borrowed from a memory mapping.
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.repr, Repr::Mapped(_))
+    }
+
+    /// Copies the bytes into a vector, or returns those already owned.
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.repr {
+            Repr::Mapped(mapped) => mapped.map[mapped.offset..].to_vec(),
+            Repr::Owned(data) => data,
+        }
+    }
+
+    /// Decodes an entry in its [stored form](crate::format) into its value,
+    /// keeping the mapping when the value is stored as is.
+    ///
+    /// # Returns
+    /// The value, and what else decoding learned about the entry; errors
+    /// as [`format::decode`] does
+    pub(crate) fn decode(
+        self,
+        key: Option<&str>,
+        keyring: Option<&Keyring>,
+    ) -> std::io::Result<(Self, Stamp)> {
+        let mut mapped = match self.repr {
+            Repr::Mapped(mapped) => mapped,
+            Repr::Owned(stored) => {
+                let (data, stamp) = format::decode_vec(stored, key, keyring)?;
+                return Ok((data.into(), stamp));
+            }
+        };
+
+        let decoded = format::decode(&mapped.map[mapped.offset..], key, keyring)?;
+        let stamp = decoded.stamp;
+        let len = match decoded.data {
+            Cow::Owned(data) => return Ok((data.into(), stamp)),
+            Cow::Borrowed(data) => data.len(),
+        };
+
+        mapped.offset = mapped.map.len() - len;
+        Ok((
+            Self {
+                repr: Repr::Mapped(mapped),
+            },
+            stamp,
+        ))
+    }
+}
+
+impl Deref for EntryBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.repr {
+            Repr::Mapped(mapped) => &mapped.map[mapped.offset..],
+            Repr::Owned(data) => data,
+        }
+    }
+}
+
+impl AsRef<[u8]> for EntryBytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<Vec<u8>> for EntryBytes {
+    fn from(data: Vec<u8>) -> Self {
+        Self {
+            repr: Repr::Owned(data),
+        }
+    }
+}
+
+impl std::fmt::Debug for EntryBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EntryBytes")
+            .field("len", &self.len())
+            .field("mapped", &self.is_mapped())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use crate::{
+        compression::Compression,
+        fs::{FsCache, Read, ReadWrite},
+    };
+
+    #[tokio::test]
+    async fn test_mapped_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FsCache::<ReadWrite>::new_write(dir.path(), 100)
+            .await
+            .unwrap();
+        cache.set_mmap_threshold(Some(1024));
+        assert_eq!(cache.mmap_threshold(), Some(1024));
+
+        let large: Vec<u8> = (0..4096u32).map(|i| i as u8).collect();
+        cache.put("large", &large).await.unwrap();
+        cache.put("small", b"Hello").await.unwrap();
+
+        let mapped = cache.get_mapped("large").await.unwrap();
+        assert!(mapped.is_mapped());
+        assert_eq!(&*mapped, &large[..]);
+        assert_eq!(cache.get("large").await, Some(large.clone()));
+
+        // Replacing a mapped entry leaves the mapping as it was read.
+        cache.put("large", b"replaced").await.unwrap();
+        assert_eq!(&*mapped, &large[..]);
+        drop(mapped);
+
+        let small = cache.get_mapped("small").await.unwrap();
+        assert!(!small.is_mapped());
+        assert_eq!(small.into_vec(), b"Hello");
+
+        // Compressed entries are decoded into memory.
+        cache.set_compression(Some(Compression::default()));
+        cache.put("compressed", &vec![7; 8192]).await.unwrap();
+        let compressed = cache.get_mapped("compressed").await.unwrap();
+        assert!(!compressed.is_mapped());
+        assert_eq!(&*compressed, &[7; 8192][..]);
+
+        cache.set_mmap_threshold(None);
+        cache.set_compression(None);
+        cache.put("large", &large).await.unwrap();
+        assert!(!cache.get_mapped("large").await.unwrap().is_mapped());
+        assert!(cache.get_mapped("missing").await.is_none());
+
+        // Sideloaded bundles are mapped as well.
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o555)).unwrap();
+        let sideload = FsCache::<Read>::new_read(dir.path()).await.unwrap();
+        sideload.set_mmap_threshold(Some(1024));
+        let mapped = sideload.get_mapped("large").await.unwrap();
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(mapped.is_mapped());
+        assert_eq!(&*mapped, &large[..]);
+    }
+}
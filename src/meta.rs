@@ -0,0 +1,105 @@
+//! # OmneCache Entry Metadata
+//!
+//! Metadata describing a cached value, as returned by [`OmneCache::get_with_meta`].
+//!
+//! Every value has an [`EntryVersion`] derived from its content, and may carry
+//! [`EntryInfo`] describing how long it stays fresh and how to revalidate it.
+//! Versions are stable across processes and restarts, which makes them suitable
+//! as the expected value of [`OmneCache::compare_and_swap`].
+//!
+//! [`OmneCache::get_with_meta`]: crate::OmneCache::get_with_meta
+//! [`OmneCache::compare_and_swap`]: crate::OmneCache::compare_and_swap
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Layer;
+
+/// Version of a cached value, derived from a BLAKE3 hash of its content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EntryVersion([u8; 32]);
+
+impl EntryVersion {
+    /// Computes the version of the given value.
+    pub fn of(data: &[u8]) -> Self {
+        Self(*blake3::hash(data).as_bytes())
+    }
+
+    /// Restores a version from its raw bytes.
+    pub(crate) fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// Returns the raw bytes of the version.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for EntryVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Metadata describing a value returned from the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryMeta {
+    /// Layer the value was served from
+    pub layer: Layer,
+    /// Size of the value, in bytes
+    pub size: u64,
+    /// Content version of the value
+    pub version: EntryVersion,
+}
+
+impl EntryMeta {
+    /// Builds the metadata of a value served from `layer`.
+    pub(crate) fn new(layer: Layer, data: &[u8]) -> Self {
+        Self {
+            layer,
+            size: data.len() as u64,
+            version: EntryVersion::of(data),
+        }
+    }
+}
+
+/// Freshness and revalidation metadata stored alongside a cached value.
+///
+/// The disk layer persists this next to each entry, so it survives restarts
+/// and can be updated without rewriting the entry itself.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryInfo {
+    /// Opaque validator supplied by the origin, such as an ETag or a Last-Modified value
+    pub validator: Option<String>,
+    /// Time until which the value is fresh, in seconds since the Unix epoch
+    pub fresh_until: Option<u64>,
+}
+
+impl EntryInfo {
+    /// Creates metadata for a value fetched at `now` that stays fresh for `ttl`.
+    pub fn new(validator: Option<String>, now: SystemTime, ttl: Option<Duration>) -> Self {
+        Self {
+            validator,
+            fresh_until: ttl.map(|ttl| unix_secs(now + ttl)),
+        }
+    }
+
+    /// Returns `true` if the value is still fresh at `now`.
+    ///
+    /// Values without a known freshness lifetime are considered stale.
+    pub fn is_fresh(&self, now: SystemTime) -> bool {
+        self.fresh_until.is_some_and(|t| unix_secs(now) < t)
+    }
+}
+
+/// Converts a timestamp to seconds since the Unix epoch, clamping pre-epoch times to zero.
+pub(crate) fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
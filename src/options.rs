@@ -0,0 +1,68 @@
+//! # OmneCache Call Options
+//!
+//! Per-call cache control for [`OmneCache::get_with`], [`OmneCache::put_with`]
+//! and [`OmneCache::get_or_fetch_with`].
+//!
+//! The plain [`OmneCache::get`], [`OmneCache::put`] and [`OmneCache::get_or_fetch`]
+//! behave like their `_with` counterparts called with [`CacheOptions::default`]:
+//! every enabled layer is used, the origin is contacted on a miss, and disk
+//! operations wait up to [`DEFAULT_LOCK_TIMEOUT`] for their per-key lock.
+//!
+//! # Example
+//! ```rust
+//! use std::time::Duration;
+//! use omnecache::{Layer, LayerSelector, options::CacheOptions};
+//!
+//! // Read around the memory layer and give up on contended disk locks quickly.
+//! let options = CacheOptions {
+//!     skip: LayerSelector::only(Layer::Memory),
+//!     deadline: Some(Duration::from_millis(200)),
+//!     ..Default::default()
+//! };
+//! assert!(!options.uses(Layer::Memory));
+//! ```
+
+use std::time::{Duration, Instant};
+
+use crate::{Layer, LayerSelector, fs::DEFAULT_LOCK_TIMEOUT};
+
+#[cfg(doc)]
+use crate::OmneCache;
+
+/// Controls how a single cache call uses the layers and the origin.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheOptions {
+    /// Layers that are neither read nor written by the call
+    pub skip: LayerSelector,
+    /// Never contact the origin; a missing value is reported as not found and
+    /// a stale value is served without revalidation
+    pub only_if_cached: bool,
+    /// Do not persist the value to the disk layer, and remove any older disk
+    /// entry for the key. The memory layer, unless skipped, still stores it
+    /// for the lifetime of the process.
+    pub no_store: bool,
+    /// Ignore cached values and fetch from the origin, storing the result.
+    /// Only meaningful for [`OmneCache::get_or_fetch_with`].
+    pub force_refresh: bool,
+    /// Longest time the call waits for per-key disk and sideload locks, in
+    /// total. Defaults to [`DEFAULT_LOCK_TIMEOUT`]. Fetching from the origin is
+    /// not bounded by the deadline.
+    pub deadline: Option<Duration>,
+}
+
+impl CacheOptions {
+    /// Returns `true` if the call reads from and writes to `layer`.
+    pub const fn uses(&self, layer: Layer) -> bool {
+        !self.skip.contains(layer)
+    }
+
+    /// Returns `true` if the call may write values to the disk layer.
+    pub(crate) const fn stores_on_disk(&self) -> bool {
+        self.uses(Layer::Disk) && !self.no_store
+    }
+
+    /// Computes the instant at which a call starting now must stop waiting for locks.
+    pub(crate) fn lock_deadline(&self) -> Instant {
+        Instant::now() + self.deadline.unwrap_or(DEFAULT_LOCK_TIMEOUT)
+    }
+}
@@ -0,0 +1,1298 @@
+//! # OmneCache Pack Store
+//!
+//! An alternative backend of the disk layer for many small entries, selected
+//! with [`DiskCfg::backend`](crate::configuration::DiskCfg::backend).
+//!
+//! The default [`Backend::Files`] stores every entry in a file of its own,
+//! next to lock and metadata files, which costs several inodes and
+//! system calls per entry. [`Backend::Pack`] instead appends entries as
+//! records to segment files in the `.pack` directory inside the cache
+//! directory, and finds them through an index kept in memory that is rebuilt
+//! from the segments when the cache is opened.
+//!
+//! A record is its length as a little-endian `u32`, the first 16 bytes of the
+//! BLAKE3 hash of its body, and its body: a kind, the key, and for a written
+//! entry when it was written, its [metadata](crate::meta::EntryInfo) and the
+//! entry in its [stored form](crate::format). Removing an entry appends a
+//! record naming only its key. Records hold no hash of an entry's value, so
+//! an [encrypted](crate::encryption) value cannot be confirmed by guessing it.
+//!
+//! * Records are checked against their hash when read; an entry whose record
+//!   does not match is reported as corrupt and dropped.
+//! * A crash while appending leaves a torn record at the end of the newest
+//!   segment, which is cut off when the store is opened. Damaged records
+//!   elsewhere are skipped with a warning, keeping the records after them,
+//!   and are dropped when their segment is compacted.
+//! * Replaced and removed entries leave dead records behind. Once at least
+//!   half of a segment is dead, a background thread copies its live records
+//!   to the newest segment and deletes it. [`FsCache::repair`] compacts every
+//!   such segment right away.
+//!
+//! Limits, eviction, conditional writes, compression and encryption behave
+//! as with [`Backend::Files`], within one cache. Unlike a directory of files,
+//! a pack store cannot be shared: since the index lives in memory, it belongs
+//! to a single cache at a time. It is locked when opened, and opening it
+//! again, from this or another process, fails until that cache is dropped.
+//! [`BlockingOmneCache`](crate::blocking::BlockingOmneCache), which is meant
+//! to share its directory, does not accept a pack store.
+//! Reads are recorded in memory only, so after a restart entries are ranked
+//! for eviction by the time they were written.
+//!
+//! [`FsCache::repair`]: crate::fs::FsCache::repair
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    os::unix::fs::{DirBuilderExt, FileExt as _},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use const_default::ConstDefault;
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    encryption::Keyring,
+    eviction::{self, EvictionPolicy},
+    format::invalid,
+    fs::{Condition, FsEntry, stores_version},
+    index::IndexEntry,
+    meta::EntryInfo,
+};
+
+/// Name of the directory holding the segments inside the cache directory
+pub(crate) const PACK_DIR: &str = ".pack";
+/// Name of the file locked by the cache using the store
+const LOCK_FILE: &str = "lock";
+/// Extension of the segment files, named after their number in hex
+const SEGMENT_EXTENSION: &str = "seg";
+/// Size in bytes beyond which new records go to a new segment
+const SEGMENT_BYTES: u64 = 8 << 20;
+/// Length of the truncated hash of a record's body
+const HASH_LEN: usize = 16;
+/// Length of a record's length and hash
+const PREFIX_LEN: usize = 4 + HASH_LEN;
+/// Kind of a record holding an entry
+const PUT: u8 = 1;
+/// Kind of a record removing an entry
+const DEL: u8 = 2;
+
+/// Where the disk layer stores its entries.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// A file per entry, arranged by the directory's [layout](crate::layout)
+    #[default]
+    Files,
+    /// Records appended to segment files
+    Pack,
+}
+
+impl ConstDefault for Backend {
+    const DEFAULT: Self = Self::Files;
+}
+
+/// The limits a write must respect, as configured on the cache.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits {
+    /// Maximum number of entries
+    pub(crate) items: usize,
+    /// Maximum total size of the entries in bytes, if limited
+    pub(crate) max_bytes: Option<u64>,
+    /// Which entries to evict to stay within the limits
+    pub(crate) policy: EvictionPolicy,
+}
+
+/// Where the current record of an entry is, and what the index knows about it
+#[derive(Clone)]
+struct Slot {
+    /// Number of the segment holding the record
+    segment: u64,
+    /// Offset of the record in the segment
+    offset: u64,
+    /// Length of the record, including its length and hash
+    len: u64,
+    /// Offset of the stored entry within the record
+    value_offset: u64,
+    /// Metadata of the entry, if any
+    info: Option<EntryInfo>,
+    /// Size and access statistics of the entry
+    entry: IndexEntry,
+}
+
+/// An open segment file
+struct Segment {
+    /// The file, opened for reading and writing, and shared with reads and
+    /// syncs running outside the store's lock
+    file: Arc<File>,
+    /// Length of the valid records in the file
+    size: u64,
+    /// Total length of the records holding live entries
+    live: u64,
+}
+
+/// The index and segments of a store
+#[derive(Default)]
+struct PackState {
+    /// Current record of every entry, by key
+    slots: HashMap<String, Slot>,
+    /// Open segments by number; new records go to the last one
+    segments: BTreeMap<u64, Segment>,
+    /// Total size of the stored entries in bytes
+    used_bytes: u64,
+}
+
+/// Entries stored as records in segment files.
+pub(crate) struct PackStore {
+    /// The `.pack` directory
+    dir: PathBuf,
+    /// Held locked while the store is open
+    _lock: File,
+    /// The index and segments
+    state: Mutex<PackState>,
+    /// Whether a background compaction is running
+    compacting: AtomicBool,
+}
+
+/// A record parsed from a segment
+struct Record<'a> {
+    /// Key of the entry
+    key: &'a str,
+    /// Content of a written entry, `None` for a removal
+    put: Option<Put>,
+}
+
+/// Content of a record holding an entry
+struct Put {
+    /// When the entry was written
+    modified: SystemTime,
+    /// Metadata of the entry, if any
+    info: Option<EntryInfo>,
+    /// Offset of the stored entry within the record
+    value_offset: u64,
+    /// Length of the stored entry
+    value_len: u64,
+}
+
+impl PackStore {
+    /// Opens the store of the cache directory `cache_dir`, creating it if needed.
+    ///
+    /// A torn record at the end of the newest segment is cut off, and damaged
+    /// records elsewhere are skipped.
+    ///
+    /// # Returns
+    /// * `Ok(PackStore)`: The store, with its index rebuilt from the segments
+    /// * `Err(std::io::Error)`: Of kind [`std::io::ErrorKind::WouldBlock`] if
+    ///   another cache uses the store, or if the segments could not be read
+    pub(crate) fn open(cache_dir: &Path) -> std::io::Result<Self> {
+        let dir = cache_dir.join(PACK_DIR);
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)?;
+
+        let lock = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dir.join(LOCK_FILE))?;
+        FileExt::try_lock_exclusive(&lock).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::WouldBlock,
+                "Pack store is in use by another cache",
+            )
+        })?;
+
+        let mut numbers = Vec::new();
+        for dir_entry in std::fs::read_dir(&dir)? {
+            let name = dir_entry?.file_name();
+            if let Some(number) = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(&format!(".{}", SEGMENT_EXTENSION)))
+                .and_then(|number| u64::from_str_radix(number, 16).ok())
+            {
+                numbers.push(number);
+            }
+        }
+        numbers.sort_unstable();
+
+        let newest = numbers.last().copied();
+        let mut state = PackState::default();
+        for number in numbers {
+            let path = segment_path(&dir, number);
+            let file = File::options().read(true).write(true).open(&path)?;
+            let content = std::fs::read(&path)?;
+
+            let (records, end, skipped) = scan(&content);
+            if skipped > 0 {
+                eprintln!(
+                    "Warning: Skipping {} damaged bytes in pack segment {}",
+                    skipped,
+                    path.display()
+                );
+            }
+
+            // Only the newest segment can end with a torn append; damage at
+            // the end of an older one stays until the segment is compacted.
+            let mut size = content.len() as u64;
+            if end < size {
+                if Some(number) == newest {
+                    eprintln!(
+                        "Warning: Cutting off {} damaged bytes at the end of pack segment {}",
+                        size - end,
+                        path.display()
+                    );
+                    file.set_len(end)?;
+                    size = end;
+                } else {
+                    eprintln!(
+                        "Warning: Skipping {} damaged bytes at the end of pack segment {}",
+                        size - end,
+                        path.display()
+                    );
+                }
+            }
+
+            state.segments.insert(
+                number,
+                Segment {
+                    file: Arc::new(file),
+                    size,
+                    live: 0,
+                },
+            );
+            for (offset, len, record) in records {
+                state.apply(number, offset, len, record);
+            }
+        }
+
+        for slot in state.slots.values() {
+            if let Some(segment) = state.segments.get_mut(&slot.segment) {
+                segment.live += slot.len;
+            }
+        }
+
+        Ok(Self {
+            dir,
+            _lock: lock,
+            state: Mutex::new(state),
+            compacting: AtomicBool::new(false),
+        })
+    }
+
+    /// Returns the `.pack` directory holding the segments.
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Locks the index and segments.
+    fn state(&self) -> MutexGuard<'_, PackState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Reads the entry for `key`, recording the access if `track` is set.
+    ///
+    /// The record is read after releasing the store's lock, so reads do not
+    /// wait for writes to be synced. Records are never overwritten in place,
+    /// and a segment deleted by compaction stays readable through its open file.
+    ///
+    /// # Returns
+    /// * `Ok(Some((Vec<u8>, Option<EntryInfo>)))`: The stored form of the entry and its metadata, if any
+    /// * `Ok(None)`: If there is no entry for the key
+    /// * `Err(std::io::Error)`: Of kind [`std::io::ErrorKind::InvalidData`] if
+    ///   the record does not match its hash, or if it could not be read
+    pub(crate) fn get(
+        &self,
+        key: &str,
+        track: bool,
+    ) -> std::io::Result<Option<(Vec<u8>, Option<EntryInfo>)>> {
+        let (file, slot) = {
+            let mut state = self.state();
+            let state = &mut *state;
+
+            let Some(slot) = state.slots.get_mut(key) else {
+                return Ok(None);
+            };
+            let file = segment_file(&state.segments, slot)?;
+
+            if track {
+                slot.entry.last_access = SystemTime::now();
+                slot.entry.hits = slot.entry.hits.saturating_add(1);
+            }
+            (file, slot.clone())
+        };
+
+        let mut record = read_record(&file, &slot)?;
+        record.drain(..slot.value_offset as usize);
+
+        Ok(Some((record, slot.info)))
+    }
+
+    /// Writes the entry for `key` if `condition` holds, evicting entries as
+    /// needed to stay within `limits`.
+    ///
+    /// # Parameters
+    /// * `key`: The key of the entry
+    /// * `stored`: The entry in its stored form
+    /// * `info`: The metadata of the entry, if any
+    /// * `condition`: What the current entry must satisfy
+    /// * `keyring`: The keys to decrypt the current entry with to check
+    ///   `condition`, if any
+    /// * `limits`: The limits of the cache
+    ///
+    /// # Returns
+    /// * `Ok(true)`: If the condition held and the entry was written
+    /// * `Ok(false)`: If the condition did not hold
+    /// * `Err(std::io::Error)`: Of kind [`std::io::ErrorKind::StorageFull`] if
+    ///   the limits cannot be met, or if the record could not be written
+    pub(crate) fn put(
+        self: &Arc<Self>,
+        key: &str,
+        stored: &[u8],
+        info: Option<&EntryInfo>,
+        condition: Condition,
+        keyring: Option<&Keyring>,
+        limits: Limits,
+    ) -> std::io::Result<bool> {
+        let unsynced = {
+            let mut state = self.state();
+
+            let holds = match (condition, state.slots.get(key)) {
+                (Condition::Always, _) => true,
+                (Condition::Absent, slot) => slot.is_none(),
+                (Condition::Matches(_), None) => false,
+                (Condition::Matches(expected), Some(slot)) => {
+                    let record = read_record(&*segment_file(&state.segments, slot)?, slot)?;
+                    let current = &record[slot.value_offset as usize..];
+
+                    stores_version(current, key, keyring, &expected)
+                }
+            };
+            if !holds {
+                return Ok(false);
+            }
+
+            let newest = newest_segment(&state);
+            self.make_room(&mut state, key, stored.len() as u64, limits)?;
+
+            let now = SystemTime::now();
+            let body = put_body(key, now, info, stored)?;
+            let (segment, offset, len) = self.append(&mut state, &body)?;
+
+            let hits = state.slots.get(key).map_or(0, |slot| slot.entry.hits);
+            state.insert(
+                key,
+                Slot {
+                    segment,
+                    offset,
+                    len,
+                    value_offset: len - stored.len() as u64,
+                    info: info.cloned(),
+                    entry: IndexEntry {
+                        size: stored.len() as u64,
+                        modified: now,
+                        last_access: now,
+                        hits: hits.saturating_add(1),
+                    },
+                },
+            );
+            segment_files(&state, newest)
+        };
+
+        sync(&unsynced)?;
+        self.compact_in_background();
+        Ok(true)
+    }
+
+    /// Replaces the metadata of the entry for `key`.
+    ///
+    /// # Returns
+    /// * `Ok(true)`: If the metadata was replaced
+    /// * `Ok(false)`: If there is no entry for the key
+    pub(crate) fn set_info(self: &Arc<Self>, key: &str, info: &EntryInfo) -> std::io::Result<bool> {
+        let unsynced = {
+            let mut state = self.state();
+
+            let Some(slot) = state.slots.get(key) else {
+                return Ok(false);
+            };
+            let record = read_record(&*segment_file(&state.segments, slot)?, slot)?;
+            let stored = &record[slot.value_offset as usize..];
+
+            let body = put_body(key, slot.entry.modified, Some(info), stored)?;
+            let entry = slot.entry;
+
+            let newest = newest_segment(&state);
+            let (segment, offset, len) = self.append(&mut state, &body)?;
+
+            state.insert(
+                key,
+                Slot {
+                    segment,
+                    offset,
+                    len,
+                    value_offset: len - entry.size,
+                    info: Some(info.clone()),
+                    entry,
+                },
+            );
+            segment_files(&state, newest)
+        };
+
+        sync(&unsynced)?;
+        self.compact_in_background();
+        Ok(true)
+    }
+
+    /// Removes the entry for `key`.
+    ///
+    /// # Returns
+    /// * `Ok(true)`: If the entry existed and was removed
+    /// * `Ok(false)`: If there was no entry for the key
+    pub(crate) fn remove(self: &Arc<Self>, key: &str) -> std::io::Result<bool> {
+        let (removed, unsynced) = {
+            let mut state = self.state();
+            let newest = newest_segment(&state);
+            let removed = self.remove_locked(&mut state, key)?;
+            (removed, segment_files(&state, newest))
+        };
+
+        sync(&unsynced)?;
+        self.compact_in_background();
+        Ok(removed)
+    }
+
+    /// Removes every entry by deleting the segments.
+    ///
+    /// # Returns
+    /// The number of entries removed
+    pub(crate) fn clear(&self) -> std::io::Result<usize> {
+        let mut state = self.state();
+        let removed = state.slots.len();
+
+        // Oldest first, so a crash never leaves an entry behind its removal.
+        while let Some((number, _)) = state.segments.pop_first() {
+            std::fs::remove_file(segment_path(&self.dir, number))?;
+        }
+        *state = PackState::default();
+
+        Ok(removed)
+    }
+
+    /// Lists the entries of the store.
+    pub(crate) fn entries(&self) -> Vec<FsEntry> {
+        self.state()
+            .slots
+            .iter()
+            .map(|(key, slot)| FsEntry {
+                key: key.clone(),
+                size: slot.entry.size,
+                modified: slot.entry.modified,
+            })
+            .collect()
+    }
+
+    /// Returns the number of entries.
+    pub(crate) fn len(&self) -> usize {
+        self.state().slots.len()
+    }
+
+    /// Returns the total size of the stored entries in bytes.
+    pub(crate) fn used_bytes(&self) -> u64 {
+        self.state().used_bytes
+    }
+
+    /// Compacts every segment that is at least half dead.
+    ///
+    /// # Returns
+    /// The number of segments compacted
+    pub(crate) fn compact(&self) -> std::io::Result<usize> {
+        let mut compacted = 0;
+
+        loop {
+            let Some(number) = self.state().compactable().first().copied() else {
+                return Ok(compacted);
+            };
+
+            self.compact_segment(number)?;
+            compacted += 1;
+        }
+    }
+
+    /// Starts compacting on a background thread, if a segment is at least
+    /// half dead and no compaction is running.
+    fn compact_in_background(self: &Arc<Self>) {
+        if self.state().compactable().is_empty() || self.compacting.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let store = self.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = store.compact() {
+                eprintln!(
+                    "Warning: Cannot compact pack store {}: {}",
+                    store.dir.display(),
+                    e
+                );
+            }
+            store.compacting.store(false, Ordering::Release);
+        });
+    }
+
+    /// Copies the live records of segment `number` to the newest segment and deletes it.
+    ///
+    /// Segments other than the newest are never appended to, so the segment
+    /// is read without holding the store's lock, which is only taken to copy
+    /// each record. Concurrent reads and writes therefore wait for at most one
+    /// record at a time.
+    fn compact_segment(&self, number: u64) -> std::io::Result<()> {
+        let (file, has_older) = {
+            let state = self.state();
+            let Some(segment) = state.segments.get(&number) else {
+                return Ok(());
+            };
+
+            (
+                segment.file.clone(),
+                state.segments.range(..number).next().is_some(),
+            )
+        };
+
+        let mut content = vec![0; file.metadata()?.len() as usize];
+        file.read_exact_at(&mut content, 0)?;
+        let (records, _, _) = scan(&content);
+
+        // Every segment the live records went to, which must be synced first.
+        let mut written = Vec::new();
+
+        for (offset, len, record) in records {
+            let range = offset as usize..(offset + len) as usize;
+            let mut state = self.state();
+
+            // A concurrent compaction or clear already dealt with the segment.
+            if !state.segments.contains_key(&number) {
+                return Ok(());
+            }
+
+            let segment = match record.put {
+                Some(_) => {
+                    let current = state
+                        .slots
+                        .get(record.key)
+                        .is_some_and(|slot| slot.segment == number && slot.offset == offset);
+                    if !current {
+                        continue;
+                    }
+
+                    let (segment, new_offset) = self.append_raw(&mut state, &content[range])?;
+                    state.moved(record.key, segment, new_offset);
+                    segment
+                }
+                // A removal still hides older records of the key in older segments.
+                None if has_older && !state.slots.contains_key(record.key) => {
+                    self.append_raw(&mut state, &content[range])?.0
+                }
+                None => continue,
+            };
+
+            if !written.contains(&segment) {
+                written.push(segment);
+            }
+        }
+
+        let unsynced = {
+            let state = self.state();
+            written
+                .iter()
+                .filter_map(|segment| state.segments.get(segment))
+                .map(|segment| segment.file.clone())
+                .collect::<Vec<_>>()
+        };
+        sync(&unsynced)?;
+
+        let mut state = self.state();
+        if state.segments.remove(&number).is_none() {
+            return Ok(());
+        }
+        std::fs::remove_file(segment_path(&self.dir, number))
+    }
+
+    /// Evicts entries until an entry of `size` bytes fits under `key` within `limits`.
+    fn make_room(
+        &self,
+        state: &mut PackState,
+        key: &str,
+        size: u64,
+        limits: Limits,
+    ) -> std::io::Result<()> {
+        if limits.max_bytes.is_some_and(|max_bytes| size > max_bytes) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::StorageFull,
+                "Entry exceeds the cache byte limit",
+            ));
+        }
+
+        let replaced = state.slots.get(key).map(|slot| slot.entry.size);
+        let mut excess_items = match replaced {
+            Some(_) => 0,
+            None => (state.slots.len() + 1).saturating_sub(limits.items),
+        };
+        let mut excess_bytes = limits.max_bytes.map_or(0, |max_bytes| {
+            (state.used_bytes + size).saturating_sub(replaced.unwrap_or(0) + max_bytes)
+        });
+
+        if (excess_items > 0 || excess_bytes > 0) && limits.policy != EvictionPolicy::Reject {
+            let ranked = eviction::rank(
+                state.slots.iter().map(|(key, slot)| (key, &slot.entry)),
+                limits.policy,
+            );
+
+            for (victim, victim_size) in ranked {
+                if excess_items == 0 && excess_bytes == 0 {
+                    break;
+                }
+                if victim == key {
+                    continue;
+                }
+
+                self.remove_locked(state, &victim)?;
+                excess_items = excess_items.saturating_sub(1);
+                excess_bytes = excess_bytes.saturating_sub(victim_size);
+            }
+        }
+
+        if excess_items > 0 || excess_bytes > 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::StorageFull,
+                "Cannot exceed cache limit",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Appends the removal of `key`, if it has an entry, without syncing it.
+    fn remove_locked(&self, state: &mut PackState, key: &str) -> std::io::Result<bool> {
+        if !state.slots.contains_key(key) {
+            return Ok(false);
+        }
+
+        self.append(state, &del_body(key))?;
+        state.remove(key);
+        Ok(true)
+    }
+
+    /// Appends a record with `body` to the newest segment, without syncing it.
+    ///
+    /// # Returns
+    /// The number of the segment, and the offset and length of the record
+    fn append(&self, state: &mut PackState, body: &[u8]) -> std::io::Result<(u64, u64, u64)> {
+        let record = frame(body);
+        let (segment, offset) = self.append_raw(state, &record)?;
+
+        Ok((segment, offset, record.len() as u64))
+    }
+
+    /// Appends a framed record to the newest segment, without syncing it.
+    ///
+    /// # Returns
+    /// The number of the segment and the offset of the record
+    fn append_raw(&self, state: &mut PackState, record: &[u8]) -> std::io::Result<(u64, u64)> {
+        let number = match state.segments.last_key_value() {
+            Some((&number, segment)) if segment.size < SEGMENT_BYTES => number,
+            newest => {
+                let number = newest.map_or(1, |(number, _)| number + 1);
+                let file = File::options()
+                    .read(true)
+                    .write(true)
+                    .create_new(true)
+                    .open(segment_path(&self.dir, number))?;
+
+                state.segments.insert(
+                    number,
+                    Segment {
+                        file: Arc::new(file),
+                        size: 0,
+                        live: 0,
+                    },
+                );
+                number
+            }
+        };
+        let Some(segment) = state.segments.get_mut(&number) else {
+            unreachable!("the newest segment is open");
+        };
+
+        if let Err(e) = segment.file.write_all_at(record, segment.size) {
+            // Leave no partial record for the next one to follow.
+            let _ = segment.file.set_len(segment.size);
+            return Err(e);
+        }
+
+        let offset = segment.size;
+        segment.size += record.len() as u64;
+
+        Ok((number, offset))
+    }
+}
+
+impl PackState {
+    /// Applies a record read from a segment while rebuilding the index.
+    fn apply(&mut self, segment: u64, offset: u64, len: u64, record: Record) {
+        match record.put {
+            Some(put) => {
+                let slot = Slot {
+                    segment,
+                    offset,
+                    len,
+                    value_offset: put.value_offset,
+                    info: put.info,
+                    entry: IndexEntry {
+                        size: put.value_len,
+                        modified: put.modified,
+                        last_access: put.modified,
+                        hits: 0,
+                    },
+                };
+
+                if let Some(previous) = self.slots.insert(record.key.to_string(), slot) {
+                    self.used_bytes -= previous.entry.size;
+                }
+                self.used_bytes += put.value_len;
+            }
+            None => {
+                if let Some(previous) = self.slots.remove(record.key) {
+                    self.used_bytes -= previous.entry.size;
+                }
+            }
+        }
+    }
+
+    /// Records `slot` as the current record of `key`.
+    fn insert(&mut self, key: &str, slot: Slot) {
+        if let Some(segment) = self.segments.get_mut(&slot.segment) {
+            segment.live += slot.len;
+        }
+        self.used_bytes += slot.entry.size;
+
+        if let Some(previous) = self.slots.insert(key.to_string(), slot) {
+            self.forget(&previous);
+        }
+    }
+
+    /// Drops the current record of `key` from the index.
+    fn remove(&mut self, key: &str) {
+        if let Some(previous) = self.slots.remove(key) {
+            self.forget(&previous);
+        }
+    }
+
+    /// Accounts for `slot` no longer being current.
+    fn forget(&mut self, slot: &Slot) {
+        if let Some(segment) = self.segments.get_mut(&slot.segment) {
+            segment.live -= slot.len;
+        }
+        self.used_bytes -= slot.entry.size;
+    }
+
+    /// Records that the current record of `key` was copied to `segment` at `offset`.
+    fn moved(&mut self, key: &str, segment: u64, offset: u64) {
+        let Some(slot) = self.slots.get_mut(key) else {
+            return;
+        };
+
+        if let Some(old) = self.segments.get_mut(&slot.segment) {
+            old.live -= slot.len;
+        }
+        if let Some(new) = self.segments.get_mut(&segment) {
+            new.live += slot.len;
+        }
+        slot.segment = segment;
+        slot.offset = offset;
+    }
+
+    /// Returns the numbers of the segments, other than the newest, that are at least half dead.
+    fn compactable(&self) -> Vec<u64> {
+        let newest = self.segments.last_key_value().map(|(number, _)| *number);
+
+        self.segments
+            .iter()
+            .filter(|(number, segment)| {
+                Some(**number) != newest && (segment.size - segment.live) * 2 >= segment.size
+            })
+            .map(|(number, _)| *number)
+            .collect()
+    }
+}
+
+/// Returns the path of segment `number` in the `.pack` directory `dir`.
+fn segment_path(dir: &Path, number: u64) -> PathBuf {
+    dir.join(format!("{:016x}.{}", number, SEGMENT_EXTENSION))
+}
+
+/// Returns the number of the newest segment, or 0 if there is none.
+fn newest_segment(state: &PackState) -> u64 {
+    state
+        .segments
+        .last_key_value()
+        .map_or(0, |(number, _)| *number)
+}
+
+/// Returns the files of the segments numbered `first` or higher, which
+/// records appended since `first` was the newest segment went to.
+fn segment_files(state: &PackState, first: u64) -> Vec<Arc<File>> {
+    state
+        .segments
+        .range(first..)
+        .map(|(_, segment)| segment.file.clone())
+        .collect()
+}
+
+/// Flushes `files` to disk.
+fn sync(files: &[Arc<File>]) -> std::io::Result<()> {
+    files.iter().try_for_each(|file| file.sync_data())
+}
+
+/// Returns the file of the segment holding the record of `slot`.
+fn segment_file(segments: &BTreeMap<u64, Segment>, slot: &Slot) -> std::io::Result<Arc<File>> {
+    segments
+        .get(&slot.segment)
+        .map(|segment| segment.file.clone())
+        .ok_or_else(|| invalid("Pack segment is missing"))
+}
+
+/// Reads the record of `slot` from `file` and checks it against its hash.
+fn read_record(file: &File, slot: &Slot) -> std::io::Result<Vec<u8>> {
+    let mut record = vec![0; slot.len as usize];
+    file.read_exact_at(&mut record, slot.offset)?;
+
+    if !matches(&record) {
+        return Err(invalid("Pack record does not match its hash"));
+    }
+
+    Ok(record)
+}
+
+/// Returns `true` if the framed `record` matches its hash.
+fn matches(record: &[u8]) -> bool {
+    record.len() >= PREFIX_LEN
+        && blake3::hash(&record[PREFIX_LEN..]).as_bytes()[..HASH_LEN] == record[4..PREFIX_LEN]
+}
+
+/// Frames a record body with its length and hash.
+fn frame(body: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(PREFIX_LEN + body.len());
+    record.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    record.extend_from_slice(&blake3::hash(body).as_bytes()[..HASH_LEN]);
+    record.extend_from_slice(body);
+    record
+}
+
+/// Builds the body of a record holding an entry.
+fn put_body(
+    key: &str,
+    modified: SystemTime,
+    info: Option<&EntryInfo>,
+    stored: &[u8],
+) -> std::io::Result<Vec<u8>> {
+    let info = match info {
+        Some(info) => toml::to_string(info)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+        None => String::new(),
+    };
+    let info_len = u16::try_from(info.len()).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Entry metadata is too long",
+        )
+    })?;
+    let nanos = modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+
+    let mut body = Vec::with_capacity(1 + 2 + key.len() + 8 + 2 + info.len() + stored.len());
+    body.push(PUT);
+    body.extend_from_slice(&(key.len() as u16).to_le_bytes());
+    body.extend_from_slice(key.as_bytes());
+    body.extend_from_slice(&nanos.to_le_bytes());
+    body.extend_from_slice(&info_len.to_le_bytes());
+    body.extend_from_slice(info.as_bytes());
+    body.extend_from_slice(stored);
+
+    if body.len() > u32::MAX as usize {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Entry is too large for a pack store",
+        ));
+    }
+
+    Ok(body)
+}
+
+/// Builds the body of a record removing an entry.
+fn del_body(key: &str) -> Vec<u8> {
+    [
+        &[DEL][..],
+        &(key.len() as u16).to_le_bytes(),
+        key.as_bytes(),
+    ]
+    .concat()
+}
+
+/// Reads the valid records of a segment's content.
+///
+/// Damaged bytes are skipped up to the next offset holding a valid record.
+///
+/// # Returns
+/// The offset, length and content of every record, the end of the last
+/// record, beyond which the content is torn or damaged, and the number of
+/// damaged bytes skipped before that end
+fn scan(content: &[u8]) -> (Vec<(u64, u64, Record<'_>)>, u64, u64) {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    let mut end = 0;
+    let mut skipped = 0;
+
+    while offset < content.len() {
+        let Some((record, parsed)) = record_at(content, offset) else {
+            // Trust the length of a damaged record if a valid one follows it,
+            // or else look for the next valid record byte by byte.
+            offset = match declared_end(content, offset) {
+                Some(next) if record_at(content, next).is_some() => next,
+                _ => offset + 1,
+            };
+            continue;
+        };
+
+        skipped += offset - end;
+        records.push((offset as u64, record.len() as u64, parsed));
+        offset += record.len();
+        end = offset;
+    }
+
+    (records, end as u64, skipped as u64)
+}
+
+/// Returns where the record starting at `offset` of `content` ends according
+/// to its length, if that is within the content.
+fn declared_end(content: &[u8], offset: usize) -> Option<usize> {
+    let prefix = content.get(offset..offset + PREFIX_LEN)?;
+    let end = offset + PREFIX_LEN + u32::from_le_bytes(prefix[..4].try_into().ok()?) as usize;
+
+    (end <= content.len()).then_some(end)
+}
+
+/// Returns the framed record starting at `offset` of `content` and its
+/// parsed body, if a valid one starts there.
+fn record_at(content: &[u8], offset: usize) -> Option<(&[u8], Record<'_>)> {
+    let prefix = content.get(offset..offset + PREFIX_LEN)?;
+    let body_len = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+    let record = content.get(offset..offset + PREFIX_LEN + body_len)?;
+
+    if !matches(record) {
+        return None;
+    }
+
+    Some((record, parse(&record[PREFIX_LEN..])?))
+}
+
+/// Parses the body of a record.
+fn parse(body: &[u8]) -> Option<Record<'_>> {
+    let (&kind, rest) = body.split_first()?;
+    let (key_len, rest) = rest.split_at_checked(2)?;
+    let (key, rest) =
+        rest.split_at_checked(u16::from_le_bytes([key_len[0], key_len[1]]) as usize)?;
+    let key = std::str::from_utf8(key).ok()?;
+
+    let put = match kind {
+        DEL if rest.is_empty() => None,
+        PUT => {
+            let (nanos, rest) = rest.split_at_checked(8)?;
+            let (info_len, rest) = rest.split_at_checked(2)?;
+            let (info, stored) =
+                rest.split_at_checked(u16::from_le_bytes([info_len[0], info_len[1]]) as usize)?;
+
+            let info = match info {
+                [] => None,
+                info => Some(toml::from_str(std::str::from_utf8(info).ok()?).ok()?),
+            };
+
+            Some(Put {
+                modified: UNIX_EPOCH
+                    + Duration::from_nanos(u64::from_le_bytes(nanos.try_into().ok()?)),
+                info,
+                value_offset: (PREFIX_LEN + body.len() - stored.len()) as u64,
+                value_len: stored.len() as u64,
+            })
+        }
+        _ => return None,
+    };
+
+    Some(Record { key, put })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::CacheableError,
+        fs::{FsCache, ReadWrite, tests::stored_size},
+        meta::EntryVersion,
+    };
+
+    async fn pack_cache(dir: &Path) -> FsCache<ReadWrite> {
+        FsCache::<ReadWrite>::new_pack(dir, 100).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pack_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = pack_cache(dir.path()).await;
+        let info = EntryInfo {
+            validator: Some("\"etag\"".to_string()),
+            fresh_until: Some(42),
+        };
+
+        cache.put("key1", b"Hello").await.unwrap();
+        cache.put_with_info("key2", b"world", &info).await.unwrap();
+        assert_eq!(cache.get("key1").await, Some(b"Hello".to_vec()));
+        assert_eq!(
+            cache.get_with_info("key2").await,
+            Some((b"world".to_vec(), info.clone()))
+        );
+        assert_eq!(cache.get("missing").await, None);
+
+        assert!(!cache.put_if_absent("key1", b"again").await.unwrap());
+        assert!(
+            !cache
+                .compare_and_swap("key1", &EntryVersion::of(b"other"), b"swapped")
+                .await
+                .unwrap()
+        );
+        assert!(
+            cache
+                .compare_and_swap("key1", &EntryVersion::of(b"Hello"), b"swapped")
+                .await
+                .unwrap()
+        );
+        assert_eq!(cache.get("key1").await, Some(b"swapped".to_vec()));
+
+        assert!(
+            cache
+                .remove_blocking("key1", std::time::Instant::now())
+                .unwrap()
+        );
+        assert_eq!(cache.get("key1").await, None);
+
+        // Only segments and the lock are in the directory.
+        assert_eq!(
+            std::fs::read_dir(dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect::<Vec<_>>(),
+            [PACK_DIR]
+        );
+
+        // The index is rebuilt when the store is opened again.
+        drop(cache);
+        let cache = pack_cache(dir.path()).await;
+        assert_eq!(cache.get("key1").await, None);
+        assert_eq!(
+            cache.get_with_info("key2").await,
+            Some((b"world".to_vec(), info))
+        );
+        assert_eq!(cache.entries().await.unwrap().len(), 1);
+
+        // A store is used by one cache at a time.
+        let opened = FsCache::<ReadWrite>::new_pack(dir.path(), 100).await;
+        assert_eq!(opened.err().unwrap().kind(), std::io::ErrorKind::WouldBlock);
+
+        // The directory cannot be opened as files.
+        let opened = FsCache::<ReadWrite>::new_write(dir.path(), 100).await;
+        assert_eq!(
+            opened.err().unwrap().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+
+        assert_eq!(cache.clear().await.unwrap(), 1);
+        assert!(cache.entries().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pack_encrypted_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = pack_cache(dir.path()).await;
+        cache.set_encryption(Some(Keyring::new([1; 32], [])));
+        cache.put("key1", b"secret evidence").await.unwrap();
+
+        // Records hold no hash of the value.
+        let version = EntryVersion::of(b"secret evidence");
+        for segment in std::fs::read_dir(dir.path().join(PACK_DIR)).unwrap() {
+            let content = std::fs::read(segment.unwrap().path()).unwrap();
+            assert!(
+                !content
+                    .windows(32)
+                    .any(|window| window == version.as_bytes())
+            );
+        }
+
+        assert!(
+            cache
+                .compare_and_swap("key1", &version, b"evidence")
+                .await
+                .unwrap()
+        );
+        assert_eq!(cache.get("key1").await, Some(b"evidence".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_pack_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = pack_cache(dir.path()).await;
+        cache.set_limit(2);
+
+        for key in ["key1", "key2", "key3"] {
+            cache.put(key, b"data").await.unwrap();
+        }
+        assert_eq!(cache.get("key1").await, None);
+        assert_eq!(cache.used_bytes().unwrap(), 2 * stored_size("key1", 4));
+
+        cache.set_eviction(EvictionPolicy::Reject);
+        match cache.put("key4", b"data").await {
+            Err(CacheableError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::StorageFull),
+            other => panic!("expected StorageFull, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pack_recovers_torn_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = pack_cache(dir.path()).await;
+        cache.put("key1", b"Hello").await.unwrap();
+        cache.put("key2", b"world").await.unwrap();
+        drop(cache);
+
+        // A crash in the middle of appending the second record
+        let segment = segment_path(&dir.path().join(PACK_DIR), 1);
+        let len = std::fs::metadata(&segment).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&segment)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let cache = pack_cache(dir.path()).await;
+        assert_eq!(cache.get("key1").await, Some(b"Hello".to_vec()));
+        assert_eq!(cache.get("key2").await, None);
+
+        cache.put("key2", b"again").await.unwrap();
+        drop(cache);
+        let cache = pack_cache(dir.path()).await;
+        assert_eq!(cache.get("key2").await, Some(b"again".to_vec()));
+    }
+
+    #[test]
+    fn test_pack_skips_damaged_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(PackStore::open(dir.path()).unwrap());
+        let limits = Limits {
+            items: usize::MAX,
+            max_bytes: None,
+            policy: EvictionPolicy::Lru,
+        };
+
+        // Keep the segments as written.
+        store.compacting.store(true, Ordering::Release);
+
+        let mut damaged = Vec::new();
+        for keys in [["a", "b", "c"], ["d", "e", "f"]] {
+            for key in keys {
+                let value = key.as_bytes();
+                store
+                    .put(key, value, None, Condition::Always, None, limits)
+                    .unwrap();
+            }
+
+            // Flip the last byte of the middle record, then start a new segment.
+            let mut state = store.state();
+            let slot = &state.slots[keys[1]];
+            damaged.push((slot.segment, slot.offset + slot.len - 1));
+            state.segments.last_entry().unwrap().get_mut().size = SEGMENT_BYTES;
+        }
+        drop(store);
+
+        let mut sizes = Vec::new();
+        for (segment, offset) in damaged {
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .open(segment_path(&dir.path().join(PACK_DIR), segment))
+                .unwrap();
+            let mut byte = [0];
+            file.read_exact_at(&mut byte, offset).unwrap();
+            file.write_all_at(&[byte[0] ^ 1], offset).unwrap();
+            sizes.push(file.metadata().unwrap().len());
+        }
+
+        // The records after the damage, in older and newest segments, are kept.
+        let store = PackStore::open(dir.path()).unwrap();
+        let mut keys: Vec<_> = store.entries().into_iter().map(|e| e.key).collect();
+        keys.sort();
+        assert_eq!(keys, ["a", "c", "d", "f"]);
+        assert_eq!(store.get("f", false).unwrap().unwrap().0, b"f");
+
+        for (number, size) in [1, 2].into_iter().zip(sizes) {
+            let path = segment_path(store.dir(), number);
+            assert_eq!(std::fs::metadata(path).unwrap().len(), size);
+        }
+    }
+
+    #[test]
+    fn test_pack_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(PackStore::open(dir.path()).unwrap());
+        let limits = Limits {
+            items: usize::MAX,
+            max_bytes: None,
+            policy: EvictionPolicy::Lru,
+        };
+        let put = |key: &str, value: &[u8]| {
+            store
+                .put(key, value, None, Condition::Always, None, limits)
+                .unwrap()
+        };
+
+        put("kept", b"kept");
+        put("removed", b"removed");
+        put("replaced", b"old");
+        {
+            // Start a new segment for the following records.
+            let mut state = store.state();
+            state.segments.last_entry().unwrap().get_mut().size = SEGMENT_BYTES;
+        }
+        put("replaced", b"new");
+        store.remove("removed").unwrap();
+
+        // Wait for a background compaction started by the writes.
+        while store.compacting.load(Ordering::Acquire) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        store.compact().unwrap();
+        assert_eq!(store.state().segments.len(), 1);
+        assert!(!segment_path(store.dir(), 1).exists());
+        drop(store);
+
+        let store = PackStore::open(dir.path()).unwrap();
+        let mut keys: Vec<_> = store.entries().into_iter().map(|e| e.key).collect();
+        keys.sort();
+        assert_eq!(keys, ["kept", "replaced"]);
+        assert_eq!(store.get("replaced", false).unwrap().unwrap().0, b"new");
+    }
+}
@@ -0,0 +1,526 @@
+//! # OmneCache Configuration Reload
+//!
+//! Applying an edited [`OmneCacheCfg`] to a running [`OmneCache`].
+//!
+//! [`OmneCache::reload`] compares a configuration with the layers of the cache
+//! and applies every difference that can be applied live:
+//!
+//! * `memory`, `sideload`, `disk`: enabling or disabling the layer
+//! * `memory.items`: the capacity of the memory layer
+//! * `disk.items`: the item limit of the disk layer
+//! * `disk.max_bytes`: the byte limit of the disk layer
+//! * `disk.eviction`: the eviction policy of the disk layer
+//! * `disk.compression`: how the disk layer compresses entries written from then on
+//! * `disk.encryption`: the keys the disk layer encrypts and decrypts entries with
+//! * `sideload.path`: the sideloaded content directory
+//! * `sideload.items`: the number of items the sideload layer reports as its limit
+//! * `ttl`: how long values stored from then on stay fresh
+//!
+//! Moving the disk layer to another `disk.path` or changing its `disk.layout`
+//! or `disk.backend` is not applied live and is reported in [`ReloadReport::restart_required`]
+//! instead. The whole
+//! configuration is validated before anything changes, so an invalid
+//! configuration leaves the cache as it was.
+//!
+//! [`OmneCache::watch_config`] polls a TOML file, such as
+//! `examples/default.toml`, and reloads it whenever it changes.
+//!
+//! # Example
+//! ```rust,no_run
+//! use std::sync::Arc;
+//! use omnecache::OmneCache;
+//!
+//! async fn follow_config(cache: Arc<OmneCache>) {
+//!     let mut watcher = cache.watch_config("/etc/SomeOmneCacheApp/cache.toml");
+//!
+//!     while let Some(reload) = watcher.next().await {
+//!         match reload {
+//!             Ok(report) => println!("needs a restart: {:?}", report.restart_required),
+//!             Err(error) => eprintln!("kept the previous configuration: {error}"),
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use futures::{StreamExt, channel::mpsc};
+use lru::LruCache;
+
+use crate::{
+    OmneCache,
+    configuration::OmneCacheCfg,
+    encryption::Keyring,
+    error::ConfigurationError,
+    fs::FsCache,
+    lock_memory,
+    pack::Backend,
+    runtime::{self, Task},
+};
+
+/// Default time between two checks of a watched configuration file
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Summary of an [`OmneCache::reload`] call.
+///
+/// Settings are named as in the TOML configuration; a layer name stands for
+/// enabling or disabling that layer.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReloadReport {
+    /// Settings that were changed on the running cache
+    pub applied: Vec<&'static str>,
+    /// Settings that differ from the running cache but only take effect once
+    /// the cache is rebuilt
+    pub restart_required: Vec<&'static str>,
+}
+
+/// Outcome of reloading a watched configuration file
+pub type Reload = Result<ReloadReport, ConfigurationError>;
+
+/// Handle to a configuration watcher started by [`OmneCache::watch_config`].
+///
+/// Dropping the handle stops the watcher.
+pub struct ConfigWatcher {
+    task: Task<()>,
+    reloads: mpsc::UnboundedReceiver<Reload>,
+}
+
+impl ConfigWatcher {
+    /// Waits until the watched file changed and was reloaded.
+    ///
+    /// # Returns
+    /// * `Some(Ok(ReloadReport))`: What the reload changed
+    /// * `Some(Err(ConfigurationError))`: If the file could not be read, parsed
+    ///   or validated; the previous configuration stays in effect
+    /// * `None`: Once the watcher stopped, for example because the cache was dropped
+    pub async fn next(&mut self) -> Option<Reload> {
+        self.reloads.next().await
+    }
+
+    /// Returns `true` once the watcher has stopped.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Stops watching the file.
+    pub fn stop(&self) {
+        self.task.abort()
+    }
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl OmneCache {
+    /// Applies `cfg` to the running cache.
+    ///
+    /// Layers are attached, detached and resized as described in the
+    /// [module documentation](crate::reload). Detaching a layer waits for the
+    /// operations using it to finish.
+    ///
+    /// # Parameters
+    /// * `cfg`: The configuration the cache should follow
+    ///
+    /// # Returns
+    /// * `Ok(ReloadReport)`: The settings that were applied and those that need a restart
+    /// * `Err(ConfigurationError)`: If the configuration is invalid or a new layer
+    ///   failed to initialize; the cache is left unchanged
+    pub async fn reload(&self, cfg: &OmneCacheCfg) -> Result<ReloadReport, ConfigurationError> {
+        let mut report = ReloadReport::default();
+
+        // Validate everything and open new layers before changing anything.
+        let capacity = match cfg.memory {
+            Some(memory) if !memory.disabled => Some(memory.capacity()?),
+            _ => None,
+        };
+
+        let sideload = match &cfg.sideload {
+            Some(sideload) if !sideload.disabled => Some((sideload.location()?, sideload.items)),
+            _ => None,
+        };
+
+        let disk = match &cfg.disk {
+            Some(disk) if !disk.disabled => Some((disk.location()?, disk)),
+            _ => None,
+        };
+
+        let current_sideload = self.sideload.get();
+        let mut sideload_items = None;
+        let sideload = match (sideload, current_sideload) {
+            (Some((path, items)), Some(current)) if path == current.path() => {
+                if items != current.configured_items() {
+                    sideload_items = Some(items);
+                }
+                None
+            }
+            (Some((path, items)), current) => {
+                let name = if current.is_some() {
+                    "sideload.path"
+                } else {
+                    "sideload"
+                };
+                let sideload = FsCache::new_read(path).await?;
+                sideload.set_configured_items(items);
+                Some((name, Some(Arc::new(sideload))))
+            }
+            (None, Some(_)) => Some(("sideload", None)),
+            (None, None) => None,
+        };
+
+        let current_disk = self.disk.get();
+        let mut disk_limit = None;
+        let mut disk_max_bytes = None;
+        let mut disk_eviction = None;
+        let mut disk_compression = None;
+        let mut disk_encryption = None;
+        let disk = match (disk, current_disk) {
+            (Some(((path, items), cfg)), Some(current)) => {
+                if path != current.path() {
+                    report.restart_required.push("disk.path");
+                }
+                if cfg.backend != current.backend() {
+                    report.restart_required.push("disk.backend");
+                } else if cfg.backend == Backend::Files && cfg.layout != current.layout() {
+                    report.restart_required.push("disk.layout");
+                }
+                if items != current.limit() {
+                    disk_limit = Some(items);
+                }
+                if cfg.max_bytes != current.max_bytes() {
+                    disk_max_bytes = Some(cfg.max_bytes);
+                }
+                if cfg.eviction != current.eviction() {
+                    disk_eviction = Some(cfg.eviction);
+                }
+                if cfg.compression != current.compression() {
+                    disk_compression = Some(cfg.compression);
+                }
+                let keyring = cfg.keyring()?;
+                if keyring.as_ref().map(Keyring::ids) != current.encryption().map(|k| k.ids()) {
+                    disk_encryption = Some(keyring);
+                }
+                None
+            }
+            (Some((_, cfg)), None) => Some(Some(Arc::new(cfg.as_fs_cache().await?))),
+            (None, Some(_)) => Some(None),
+            (None, None) => None,
+        };
+
+        // Apply the changes.
+        let ttl = cfg.ttl.map(Duration::from_secs);
+        if ttl != self.ttl() {
+            self.set_ttl(ttl);
+            report.applied.push("ttl");
+        }
+
+        let current_capacity = self.memory().map(|memory| lock_memory(&memory).cap());
+        match (capacity, current_capacity) {
+            (Some(capacity), Some(current)) if capacity != current => {
+                self.resize_memory(capacity);
+                report.applied.push("memory.items");
+            }
+            (Some(capacity), None) => {
+                let memory = Arc::new(Mutex::new(LruCache::new(capacity)));
+                self.memory.replace(Some(memory)).await;
+                report.applied.push("memory");
+            }
+            (None, Some(_)) => {
+                self.memory.replace(None).await;
+                report.applied.push("memory");
+            }
+            _ => {}
+        }
+
+        if let Some((name, sideload)) = sideload {
+            self.sideload.replace(sideload).await;
+            report.applied.push(name);
+        }
+
+        if let Some(items) = sideload_items
+            && let Some(sideload) = self.sideload.get()
+        {
+            sideload.set_configured_items(items);
+            report.applied.push("sideload.items");
+        }
+
+        if let Some(disk) = disk {
+            self.disk.replace(disk).await;
+            report.applied.push("disk");
+        }
+
+        if let Some(items) = disk_limit {
+            self.set_disk_limit(items);
+            report.applied.push("disk.items");
+        }
+
+        if let Some(max_bytes) = disk_max_bytes {
+            self.set_disk_max_bytes(max_bytes);
+            report.applied.push("disk.max_bytes");
+        }
+
+        if let Some(eviction) = disk_eviction
+            && let Some(disk) = self.disk.get()
+        {
+            disk.set_eviction(eviction);
+            report.applied.push("disk.eviction");
+        }
+
+        if let Some(compression) = disk_compression
+            && let Some(disk) = self.disk.get()
+        {
+            disk.set_compression(compression);
+            report.applied.push("disk.compression");
+        }
+
+        if let Some(keyring) = disk_encryption
+            && let Some(disk) = self.disk.get()
+        {
+            disk.set_encryption(keyring);
+            report.applied.push("disk.encryption");
+        }
+
+        Ok(report)
+    }
+
+    /// Starts reloading the TOML configuration at `path` whenever it changes.
+    ///
+    /// This is equivalent to [`OmneCache::watch_config_with`] checking the file
+    /// every [`DEFAULT_WATCH_INTERVAL`].
+    ///
+    /// # Panics
+    /// With the default `tokio` feature, this method must be called from within
+    /// a tokio runtime.
+    pub fn watch_config(self: &Arc<Self>, path: impl Into<PathBuf>) -> ConfigWatcher {
+        self.watch_config_with(path, DEFAULT_WATCH_INTERVAL)
+    }
+
+    /// Starts reloading the TOML configuration at `path` whenever it changes.
+    ///
+    /// The file is assumed to match the running cache when watching starts; it
+    /// is reloaded with [`OmneCache::reload`] each time its modification time or
+    /// size changes. The watcher does not keep the cache alive and stops once
+    /// the cache is dropped.
+    ///
+    /// # Parameters
+    /// * `path`: The TOML configuration file to watch
+    /// * `interval`: Time between two checks of the file
+    ///
+    /// # Returns
+    /// A [`ConfigWatcher`] reporting the outcome of every reload.
+    ///
+    /// # Panics
+    /// With the default `tokio` feature, this method must be called from within
+    /// a tokio runtime.
+    pub fn watch_config_with(
+        self: &Arc<Self>,
+        path: impl Into<PathBuf>,
+        interval: Duration,
+    ) -> ConfigWatcher {
+        let cache = Arc::downgrade(self);
+        let path = path.into();
+        let mut seen = stamp(&path);
+        let (tx, reloads) = mpsc::unbounded();
+
+        let task = runtime::spawn(async move {
+            loop {
+                runtime::sleep(interval).await;
+
+                let current = {
+                    let path = path.clone();
+                    runtime::unblock(move || stamp(&path)).await.ok().flatten()
+                };
+
+                if current == seen {
+                    if cache.strong_count() == 0 {
+                        break;
+                    }
+                    continue;
+                }
+                seen = current;
+
+                let Some(cache) = cache.upgrade() else {
+                    break;
+                };
+
+                let reload = match load(path.clone()).await {
+                    Ok(cfg) => cache.reload(&cfg).await,
+                    Err(error) => Err(error),
+                };
+
+                if tx.unbounded_send(reload).is_err() {
+                    break;
+                }
+            }
+        });
+
+        ConfigWatcher { task, reloads }
+    }
+}
+
+/// Returns what identifies a version of the file at `path`, if it exists.
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Reads and parses the TOML configuration at `path`.
+async fn load(path: PathBuf) -> Result<OmneCacheCfg, ConfigurationError> {
+    let text = runtime::unblock(move || std::fs::read_to_string(path)).await??;
+    Ok(toml::from_str(&text)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        compression::Compression,
+        configuration::{DiskCfg, MemoryCfg, SideloadCfg},
+        encryption::Encryption,
+        fs::tests::read_only_fixture,
+        layout::Layout,
+    };
+
+    use super::*;
+
+    fn cfg(disk: &Path) -> OmneCacheCfg {
+        OmneCacheCfg {
+            memory: Some(MemoryCfg {
+                disabled: false,
+                items: Some(10),
+            }),
+            disk: Some(DiskCfg {
+                disabled: false,
+                path: Some(disk.to_string_lossy().to_string()),
+                items: Some(100),
+                max_bytes: None,
+                eviction: Default::default(),
+                layout: Default::default(),
+                repair: false,
+                compression: None,
+                encryption: None,
+                backend: Default::default(),
+            }),
+            sideload: None,
+            ttl: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reload_applies_live_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let moved = tempfile::tempdir().unwrap();
+        let sideload = read_only_fixture();
+        let cache = OmneCache::try_from(cfg(dir.path())).await.unwrap();
+
+        let mut next = cfg(moved.path());
+        next.memory.as_mut().unwrap().items = Some(2);
+        next.disk.as_mut().unwrap().items = Some(50);
+        next.disk.as_mut().unwrap().max_bytes = Some(4096);
+        next.disk.as_mut().unwrap().layout = Layout::Sharded;
+        next.disk.as_mut().unwrap().compression = Some(Compression::default());
+        let key_file = moved.path().join("cache.key");
+        std::fs::write(&key_file, [7; 32]).unwrap();
+        next.disk.as_mut().unwrap().encryption = Some(Encryption {
+            key_file: Some(key_file.to_string_lossy().to_string()),
+            ..Default::default()
+        });
+        next.sideload = Some(SideloadCfg {
+            disabled: false,
+            path: Some(sideload.path().to_string_lossy().to_string()),
+            items: None,
+        });
+        next.ttl = Some(60);
+
+        let report = cache.reload(&next).await.unwrap();
+        assert_eq!(
+            report.applied,
+            vec![
+                "ttl",
+                "memory.items",
+                "sideload",
+                "disk.items",
+                "disk.max_bytes",
+                "disk.compression",
+                "disk.encryption"
+            ]
+        );
+        assert_eq!(report.restart_required, vec!["disk.path", "disk.layout"]);
+        assert_eq!(lock_memory(&cache.memory().unwrap()).cap().get(), 2);
+        assert_eq!(cache.sideload.get().unwrap().path(), sideload.path());
+        assert_eq!(cache.disk.get().unwrap().path(), dir.path());
+        assert_eq!(cache.disk.get().unwrap().limit(), 50);
+        assert_eq!(cache.disk.get().unwrap().max_bytes(), Some(4096));
+        assert_eq!(
+            cache.disk.get().unwrap().compression(),
+            Some(Compression::default())
+        );
+        assert_eq!(
+            cache.disk.get().unwrap().encryption().unwrap().ids(),
+            Keyring::new([7; 32], []).ids()
+        );
+        assert_eq!(cache.ttl(), Some(Duration::from_secs(60)));
+
+        next.sideload.as_mut().unwrap().items = Some(20);
+        let report = cache.reload(&next).await.unwrap();
+        assert_eq!(report.applied, vec!["sideload.items"]);
+        assert_eq!(cache.sideload.get().unwrap().configured_items(), Some(20));
+
+        next.memory = None;
+        next.sideload.as_mut().unwrap().disabled = true;
+        let report = cache.reload(&next).await.unwrap();
+        assert_eq!(report.applied, vec!["memory", "sideload"]);
+        assert!(cache.memory().is_none());
+        assert!(cache.sideload.get().is_none());
+
+        next.disk.as_mut().unwrap().backend = Backend::Pack;
+        let report = cache.reload(&next).await.unwrap();
+        assert_eq!(report.restart_required, vec!["disk.path", "disk.backend"]);
+    }
+
+    #[tokio::test]
+    async fn test_reload_rejects_invalid_configuration() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = OmneCache::try_from(cfg(dir.path())).await.unwrap();
+
+        let mut next = cfg(dir.path());
+        next.memory.as_mut().unwrap().items = Some(0);
+        next.ttl = Some(60);
+
+        assert!(cache.reload(&next).await.is_err());
+        assert_eq!(cache.ttl(), None);
+        assert_eq!(lock_memory(&cache.memory().unwrap()).cap().get(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_watch_config_reloads_changed_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("cache.toml");
+        let disk = dir.path().join("disk");
+        std::fs::write(&file, toml::to_string(&cfg(&disk)).unwrap()).unwrap();
+
+        let cache = Arc::new(OmneCache::try_from(cfg(&disk)).await.unwrap());
+        let mut watcher = cache.watch_config_with(&file, Duration::from_millis(20));
+
+        let mut next = cfg(&disk);
+        next.ttl = Some(30);
+        std::fs::write(&file, toml::to_string(&next).unwrap()).unwrap();
+        let report = watcher.next().await.unwrap().unwrap();
+        assert_eq!(report.applied, vec!["ttl"]);
+
+        std::fs::write(&file, "memory = [").unwrap();
+        assert!(matches!(
+            watcher.next().await,
+            Some(Err(ConfigurationError::ParseDeError(_)))
+        ));
+        assert_eq!(cache.ttl(), Some(Duration::from_secs(30)));
+
+        drop(cache);
+        assert!(watcher.next().await.is_none());
+    }
+}
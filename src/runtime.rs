@@ -0,0 +1,236 @@
+//! # OmneCache Runtime
+//!
+//! The executor services OmneCache relies on: running blocking filesystem work
+//! off the async threads, bounding waits by a deadline, and spawning background
+//! tasks such as [`OmneCache::warm`](crate::OmneCache::warm).
+//!
+//! The runtime providing them is selected with cargo features:
+//!
+//! * `tokio` (default): tokio's blocking pool, timers and task spawner. Calls
+//!   must be made from within a tokio runtime.
+//! * `smol`: smol's blocking pool, timers and global executor. These do not
+//!   depend on the caller's executor, so the cache also runs under async-std or
+//!   any other runtime.
+//!
+//! Exactly one of them should be enabled; use `default-features = false` to
+//! select `smol`. If both are enabled, tokio is used.
+
+use std::{future::Future, time::Instant};
+
+#[cfg(not(any(feature = "tokio", feature = "smol")))]
+compile_error!("omnecache needs an async runtime: enable the `tokio` or the `smol` feature");
+
+/// Error returned when an operation did not complete before its deadline
+fn timed_out() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::TimedOut,
+        "Cache operation did not complete before its deadline",
+    )
+}
+
+/// Starts `f` on the runtime's blocking thread pool; the returned future
+/// resolves to its result.
+///
+/// `f` starts running immediately, before the future is first polled.
+#[cfg(feature = "tokio")]
+pub(crate) fn unblock<F, R>(f: F) -> impl Future<Output = std::io::Result<R>>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let handle = tokio::task::spawn_blocking(f);
+    async { handle.await.map_err(std::io::Error::other) }
+}
+
+/// Starts `f` on the runtime's blocking thread pool; the returned future
+/// resolves to its result.
+///
+/// `f` starts running immediately, before the future is first polled.
+#[cfg(all(feature = "smol", not(feature = "tokio")))]
+pub(crate) fn unblock<F, R>(f: F) -> impl Future<Output = std::io::Result<R>>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let task = smol::unblock(f);
+    async { Ok(task.await) }
+}
+
+/// Waits for `future`, giving up with [`std::io::ErrorKind::TimedOut`] at `deadline`.
+#[cfg(feature = "tokio")]
+pub(crate) async fn timeout_at<F: Future>(
+    deadline: Instant,
+    future: F,
+) -> std::io::Result<F::Output> {
+    tokio::time::timeout_at(deadline.into(), future)
+        .await
+        .map_err(|_| timed_out())
+}
+
+/// Waits for `future`, giving up with [`std::io::ErrorKind::TimedOut`] at `deadline`.
+#[cfg(all(feature = "smol", not(feature = "tokio")))]
+pub(crate) async fn timeout_at<F: Future>(
+    deadline: Instant,
+    future: F,
+) -> std::io::Result<F::Output> {
+    smol::future::or(async { Ok(future.await) }, async {
+        smol::Timer::at(deadline).await;
+        Err(timed_out())
+    })
+    .await
+}
+
+/// Waits for `duration` without blocking the executor.
+#[cfg(feature = "tokio")]
+pub(crate) async fn sleep(duration: std::time::Duration) {
+    tokio::time::sleep(duration).await
+}
+
+/// Waits for `duration` without blocking the executor.
+#[cfg(all(feature = "smol", not(feature = "tokio")))]
+pub(crate) async fn sleep(duration: std::time::Duration) {
+    smol::Timer::after(duration).await;
+}
+
+/// Runs `f` on the blocking thread pool, waiting for it no longer than `deadline`.
+///
+/// `f` itself should also respect the deadline (for example by polling for
+/// its locks with one), since blocking work cannot be interrupted.
+pub(crate) async fn unblock_until<F, R>(deadline: Instant, f: F) -> std::io::Result<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    timeout_at(deadline, unblock(f)).await?
+}
+
+/// A background task spawned with [`spawn`].
+///
+/// Dropping a `Task` detaches it; the task keeps running until it completes
+/// or is aborted.
+pub(crate) struct Task<T> {
+    #[cfg(feature = "tokio")]
+    handle: tokio::task::JoinHandle<T>,
+    #[cfg(all(feature = "smol", not(feature = "tokio")))]
+    handle: Option<smol::Task<Result<T, futures::future::Aborted>>>,
+    #[cfg(all(feature = "smol", not(feature = "tokio")))]
+    abort: futures::future::AbortHandle,
+}
+
+/// Starts `future` as a background task on the runtime.
+#[cfg(feature = "tokio")]
+pub(crate) fn spawn<F>(future: F) -> Task<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    Task {
+        handle: tokio::spawn(future),
+    }
+}
+
+/// Starts `future` as a background task on the runtime.
+#[cfg(all(feature = "smol", not(feature = "tokio")))]
+pub(crate) fn spawn<F>(future: F) -> Task<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (future, abort) = futures::future::abortable(future);
+
+    Task {
+        handle: Some(smol::spawn(future)),
+        abort,
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T> Task<T> {
+    /// Returns `true` once the task has completed or was aborted.
+    pub(crate) fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Stops the task at its next await point.
+    pub(crate) fn abort(&self) {
+        self.handle.abort()
+    }
+
+    /// Waits for the task's output.
+    ///
+    /// # Returns
+    /// * `Ok(T)`: The output of the task
+    /// * `Err(std::io::Error)`: If the task was aborted or panicked
+    pub(crate) async fn join(self) -> std::io::Result<T> {
+        self.handle.await.map_err(std::io::Error::other)
+    }
+}
+
+#[cfg(all(feature = "smol", not(feature = "tokio")))]
+impl<T> Task<T> {
+    /// Returns `true` once the task has completed or was aborted.
+    pub(crate) fn is_finished(&self) -> bool {
+        self.handle.as_ref().is_none_or(|task| task.is_finished())
+    }
+
+    /// Stops the task at its next await point.
+    pub(crate) fn abort(&self) {
+        self.abort.abort()
+    }
+
+    /// Waits for the task's output.
+    ///
+    /// # Returns
+    /// * `Ok(T)`: The output of the task
+    /// * `Err(std::io::Error)`: If the task was aborted
+    pub(crate) async fn join(mut self) -> std::io::Result<T> {
+        match self.handle.take() {
+            Some(task) => task.await.map_err(std::io::Error::other),
+            None => Err(std::io::Error::other(futures::future::Aborted)),
+        }
+    }
+}
+
+#[cfg(all(feature = "smol", not(feature = "tokio")))]
+impl<T> Drop for Task<T> {
+    fn drop(&mut self) {
+        // Dropping a smol task cancels it; detach instead to match tokio.
+        if let Some(task) = self.handle.take() {
+            task.detach();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unblock_until_times_out() {
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let result =
+            unblock_until(deadline, || std::thread::sleep(Duration::from_millis(500))).await;
+
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        assert_eq!(
+            unblock_until(Instant::now() + Duration::from_secs(5), || 7)
+                .await
+                .unwrap(),
+            7
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spawned_task_can_be_aborted() {
+        let task = spawn(async {
+            futures::future::pending::<()>().await;
+        });
+        assert!(!task.is_finished());
+
+        task.abort();
+        assert!(task.join().await.is_err());
+        assert_eq!(spawn(async { 7 }).join().await.unwrap(), 7);
+    }
+}
@@ -0,0 +1,793 @@
+//! # OmneCache Snapshots
+//!
+//! Export and import of a cache's contents as a single tar archive.
+//!
+//! Copying a live cache directory can capture half-written entries. An export
+//! instead reads every entry under the same shared lock that [`FsCache::get`]
+//! uses, so each archived entry is a complete value. These reads are not
+//! recorded as accesses, so an export leaves the eviction order as it was.
+//! The archive layout is:
+//!
+//! * `disk/<key>`: one file per disk layer entry
+//! * `memory/<key>`: one file per memory layer entry (only if requested)
+//! * `manifest.toml`: the [`Manifest`], written last, which also carries each
+//!   entry's [`EntryInfo`] (validator and freshness)
+//!
+//! Archived values are plaintext. A disk layer that is
+//! [encrypted](crate::encryption) is only exported if
+//! [`ExportOptions::decrypt`] is set; its values are decrypted into the
+//! archive, which must then be protected like the unencrypted values, and are
+//! encrypted again with the importing cache's key, if any, when imported.
+//!
+//! Importing stages the archive's disk entries in a hidden directory of the
+//! disk layer and checks the archive against its manifest before applying
+//! anything, so a truncated or mismatched archive leaves the cache untouched.
+//! Disk entries are then written through [`FsCache::put`], so they land with
+//! the same atomic temporary-file-then-rename sequence as a regular write.
+//!
+//! [`FsCache::get`]: crate::fs::FsCache::get
+//! [`FsCache::put`]: crate::fs::FsCache::put
+
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    path::PathBuf,
+    time::SystemTime,
+};
+
+use futures::{SinkExt, StreamExt, channel::mpsc, executor::block_on};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Layer, MemoryEntry, OmneCache,
+    error::CacheableError,
+    fs::{default_deadline, validate_key},
+    lock_memory,
+    meta::{EntryInfo, unix_secs},
+    result::Result,
+    runtime,
+};
+
+/// Version of the manifest layout written by this crate; version 1 manifests
+/// carry no [`ManifestEntry::info`]
+pub const MANIFEST_VERSION: u32 = 2;
+
+/// Largest archived value, in bytes, that an import reads
+pub const MAX_RECORD_LEN: u64 = 1 << 30;
+
+/// Name of the manifest entry inside a snapshot archive
+const MANIFEST_PATH: &str = "manifest.toml";
+
+/// Prefix of the hidden directory an import stages disk entries in
+const STAGING_PREFIX: &str = ".import-";
+
+/// Options controlling what [`OmneCache::export_with`] writes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExportOptions {
+    /// Also archive the contents of the memory layer, which holds values in plaintext
+    pub include_memory: bool,
+    /// Export an [encrypted](crate::encryption) disk layer, writing its
+    /// values into the archive in plaintext
+    pub decrypt: bool,
+}
+
+/// A single entry listed in a snapshot [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Layer the entry was exported from
+    pub layer: Layer,
+    /// Complete cache key of the entry
+    pub key: String,
+    /// Size of the entry's value, in bytes
+    pub size: u64,
+    /// Last write time as seconds since the Unix epoch (export time for memory entries)
+    pub modified: u64,
+    /// Validator and freshness of the entry, if it has any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<EntryInfo>,
+}
+
+/// Description of a snapshot archive's contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Layout version of the manifest
+    pub version: u32,
+    /// Time the snapshot was taken, as seconds since the Unix epoch
+    pub created: u64,
+    /// Every entry stored in the archive
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// Summary of a completed [`OmneCache::import`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Entries written to the disk layer
+    pub disk: usize,
+    /// Entries inserted into the memory layer
+    pub memory: usize,
+    /// Entries ignored because their layer is disabled in this cache
+    pub skipped: usize,
+}
+
+/// A file travelling between the cache and the archive thread
+struct Record {
+    path: String,
+    data: Vec<u8>,
+    modified: u64,
+}
+
+/// An archived entry received by an import, kept until the manifest is checked
+struct Staged {
+    layer: Layer,
+    key: String,
+    size: u64,
+    value: StagedValue,
+    info: Option<EntryInfo>,
+}
+
+/// Where the value of a [`Staged`] entry is kept
+enum StagedValue {
+    /// In a file of the staging directory
+    File(PathBuf),
+    /// In memory, for the memory layer
+    Memory(Vec<u8>),
+    /// Nowhere, because the entry's layer is disabled in this cache
+    Skipped,
+}
+
+/// An archive read by an import that matches its manifest
+struct StagedSnapshot {
+    /// The archived entries, in archive order
+    entries: Vec<Staged>,
+    /// Records that belong to no layer
+    skipped: usize,
+    /// Where the disk entries are staged, if there are any
+    _dir: Option<StagingDir>,
+}
+
+/// The hidden directory an import stages disk entries in, removed when dropped
+struct StagingDir(PathBuf);
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Error returned when the archive thread stopped accepting records
+fn archive_closed() -> CacheableError {
+    std::io::Error::from(std::io::ErrorKind::BrokenPipe).into()
+}
+
+impl OmneCache {
+    /// Writes a snapshot of the disk layer to `writer` as a tar archive.
+    ///
+    /// This is equivalent to [`OmneCache::export_with`] with default options.
+    pub async fn export<W>(&self, writer: W) -> Result<Manifest>
+    where
+        W: Write + Send + 'static,
+    {
+        self.export_with(writer, ExportOptions::default()).await
+    }
+
+    /// Writes a snapshot of the cache to `writer` as a tar archive.
+    ///
+    /// Each disk entry is read under its shared lock, so concurrent writers can
+    /// never produce a torn entry in the archive. Entries removed while the export
+    /// runs are left out of both the archive and the manifest. Archive I/O runs on
+    /// a blocking thread, so `writer` may be a plain file or socket.
+    ///
+    /// The archive holds values in plaintext, so a disk layer that is
+    /// [encrypted](crate::encryption) is only exported with
+    /// [`ExportOptions::decrypt`].
+    ///
+    /// # Parameters
+    /// * `writer`: Destination of the tar archive
+    /// * `options`: Which layers to include
+    ///
+    /// # Returns
+    /// * `Ok(Manifest)`: The manifest written at the end of the archive
+    /// * `Err(CacheableError)`: If a layer could not be listed or the archive
+    ///   could not be written, or of kind [`std::io::ErrorKind::PermissionDenied`]
+    ///   if the disk layer is encrypted and `options` do not allow decrypting it
+    pub async fn export_with<W>(&self, writer: W, options: ExportOptions) -> Result<Manifest>
+    where
+        W: Write + Send + 'static,
+    {
+        if !options.decrypt
+            && let Some(disk) = self.disk.get()
+            && disk.encryption().is_some()
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "The disk layer is encrypted; exporting it writes its values in plaintext",
+            ))?;
+        }
+
+        let (mut tx, mut rx) = mpsc::channel::<Record>(1);
+
+        let archive = runtime::unblock(move || -> std::io::Result<()> {
+            let mut builder = tar::Builder::new(writer);
+
+            while let Some(record) = block_on(rx.next()) {
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(record.data.len() as u64);
+                header.set_mode(0o600);
+                header.set_mtime(record.modified);
+                builder.append_data(&mut header, &record.path, record.data.as_slice())?;
+            }
+
+            builder.into_inner()?.flush()
+        });
+
+        let manifest = self.send_snapshot(&mut tx, options).await;
+        drop(tx);
+
+        // A failing writer closes the channel, so report its error first.
+        archive.await??;
+        manifest
+    }
+
+    /// Streams every exported entry, followed by the manifest, to the archive thread.
+    async fn send_snapshot(
+        &self,
+        tx: &mut mpsc::Sender<Record>,
+        options: ExportOptions,
+    ) -> Result<Manifest> {
+        let mut manifest = Manifest {
+            version: MANIFEST_VERSION,
+            created: unix_secs(SystemTime::now()),
+            entries: Vec::new(),
+        };
+
+        if let Some(disk) = self.disk.get() {
+            for entry in disk.entries().await? {
+                // Skip entries removed since the listing. Exporting is not an
+                // access, so the entries keep their ranking for eviction.
+                let Some((data, info)) = disk
+                    .read_with_info(&entry.key, default_deadline(), false)
+                    .await
+                else {
+                    continue;
+                };
+
+                let record = (entry.key, data, info);
+                let modified = unix_secs(entry.modified);
+                send_record(tx, &mut manifest, Layer::Disk, record, modified).await?;
+            }
+        }
+
+        if options.include_memory
+            && let Some(memory) = self.memory()
+        {
+            let keys: Vec<String> = lock_memory(&memory)
+                .iter()
+                .map(|(k, _)| k.clone())
+                .collect();
+
+            for key in keys {
+                // Peek so exporting does not reorder the LRU.
+                let Some(entry) = lock_memory(&memory).peek(&key).cloned() else {
+                    continue;
+                };
+
+                let record = (key, entry.data, entry.info);
+                let modified = manifest.created;
+                send_record(tx, &mut manifest, Layer::Memory, record, modified).await?;
+            }
+        }
+
+        let data = toml::to_string(&manifest)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+            .into_bytes();
+
+        tx.send(Record {
+            path: MANIFEST_PATH.to_string(),
+            data,
+            modified: manifest.created,
+        })
+        .await
+        .map_err(|_| archive_closed())?;
+
+        Ok(manifest)
+    }
+
+    /// Loads a snapshot archive produced by [`OmneCache::export`] into this cache.
+    ///
+    /// The whole archive is read and checked against its manifest first: disk
+    /// entries are staged in a hidden directory of the disk layer, and memory
+    /// entries are kept in memory. Only an archive that matches its manifest is
+    /// applied: disk entries are written through
+    /// [`FsCache::put_with_info`](crate::fs::FsCache::put_with_info) and memory
+    /// entries are inserted into the memory layer, both with their archived
+    /// [`EntryInfo`]. Entries for a layer that is disabled in this cache are
+    /// skipped. An entry that cannot be written, for example because the disk
+    /// layer is full, stops the import after the entries applied before it.
+    ///
+    /// # Parameters
+    /// * `reader`: Source of the tar archive
+    ///
+    /// # Returns
+    /// * `Ok(ImportReport)`: Counters describing what was imported
+    /// * `Err(CacheableError)`: If the archive is unreadable, holds a value larger
+    ///   than [`MAX_RECORD_LEN`] or an invalid key, does not match its manifest,
+    ///   or an entry could not be staged or written
+    pub async fn import<R>(&self, reader: R) -> Result<ImportReport>
+    where
+        R: Read + Send + 'static,
+    {
+        let (mut tx, mut rx) = mpsc::channel::<Record>(1);
+
+        let archive = runtime::unblock(move || -> std::io::Result<()> {
+            let mut archive = tar::Archive::new(reader);
+
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+
+                if entry.header().entry_type() != tar::EntryType::Regular {
+                    continue;
+                }
+
+                let path = entry.path()?.to_string_lossy().into_owned();
+                let modified = entry.header().mtime().unwrap_or(0);
+                // The header's size is not trusted to preallocate.
+                let mut data = Vec::new();
+                (&mut entry)
+                    .take(MAX_RECORD_LEN + 1)
+                    .read_to_end(&mut data)?;
+
+                if data.len() as u64 > MAX_RECORD_LEN {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "Archived value exceeds the import size limit",
+                    ));
+                }
+
+                // The receiver only goes away when the import has already failed.
+                if block_on(tx.send(Record {
+                    path,
+                    data,
+                    modified,
+                }))
+                .is_err()
+                {
+                    break;
+                }
+            }
+
+            Ok(())
+        });
+
+        let staged = self.stage_snapshot(&mut rx).await;
+        drop(rx);
+
+        // Nothing is applied unless the whole archive could be read.
+        archive.await??;
+        self.apply_snapshot(staged?).await
+    }
+
+    /// Stages records from the archive thread and verifies them against the manifest.
+    async fn stage_snapshot(&self, rx: &mut mpsc::Receiver<Record>) -> Result<StagedSnapshot> {
+        let mut manifest: Option<Manifest> = None;
+        let mut staged = StagedSnapshot {
+            entries: Vec::new(),
+            skipped: 0,
+            _dir: None,
+        };
+
+        while let Some(record) = rx.next().await {
+            if record.path == MANIFEST_PATH {
+                let text = String::from_utf8(record.data)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                manifest = Some(
+                    toml::from_str(&text)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+                );
+                continue;
+            }
+
+            let Some((layer, key)) = record
+                .path
+                .split_once('/')
+                .and_then(|(dir, key)| Some((dir.parse::<Layer>().ok()?, key.to_string())))
+            else {
+                staged.skipped += 1;
+                continue;
+            };
+            validate_key(&key)?;
+
+            let size = record.data.len() as u64;
+            let value = match (layer, self.disk.get(), self.memory()) {
+                (Layer::Disk, Some(disk), _) => {
+                    let dir = match &staged._dir {
+                        Some(dir) => dir.0.clone(),
+                        None => {
+                            let dir = disk.path().join(format!(
+                                "{}{}",
+                                STAGING_PREFIX,
+                                uuid::Uuid::new_v4()
+                            ));
+                            runtime::unblock({
+                                let dir = dir.clone();
+                                move || std::fs::create_dir(dir)
+                            })
+                            .await??;
+                            staged._dir = Some(StagingDir(dir.clone()));
+                            dir
+                        }
+                    };
+
+                    // Staged files are numbered, as keys are only checked as file names.
+                    let path = dir.join(staged.entries.len().to_string());
+                    runtime::unblock({
+                        let path = path.clone();
+                        move || std::fs::write(path, record.data)
+                    })
+                    .await??;
+                    StagedValue::File(path)
+                }
+                (Layer::Memory, _, Some(_)) => StagedValue::Memory(record.data),
+                // Sideload content is read-only and never exported.
+                _ => StagedValue::Skipped,
+            };
+
+            staged.entries.push(Staged {
+                layer,
+                key,
+                size,
+                value,
+                info: None,
+            });
+        }
+
+        let manifest = manifest.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "Archive has no manifest")
+        })?;
+
+        let mut listed: HashMap<_, _> = manifest
+            .entries
+            .into_iter()
+            .map(|e| ((e.layer, e.key), (e.size, e.info)))
+            .collect();
+
+        // Every listed entry must be archived exactly once, with its listed size.
+        let complete = listed.len() == staged.entries.len()
+            && staged.entries.iter_mut().all(|entry| {
+                match listed.remove(&(entry.layer, entry.key.clone())) {
+                    Some((size, info)) if size == entry.size => {
+                        entry.info = info;
+                        true
+                    }
+                    _ => false,
+                }
+            });
+
+        if !complete {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Archive contents do not match its manifest",
+            ))?;
+        }
+
+        Ok(staged)
+    }
+
+    /// Writes the entries of a verified archive to their layers.
+    async fn apply_snapshot(&self, staged: StagedSnapshot) -> Result<ImportReport> {
+        let mut report = ImportReport {
+            skipped: staged.skipped,
+            ..Default::default()
+        };
+
+        for entry in staged.entries {
+            match (entry.value, self.disk.get(), self.memory()) {
+                (StagedValue::File(path), Some(disk), _) => {
+                    let data = runtime::unblock(move || std::fs::read(path)).await??;
+                    match &entry.info {
+                        Some(info) => disk.put_with_info(&entry.key, &data, info).await?,
+                        None => disk.put(&entry.key, &data).await?,
+                    }
+                    report.disk += 1;
+                }
+                (StagedValue::Memory(data), _, Some(memory)) => {
+                    let info = entry.info.unwrap_or_default();
+                    lock_memory(&memory).put(entry.key, MemoryEntry { data, info });
+                    report.memory += 1;
+                }
+                // Also covers a layer detached while the archive was read.
+                _ => report.skipped += 1,
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Sends one entry to the archive thread and records it in the manifest.
+async fn send_record(
+    tx: &mut mpsc::Sender<Record>,
+    manifest: &mut Manifest,
+    layer: Layer,
+    (key, data, info): (String, Vec<u8>, EntryInfo),
+    modified: u64,
+) -> Result<()> {
+    manifest.entries.push(ManifestEntry {
+        layer,
+        key: key.clone(),
+        size: data.len() as u64,
+        modified,
+        info: (info != EntryInfo::default()).then_some(info),
+    });
+
+    tx.send(Record {
+        path: format!("{}/{}", layer, key),
+        data,
+        modified,
+    })
+    .await
+    .map_err(|_| archive_closed())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        num::NonZeroUsize,
+        sync::{Arc, Mutex},
+    };
+
+    use lru::LruCache;
+
+    use super::*;
+    use crate::{
+        encryption::Keyring,
+        fs::{FsCache, tests::stored_value},
+    };
+
+    async fn cache(dir: &std::path::Path) -> OmneCache {
+        OmneCache::with_layers(
+            Some(Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(10).unwrap(),
+            )))),
+            None,
+            Some(Arc::new(FsCache::new_write(dir, 100).await.unwrap())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_export_import_round_trip() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source = cache(source_dir.path()).await;
+        source.put("a".to_string(), b"alpha").await.unwrap();
+        source.put("b".to_string(), b"beta").await.unwrap();
+        std::fs::write(source_dir.path().join("CustomString_c.tmp"), b"torn").unwrap();
+
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive_path = archive_dir.path().join("cache.tar");
+        let options = ExportOptions {
+            include_memory: true,
+            ..Default::default()
+        };
+        let manifest = source
+            .export_with(std::fs::File::create(&archive_path).unwrap(), options)
+            .await
+            .unwrap();
+
+        assert_eq!(manifest.version, MANIFEST_VERSION);
+        assert_eq!(manifest.entries.len(), 4);
+        assert!(manifest.entries.iter().all(|e| !e.key.ends_with(".tmp")));
+
+        let target_dir = tempfile::tempdir().unwrap();
+        let target = cache(target_dir.path()).await;
+        let report = target
+            .import(std::fs::File::open(&archive_path).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(report.disk, 2);
+        assert_eq!(report.memory, 2);
+        assert_eq!(
+            stored_value(target_dir.path().join("CustomString_a")),
+            b"alpha"
+        );
+        assert_eq!(lock_memory(&target.memory().unwrap()).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_export_disk_only_by_default() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source = cache(source_dir.path()).await;
+        source.put("a".to_string(), b"alpha").await.unwrap();
+
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive_path = archive_dir.path().join("cache.tar");
+        let manifest = source
+            .export(std::fs::File::create(&archive_path).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            manifest.entries,
+            vec![ManifestEntry {
+                layer: Layer::Disk,
+                key: "CustomString_a".to_string(),
+                size: 5,
+                modified: manifest.entries[0].modified,
+                info: None,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_manifest_mismatch() {
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive_path = archive_dir.path().join("cache.tar");
+
+        let mut builder = tar::Builder::new(std::fs::File::create(&archive_path).unwrap());
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            created: 0,
+            entries: vec![ManifestEntry {
+                layer: Layer::Disk,
+                key: "missing".to_string(),
+                size: 1,
+                modified: 0,
+                info: None,
+            }],
+        };
+        let data = toml::to_string(&manifest).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        builder
+            .append_data(&mut header, MANIFEST_PATH, data.as_bytes())
+            .unwrap();
+        builder.finish().unwrap();
+        drop(builder);
+
+        let target_dir = tempfile::tempdir().unwrap();
+        let target = cache(target_dir.path()).await;
+        let result = target
+            .import(std::fs::File::open(&archive_path).unwrap())
+            .await;
+
+        assert!(
+            matches!(result, Err(CacheableError::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_of_mismatched_archive_applies_nothing() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source = cache(source_dir.path()).await;
+        source.put("a".to_string(), b"alpha").await.unwrap();
+        source.put("b".to_string(), b"beta").await.unwrap();
+
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive_path = archive_dir.path().join("cache.tar");
+        source
+            .export(std::fs::File::create(&archive_path).unwrap())
+            .await
+            .unwrap();
+
+        // Drop the manifest's first entry, so the archive holds one entry too many.
+        let mut archive = tar::Archive::new(std::fs::File::open(&archive_path).unwrap());
+        let forged_path = archive_dir.path().join("forged.tar");
+        let mut builder = tar::Builder::new(std::fs::File::create(&forged_path).unwrap());
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+
+            if entry.path().unwrap().to_str() == Some(MANIFEST_PATH) {
+                let mut manifest: Manifest =
+                    toml::from_str(std::str::from_utf8(&data).unwrap()).unwrap();
+                manifest.entries.remove(0);
+                data = toml::to_string(&manifest).unwrap().into_bytes();
+            }
+
+            let mut header = entry.header().clone();
+            header.set_size(data.len() as u64);
+            builder
+                .append_data(&mut header, entry.path().unwrap(), data.as_slice())
+                .unwrap();
+        }
+        builder.into_inner().unwrap();
+
+        let target_dir = tempfile::tempdir().unwrap();
+        let target = cache(target_dir.path()).await;
+        let result = target
+            .import(std::fs::File::open(&forged_path).unwrap())
+            .await;
+
+        assert!(
+            matches!(result, Err(CacheableError::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData)
+        );
+        // Neither entry was applied and the staging directory is gone.
+        assert!(
+            target
+                .disk
+                .get()
+                .unwrap()
+                .entries()
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert!(std::fs::read_dir(target_dir.path()).unwrap().all(|e| {
+            !e.unwrap()
+                .file_name()
+                .to_string_lossy()
+                .starts_with(STAGING_PREFIX)
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_import_keeps_entry_info() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source = cache(source_dir.path()).await;
+        let info = EntryInfo {
+            validator: Some("\"v1\"".to_string()),
+            fresh_until: Some(4_000_000_000),
+        };
+        source
+            .disk
+            .get()
+            .unwrap()
+            .put_with_info("a", b"alpha", &info)
+            .await
+            .unwrap();
+
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive_path = archive_dir.path().join("cache.tar");
+        let manifest = source
+            .export(std::fs::File::create(&archive_path).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(manifest.entries[0].info, Some(info.clone()));
+
+        let target_dir = tempfile::tempdir().unwrap();
+        let target = cache(target_dir.path()).await;
+        target
+            .import(std::fs::File::open(&archive_path).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            target.disk.get().unwrap().get_with_info("a").await,
+            Some((b"alpha".to_vec(), info))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_of_encrypted_disk_layer() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source = cache(source_dir.path()).await;
+        let disk = source.disk.get().unwrap();
+        disk.set_encryption(Some(Keyring::new([1; 32], [])));
+        disk.put("a", b"secret").await.unwrap();
+
+        let archive_dir = tempfile::tempdir().unwrap();
+        let archive_path = archive_dir.path().join("cache.tar");
+        match source
+            .export(std::fs::File::create(&archive_path).unwrap())
+            .await
+        {
+            Err(CacheableError::Io(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied)
+            }
+            other => panic!("expected PermissionDenied, got {:?}", other),
+        }
+
+        // Asked for explicitly, the values are archived in plaintext.
+        let options = ExportOptions {
+            decrypt: true,
+            ..Default::default()
+        };
+        source
+            .export_with(std::fs::File::create(&archive_path).unwrap(), options)
+            .await
+            .unwrap();
+        let archive = std::fs::read(&archive_path).unwrap();
+        assert!(archive.windows(6).any(|window| window == b"secret"));
+    }
+}
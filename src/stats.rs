@@ -0,0 +1,208 @@
+//! # OmneCache Statistics
+//!
+//! Size and capacity of each cache layer, for capacity dashboards.
+//!
+//! [`OmneCache::stats`] reports one set of statistics per enabled layer: the
+//! memory layer's length, capacity and bytes, and the
+//! [`FsStats`] of the sideload and disk layers (see
+//! [`FsCache::stats`](crate::fs::FsCache::stats)).
+//!
+//! Like [`OmneCache::entries`], each layer is inspected in turn, so the
+//! statistics reflect each layer at a slightly different moment.
+
+use crate::{OmneCache, fs::FsStats, lock_memory, result::Result};
+
+/// Size and capacity of the memory layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Number of values held
+    pub len: usize,
+    /// Maximum number of values held before the least recently used is dropped
+    pub capacity: usize,
+    /// Total size of the values held, in bytes
+    pub bytes: u64,
+}
+
+/// Statistics of every enabled layer of an [`OmneCache`]; disabled layers are `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Statistics of the in-memory LRU layer
+    pub memory: Option<MemoryStats>,
+    /// Statistics of the read-only sideload layer
+    pub sideload: Option<FsStats>,
+    /// Statistics of the persistent disk layer
+    pub disk: Option<FsStats>,
+}
+
+impl OmneCache {
+    /// Reports the size and capacity of every enabled layer.
+    ///
+    /// # Returns
+    /// * `Ok(CacheStats)`: The statistics of each layer
+    /// * `Err(CacheableError)`: If the directory of a filesystem layer could not be read
+    pub async fn stats(&self) -> Result<CacheStats> {
+        let memory = self.memory().map(|memory| {
+            let memory = lock_memory(&memory);
+
+            MemoryStats {
+                len: memory.len(),
+                capacity: memory.cap().get(),
+                bytes: memory
+                    .iter()
+                    .map(|(_, value)| value.data.len() as u64)
+                    .sum(),
+            }
+        });
+
+        let sideload = match self.sideload.get() {
+            Some(sideload) => Some(sideload.stats().await?),
+            None => None,
+        };
+
+        let disk = match self.disk.get() {
+            Some(disk) => Some(disk.stats().await?),
+            None => None,
+        };
+
+        Ok(CacheStats {
+            memory,
+            sideload,
+            disk,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        num::NonZeroUsize,
+        sync::{Arc, Mutex},
+        time::{Duration, SystemTime},
+    };
+
+    use lru::LruCache;
+
+    use super::*;
+    use crate::{
+        eviction::EvictionPolicy,
+        fs::{
+            FsCache, FsLimits,
+            tests::{read_only_fixture, stored_size},
+        },
+    };
+
+    #[tokio::test]
+    async fn test_stats_reports_every_layer() {
+        let disk = tempfile::tempdir().unwrap();
+        let sideload = read_only_fixture();
+        let cache = OmneCache::with_layers(
+            Some(Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(10).unwrap(),
+            )))),
+            Some(Arc::new(FsCache::new_read(sideload.path()).await.unwrap())),
+            Some(Arc::new(
+                FsCache::new_write(disk.path(), 100).await.unwrap(),
+            )),
+        );
+        cache.put("a".to_string(), b"alpha").await.unwrap();
+        cache.put("b".to_string(), b"bravo!").await.unwrap();
+
+        let base = SystemTime::now() - Duration::from_secs(3600);
+        for (i, key) in ["CustomString_a", "CustomString_b"].into_iter().enumerate() {
+            std::fs::File::options()
+                .write(true)
+                .open(disk.path().join(key))
+                .unwrap()
+                .set_modified(base + Duration::from_secs(i as u64))
+                .unwrap();
+        }
+
+        // Left by a crashed write, with no live owner in its lock file
+        std::fs::write(disk.path().join("CustomString_c.tmp"), b"partial").unwrap();
+
+        let stats = cache.stats().await.unwrap();
+
+        assert_eq!(
+            stats.memory,
+            Some(MemoryStats {
+                len: 2,
+                capacity: 10,
+                bytes: 11,
+            })
+        );
+
+        let disk_stats = stats.disk.unwrap();
+        assert_eq!(disk_stats.entries, 2);
+        assert_eq!(
+            disk_stats.bytes,
+            stored_size("CustomString_a", 5) + stored_size("CustomString_b", 6)
+        );
+        assert_eq!(disk_stats.oldest.unwrap().key, "CustomString_a");
+        assert_eq!(disk_stats.newest.unwrap().key, "CustomString_b");
+        assert_eq!(disk_stats.orphaned_tmp_files, 1);
+        assert_eq!(disk_stats.orphaned_tmp_bytes, 7);
+        assert_eq!(
+            disk_stats.limits,
+            Some(FsLimits {
+                max_entries: 100,
+                max_bytes: None,
+                eviction: EvictionPolicy::default(),
+            })
+        );
+
+        let sideload_stats = stats.sideload.unwrap();
+        assert!(sideload_stats.entries > 0);
+        assert_eq!(sideload_stats.limits, None);
+
+        cache.sideload.get().unwrap().set_configured_items(Some(25));
+        assert_eq!(
+            cache.stats().await.unwrap().sideload.unwrap().limits,
+            Some(FsLimits {
+                max_entries: 25,
+                max_bytes: None,
+                eviction: EvictionPolicy::Reject,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stats_skips_writes_in_flight() {
+        let disk = tempfile::tempdir().unwrap();
+        let cache = OmneCache::with_layers(
+            None,
+            None,
+            Some(Arc::new(
+                FsCache::new_write(disk.path(), 100).await.unwrap(),
+            )),
+        );
+
+        // A write in flight in this process holds the key's lock.
+        let lock = crate::lock::lock_key(
+            &disk.path().join("key1.lock"),
+            crate::fs::default_deadline(),
+        )
+        .unwrap();
+        std::fs::write(disk.path().join("key1.tmp"), b"partial").unwrap();
+
+        let stats = cache.stats().await.unwrap();
+        assert_eq!(stats.memory, None);
+        assert_eq!(stats.sideload, None);
+
+        let disk_stats = stats.disk.unwrap();
+        assert_eq!(disk_stats.entries, 0);
+        assert_eq!(disk_stats.oldest, None);
+        assert_eq!(disk_stats.orphaned_tmp_files, 0);
+
+        drop(lock);
+        assert_eq!(
+            cache
+                .stats()
+                .await
+                .unwrap()
+                .disk
+                .unwrap()
+                .orphaned_tmp_files,
+            1
+        );
+    }
+}
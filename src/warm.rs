@@ -0,0 +1,485 @@
+//! # OmneCache Warming
+//!
+//! Background population of the memory layer from the sideload and disk layers.
+//!
+//! After a restart the memory layer is empty, and every request pays the cost of
+//! a filesystem read until the working set has been touched once. Warming loads a
+//! chosen set of entries into memory ahead of time:
+//!
+//! * [`WarmStrategy::Recent`]: the most recently written disk entries
+//! * [`WarmStrategy::Keys`]: an explicit list of cache keys
+//! * [`WarmStrategy::Prefix`]: every entry belonging to one [`Cacheable`] type
+//!
+//! Warming runs as a background task with a bounded number of concurrent reads,
+//! so [`OmneCache::get`] and [`OmneCache::put`] keep serving while it progresses.
+//! Entries already present in memory are never overwritten, which keeps values
+//! written by a concurrent `put` authoritative.
+
+use std::sync::Arc;
+
+use futures::{StreamExt, stream};
+
+use crate::{
+    Cacheable, MemoryEntry, MemoryLayer, OmneCache, cache_key,
+    fs::{FsCache, Read, ReadWrite},
+    lock_memory,
+    result::Result,
+    runtime::{self, Task},
+};
+
+/// Default number of entries read concurrently while warming
+pub const DEFAULT_WARM_CONCURRENCY: usize = 8;
+
+/// Selects which entries are loaded into the memory layer by [`OmneCache::warm`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WarmStrategy {
+    /// Load the `n` most recently written entries of the disk layer
+    Recent(usize),
+    /// Load the listed complete cache keys (in the format "PREFIX_key")
+    Keys(Vec<String>),
+    /// Load every sideload and disk entry stored under the given type prefix
+    Prefix(&'static str),
+}
+
+impl WarmStrategy {
+    /// Creates a strategy loading every entry stored for the cacheable type `C`.
+    pub fn prefix<C: Cacheable>() -> Self {
+        Self::Prefix(C::PREFIX)
+    }
+
+    /// Creates a strategy loading the given cacheable entries.
+    ///
+    /// The complete cache key of each entry is built the same way as
+    /// [`OmneCache::get`] builds it.
+    pub async fn keys<C: Cacheable>(entries: impl IntoIterator<Item = C>) -> Self {
+        let mut keys = Vec::new();
+
+        for entry in entries {
+            keys.push(cache_key(&entry).await);
+        }
+
+        Self::Keys(keys)
+    }
+}
+
+/// Summary of a completed warming run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WarmReport {
+    /// Entries read from sideload or disk and inserted into memory
+    pub loaded: usize,
+    /// Entries skipped because they were already present in memory
+    pub skipped: usize,
+    /// Entries that could not be found in any filesystem layer
+    pub missing: usize,
+}
+
+/// Handle to a background warming task started by [`OmneCache::warm`].
+///
+/// Dropping the handle does not stop the task; use [`WarmHandle::abort`] for that.
+pub struct WarmHandle(Task<Result<WarmReport>>);
+
+impl WarmHandle {
+    /// Returns `true` once the warming task has finished.
+    pub fn is_finished(&self) -> bool {
+        self.0.is_finished()
+    }
+
+    /// Stops the warming task. Entries loaded so far stay in memory.
+    pub fn abort(&self) {
+        self.0.abort()
+    }
+
+    /// Waits for the warming task to finish.
+    ///
+    /// # Returns
+    /// * `Ok(WarmReport)`: Counters describing what was loaded
+    /// * `Err(CacheableError)`: If a layer could not be listed or the task was aborted
+    pub async fn wait(self) -> Result<WarmReport> {
+        self.0.join().await?
+    }
+}
+
+/// Outcome of warming a single key
+enum Outcome {
+    Loaded,
+    Skipped,
+    Missing,
+}
+
+impl WarmReport {
+    fn record(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Loaded => self.loaded += 1,
+            Outcome::Skipped => self.skipped += 1,
+            Outcome::Missing => self.missing += 1,
+        }
+    }
+}
+
+impl OmneCache {
+    /// Starts warming the memory layer in the background.
+    ///
+    /// This is equivalent to [`OmneCache::warm_with`] using
+    /// [`DEFAULT_WARM_CONCURRENCY`] concurrent reads.
+    ///
+    /// # Panics
+    /// With the default `tokio` feature, this method must be called from within
+    /// a tokio runtime.
+    pub fn warm(&self, strategy: WarmStrategy) -> WarmHandle {
+        self.warm_with(strategy, DEFAULT_WARM_CONCURRENCY)
+    }
+
+    /// Starts warming the memory layer in the background with bounded concurrency.
+    ///
+    /// Entries are looked up in the same order as [`OmneCache::get`] (sideload,
+    /// then disk), so a warmed value is always the one `get` would have returned.
+    /// At most as many entries as the memory layer can hold are loaded. If the
+    /// memory layer is disabled, the task completes immediately with an empty report.
+    ///
+    /// # Parameters
+    /// * `strategy`: Which entries to load
+    /// * `concurrency`: Maximum number of entries read at the same time (at least one)
+    ///
+    /// # Returns
+    /// A [`WarmHandle`] that can be awaited for a [`WarmReport`] or aborted.
+    ///
+    /// # Panics
+    /// With the default `tokio` feature, this method must be called from within
+    /// a tokio runtime.
+    pub fn warm_with(&self, strategy: WarmStrategy, concurrency: usize) -> WarmHandle {
+        let memory = self.memory();
+        let sideload = self.sideload.get();
+        let disk = self.disk.get();
+
+        WarmHandle(runtime::spawn(async move {
+            let Some(memory) = memory else {
+                return Ok(WarmReport::default());
+            };
+
+            let capacity = lock_memory(&memory).cap().get();
+            let keys = plan(strategy, sideload.as_deref(), disk.as_deref(), capacity).await?;
+
+            load(keys, memory, sideload, disk, concurrency.max(1)).await
+        }))
+    }
+}
+
+/// Resolves a strategy into the list of keys to load, in insertion order.
+///
+/// The most valuable entries are placed last so that they end up as the most
+/// recently used items in the LRU.
+async fn plan(
+    strategy: WarmStrategy,
+    sideload: Option<&FsCache<Read>>,
+    disk: Option<&FsCache<ReadWrite>>,
+    capacity: usize,
+) -> Result<Vec<String>> {
+    let mut keys = match strategy {
+        WarmStrategy::Recent(n) => {
+            let mut entries = match disk {
+                Some(disk) => disk.entries().await?,
+                None => Vec::new(),
+            };
+
+            // Newest first, then keep the `n` newest and reverse so the newest is inserted last
+            entries.sort_by_key(|e| std::cmp::Reverse(e.modified));
+            entries.truncate(n);
+            entries.reverse();
+            entries.into_iter().map(|e| e.key).collect()
+        }
+        WarmStrategy::Keys(keys) => keys,
+        WarmStrategy::Prefix(prefix) => {
+            let prefix = format!("{}_", prefix);
+            let mut keys = Vec::new();
+
+            if let Some(sideload) = sideload {
+                keys.extend(sideload.entries().await?.into_iter().map(|e| e.key));
+            }
+
+            if let Some(disk) = disk {
+                keys.extend(disk.entries().await?.into_iter().map(|e| e.key));
+            }
+
+            keys.retain(|key| key.starts_with(&prefix));
+            keys.sort();
+            keys.dedup();
+            keys
+        }
+    };
+
+    // Loading more than the memory layer holds would only evict earlier loads.
+    if keys.len() > capacity {
+        keys.drain(..keys.len() - capacity);
+    }
+
+    Ok(keys)
+}
+
+/// Loads the keys into memory with at most `concurrency` reads in flight.
+///
+/// Values are inserted in the order of `keys`, whatever order their reads
+/// complete in, so the last key ends up as the most recently used.
+async fn load(
+    keys: Vec<String>,
+    memory: MemoryLayer,
+    sideload: Option<Arc<FsCache<Read>>>,
+    disk: Option<Arc<FsCache<ReadWrite>>>,
+    concurrency: usize,
+) -> Result<WarmReport> {
+    let mut report = WarmReport::default();
+
+    let mut reads = stream::iter(keys)
+        .map(|key| read_one(key, memory.clone(), sideload.clone(), disk.clone()))
+        .buffered(concurrency);
+
+    while let Some((key, found)) = reads.next().await {
+        report.record(insert(&memory, key, found));
+    }
+
+    Ok(report)
+}
+
+/// What reading a single key found
+enum Found {
+    /// The key was already present in memory, so nothing was read
+    Present,
+    /// No filesystem layer holds the key
+    Missing,
+    /// The value to insert into memory
+    Value(MemoryEntry),
+}
+
+/// Reads a single key from the filesystem layers, unless it is already in memory.
+async fn read_one(
+    key: String,
+    memory: MemoryLayer,
+    sideload: Option<Arc<FsCache<Read>>>,
+    disk: Option<Arc<FsCache<ReadWrite>>>,
+) -> (String, Found) {
+    if lock_memory(&memory).contains(&key) {
+        return (key, Found::Present);
+    }
+
+    let mut found = None;
+
+    if let Some(sideload) = &sideload {
+        found = sideload.get_with_info(&key).await;
+    }
+
+    if found.is_none()
+        && let Some(disk) = &disk
+    {
+        found = disk.get_with_info(&key).await;
+    }
+
+    match found {
+        Some((data, info)) => (key, Found::Value(MemoryEntry { data, info })),
+        None => (key, Found::Missing),
+    }
+}
+
+/// Inserts a value read by [`read_one`] into memory, unless the key is present.
+fn insert(memory: &MemoryLayer, key: String, found: Found) -> Outcome {
+    let entry = match found {
+        Found::Present => return Outcome::Skipped,
+        Found::Missing => return Outcome::Missing,
+        Found::Value(entry) => entry,
+    };
+
+    let mut memory = lock_memory(memory);
+
+    // A concurrent `put` may have stored a newer value while we were reading.
+    if memory.contains(&key) {
+        return Outcome::Skipped;
+    }
+
+    memory.put(key, entry);
+    Outcome::Loaded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        num::NonZeroUsize,
+        sync::Mutex,
+        time::{Duration, SystemTime},
+    };
+
+    use lru::LruCache;
+
+    use super::*;
+    use crate::{fs::tests::read_only_fixture, meta::EntryInfo};
+
+    fn memory(items: usize) -> MemoryLayer {
+        Arc::new(Mutex::new(LruCache::new(NonZeroUsize::new(items).unwrap())))
+    }
+
+    async fn disk_with(dir: &std::path::Path, keys: &[&str]) -> Arc<FsCache<ReadWrite>> {
+        let disk = FsCache::new_write(dir, 100).await.unwrap();
+
+        // Give each entry a distinct modification time, oldest first.
+        let base = SystemTime::now() - Duration::from_secs(3600);
+        for (i, key) in keys.iter().enumerate() {
+            disk.put(key, key.as_bytes()).await.unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(dir.join(key))
+                .unwrap()
+                .set_modified(base + Duration::from_secs(i as u64 * 60))
+                .unwrap();
+        }
+
+        Arc::new(disk)
+    }
+
+    fn memory_keys(cache: &OmneCache) -> Vec<String> {
+        let mut keys: Vec<_> = lock_memory(&cache.memory().unwrap())
+            .iter()
+            .map(|(k, _)| k.clone())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn test_warm_recent() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = OmneCache::with_layers(
+            Some(memory(10)),
+            None,
+            Some(disk_with(dir.path(), &["A_1", "A_2", "B_3", "B_4"]).await),
+        );
+
+        let report = cache.warm(WarmStrategy::Recent(2)).wait().await.unwrap();
+
+        assert_eq!(report.loaded, 2);
+        assert_eq!(memory_keys(&cache), vec!["B_3", "B_4"]);
+    }
+
+    #[tokio::test]
+    async fn test_warm_recent_is_bounded_by_capacity() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = OmneCache::with_layers(
+            Some(memory(1)),
+            None,
+            Some(disk_with(dir.path(), &["A_1", "A_2", "A_3"]).await),
+        );
+
+        let report = cache.warm(WarmStrategy::Recent(3)).wait().await.unwrap();
+
+        assert_eq!(report.loaded, 1);
+        assert_eq!(memory_keys(&cache), vec!["A_3"]);
+    }
+
+    #[tokio::test]
+    async fn test_warm_recent_leaves_newest_most_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = OmneCache::with_layers(
+            Some(memory(10)),
+            None,
+            Some(disk_with(dir.path(), &["A_1", "A_2", "B_3", "B_4"]).await),
+        );
+
+        cache
+            .warm_with(WarmStrategy::Recent(4), 4)
+            .wait()
+            .await
+            .unwrap();
+
+        // The LRU iterates from the most to the least recently used.
+        let order: Vec<_> = lock_memory(&cache.memory().unwrap())
+            .iter()
+            .map(|(k, _)| k.clone())
+            .collect();
+        assert_eq!(order, vec!["B_4", "B_3", "A_2", "A_1"]);
+    }
+
+    #[tokio::test]
+    async fn test_warm_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = OmneCache::with_layers(
+            Some(memory(10)),
+            None,
+            Some(disk_with(dir.path(), &["CustomString_a", "CustomString_b"]).await),
+        );
+
+        let strategy = WarmStrategy::keys(["a".to_string(), "missing".to_string()]).await;
+        let report = cache.warm_with(strategy, 1).wait().await.unwrap();
+
+        assert_eq!(report.loaded, 1);
+        assert_eq!(report.missing, 1);
+        assert_eq!(memory_keys(&cache), vec!["CustomString_a"]);
+    }
+
+    #[tokio::test]
+    async fn test_warm_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = OmneCache::with_layers(
+            Some(memory(10)),
+            None,
+            Some(disk_with(dir.path(), &["key1", "key_2", "keyed_3"]).await),
+        );
+
+        let report = cache
+            .warm(WarmStrategy::Prefix("key"))
+            .wait()
+            .await
+            .unwrap();
+
+        assert_eq!(report.loaded, 1);
+        assert_eq!(memory_keys(&cache), vec!["key_2"]);
+    }
+
+    #[tokio::test]
+    async fn test_warm_prefers_sideload() {
+        let dir = tempfile::tempdir().unwrap();
+        let sideload = read_only_fixture();
+        let cache = OmneCache::with_layers(
+            Some(memory(10)),
+            Some(Arc::new(FsCache::new_read(sideload.path()).await.unwrap())),
+            Some(disk_with(dir.path(), &["key1"]).await),
+        );
+
+        let strategy = WarmStrategy::Keys(vec!["key1".to_string()]);
+        let report = cache.warm(strategy).wait().await.unwrap();
+
+        assert_eq!(report.loaded, 1);
+        assert_eq!(
+            lock_memory(&cache.memory().unwrap())
+                .get("key1")
+                .map(|e| e.data.clone()),
+            Some(std::fs::read(sideload.path().join("key1")).unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_warm_keeps_existing_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = OmneCache::with_layers(
+            Some(memory(10)),
+            None,
+            Some(disk_with(dir.path(), &["A_1", "A_2"]).await),
+        );
+        cache.remember("A_1", b"fresh", &EntryInfo::default());
+
+        let report = cache.warm(WarmStrategy::Prefix("A")).wait().await.unwrap();
+
+        assert_eq!(report.loaded, 1);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(
+            lock_memory(&cache.memory().unwrap())
+                .get("A_1")
+                .map(|e| e.data.clone()),
+            Some(b"fresh".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_warm_without_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = OmneCache::with_layers(None, None, Some(disk_with(dir.path(), &["A_1"]).await));
+
+        let report = cache.warm(WarmStrategy::Recent(1)).wait().await.unwrap();
+        assert_eq!(report, WarmReport::default());
+    }
+}